@@ -0,0 +1,105 @@
+#![forbid(unsafe_code)]
+
+//! `#[derive(FromJavaObject)]` for `hprof_dump_parser::mapping`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/**
+Derive `FromJavaObject` and `FromJavaValue` for a struct with named
+fields.
+
+Struct attribute `#[hprof(class = "com.acme.Session")]` restricts
+mapping to instances of the class (or its subclasses).  Field attribute
+`#[hprof(field = "userId")]` sets Java field name; by default, the
+Rust field name is used.
+ */
+#[proc_macro_derive(FromJavaObject, attributes(hprof))]
+pub fn derive_from_java_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let class_name = hprof_attr(&input.attrs, "class")?;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "FromJavaObject can be derived only for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "FromJavaObject can be derived only for structs",
+            ))
+        }
+    };
+
+    let mut initializers = Vec::with_capacity(fields.len());
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let java_name = match hprof_attr(&field.attrs, "field")? {
+            Some(name) => name,
+            None => LitStr::new(&ident.to_string(), ident.span()),
+        };
+        initializers.push(quote! {
+            #ident: ::hprof_dump_parser::mapping::field(store, instance, #java_name)?
+        });
+    }
+
+    let class_name = match class_name {
+        Some(name) => quote! { ::std::option::Option::Some(#name) },
+        None => quote! { ::std::option::Option::None },
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::hprof_dump_parser::mapping::FromJavaObject for #name #ty_generics #where_clause {
+            fn from_java_object(
+                store: &::hprof_dump_parser::objects::ObjectStore,
+                id: ::hprof_dump_parser::decl::Id,
+            ) -> ::std::result::Result<Self, ::hprof_dump_parser::mapping::MappingError> {
+                let instance = ::hprof_dump_parser::mapping::instance_of(store, id, #class_name)?;
+                ::std::result::Result::Ok(Self {
+                    #(#initializers,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::hprof_dump_parser::mapping::FromJavaValue for #name #ty_generics #where_clause {
+            fn from_java_value(
+                store: &::hprof_dump_parser::objects::ObjectStore,
+                value: ::hprof_dump_parser::decl::FieldValue,
+            ) -> ::std::result::Result<Self, ::hprof_dump_parser::mapping::MappingError> {
+                ::hprof_dump_parser::mapping::object_value(store, value)
+            }
+        }
+    })
+}
+
+/// Find `#[hprof(key = "value")]` among attributes.
+fn hprof_attr(attrs: &[syn::Attribute], key: &str) -> syn::Result<Option<LitStr>> {
+    let mut result = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("hprof")) {
+        attr.parse_nested_meta(|meta| {
+            let value: LitStr = meta.value()?.parse()?;
+            if meta.path.is_ident(key) {
+                result = Some(value);
+                Ok(())
+            } else {
+                Err(meta.error(format!("unsupported hprof attribute, expected `{}`", key)))
+            }
+        })?;
+    }
+    Ok(result)
+}
@@ -0,0 +1,306 @@
+#![forbid(unsafe_code)]
+
+/*!
+Allocation sites written by the `hprof` agent (`-agentlib:hprof`) as
+ALLOC_SITES records: live and allocated bytes and instances per class
+and allocation stack trace.  HotSpot heap dumps do not contain them.
+ */
+
+use crate::decl::*;
+use crate::registry::java_name;
+use crate::symbol::{StackFrame, Symbolizer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// `AllocSitesRecord::flags` bit: incremental, not complete report.
+pub const FLAG_INCREMENTAL: u16 = 0x1;
+/// `AllocSitesRecord::flags` bit: sorted by allocation, not by live
+/// bytes.
+pub const FLAG_SORTED_BY_ALLOCATION: u16 = 0x2;
+/// `AllocSitesRecord::flags` bit: garbage was collected before the
+/// report.
+pub const FLAG_FORCED_GC: u16 = 0x4;
+
+/// Allocation site with the resolved class and stack trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Site {
+    /// Class in the Java form; array types are named by the element
+    /// type if the class is unknown.
+    pub class_name: String,
+    pub is_array: bool,
+    pub stack_trace_serial: SerialNumber,
+    /// Top frame first; `None` for missing frame records.
+    pub frames: Vec<Option<StackFrame>>,
+    pub live_bytes: u64,
+    pub live_instances: u64,
+    pub allocated_bytes: u64,
+    pub allocated_instances: u64,
+}
+
+impl Site {
+    pub fn top_frame(&self) -> Option<&StackFrame> {
+        self.frames.first().and_then(Option::as_ref)
+    }
+}
+
+/// Sites aggregated by class or top frame.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SiteGroup {
+    pub name: String,
+    pub sites: u64,
+    pub live_bytes: u64,
+    pub live_instances: u64,
+    pub allocated_bytes: u64,
+    pub allocated_instances: u64,
+}
+
+/// A resolved ALLOC_SITES record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllocSitesReport {
+    pub flags: u16,
+    /// Sites below this part of live bytes are omitted by the agent.
+    pub cutoff_ratio: f32,
+    pub total_live_bytes: u64,
+    pub total_live_instances: u64,
+    pub total_bytes_allocated: u64,
+    pub total_instances_allocated: u64,
+    /// In the record order.
+    pub sites: Vec<Site>,
+}
+
+impl AllocSitesReport {
+    /// Resolve the record with the symbolizer fed by the same dump.
+    pub fn new(record: &AllocSitesRecord, symbolizer: &Symbolizer) -> Self {
+        let registry = symbolizer.registry();
+        let sites = record
+            .sites
+            .iter()
+            .map(|site| {
+                let class_name = registry
+                    .class_id_by_serial(site.class_serial)
+                    .and_then(|class_id| registry.class_name(class_id))
+                    .map(java_name)
+                    .or_else(|| match FieldType::try_from(site.is_array) {
+                        Ok(FieldType::Object) => Some("java.lang.Object[]".to_string()),
+                        Ok(elem_type) => Some(format!("{}[]", elem_type.java_name())),
+                        Err(_) => None,
+                    })
+                    .unwrap_or_else(|| format!("<unknown class #{}>", site.class_serial));
+                Site {
+                    class_name,
+                    is_array: site.is_array != 0,
+                    stack_trace_serial: site.stack_trace_serial,
+                    frames: symbolizer
+                        .stack_trace(site.stack_trace_serial)
+                        .unwrap_or_default(),
+                    live_bytes: site.bytes_alive.into(),
+                    live_instances: site.instances_alive.into(),
+                    allocated_bytes: site.bytes_allocated.into(),
+                    allocated_instances: site.instances_allocated.into(),
+                }
+            })
+            .collect();
+        Self {
+            flags: record.flags,
+            cutoff_ratio: f32::from_bits(record.cutoff_ratio),
+            total_live_bytes: record.total_live_bytes.into(),
+            total_live_instances: record.total_live_instances.into(),
+            total_bytes_allocated: record.total_bytes_allocated,
+            total_instances_allocated: record.total_instances_allocated,
+            sites,
+        }
+    }
+
+    /// Reports for all ALLOC_SITES records of the dump.
+    pub fn from_records<I, S>(records: I) -> Result<Vec<Self>, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut symbolizer = Symbolizer::new();
+        let mut alloc_sites = Vec::new();
+        for rec in records {
+            let (_, record) = rec?;
+            match record {
+                Record::AllocSites(sites) => alloc_sites.push(sites),
+                record => symbolizer.add_record(&record),
+            }
+        }
+        Ok(alloc_sites
+            .iter()
+            .map(|sites| Self::new(sites, &symbolizer))
+            .collect())
+    }
+
+    /// Sites by class, most live bytes first.
+    pub fn by_class(&self) -> Vec<SiteGroup> {
+        self.group_by(|site| site.class_name.clone())
+    }
+
+    /// Sites by the top frame of the allocation trace, most live bytes
+    /// first.  Sites without frames are grouped as `<no frames>`.
+    pub fn by_top_frame(&self) -> Vec<SiteGroup> {
+        self.group_by(|site| match site.frames.first() {
+            Some(Some(frame)) => frame.to_string(),
+            Some(None) => "<unknown frame>".to_string(),
+            None => "<no frames>".to_string(),
+        })
+    }
+
+    fn group_by<F: Fn(&Site) -> String>(&self, key: F) -> Vec<SiteGroup> {
+        let mut groups: HashMap<String, SiteGroup> = HashMap::new();
+        for site in &self.sites {
+            let group = groups.entry(key(site)).or_default();
+            group.sites += 1;
+            group.live_bytes += site.live_bytes;
+            group.live_instances += site.live_instances;
+            group.allocated_bytes += site.allocated_bytes;
+            group.allocated_instances += site.allocated_instances;
+        }
+        let mut groups: Vec<SiteGroup> = groups
+            .into_iter()
+            .map(|(name, group)| SiteGroup { name, ..group })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.live_bytes
+                .cmp(&a.live_bytes)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        groups
+    }
+
+    /// Write the sites like the `hprof` agent's text output, with up to
+    /// `max_frames` frames per site.
+    pub fn write_text<W: Write>(&self, out: &mut W, max_frames: usize) -> io::Result<()> {
+        writeln!(
+            out,
+            "SITES ({}, {}{}), cutoff {:.2}%",
+            if self.flags & FLAG_INCREMENTAL != 0 {
+                "incremental"
+            } else {
+                "complete"
+            },
+            if self.flags & FLAG_SORTED_BY_ALLOCATION != 0 {
+                "sorted by allocation"
+            } else {
+                "sorted by live bytes"
+            },
+            if self.flags & FLAG_FORCED_GC != 0 {
+                ", after GC"
+            } else {
+                ""
+            },
+            self.cutoff_ratio * 100.0
+        )?;
+        writeln!(
+            out,
+            "live {} bytes in {} objects, allocated {} bytes in {} objects",
+            self.total_live_bytes,
+            self.total_live_instances,
+            self.total_bytes_allocated,
+            self.total_instances_allocated
+        )?;
+        writeln!(
+            out,
+            "rank   self  accum     live bytes    objs    alloc bytes    objs  trace  class"
+        )?;
+        let total = self.total_live_bytes.max(1) as f64;
+        let mut accum = 0.0;
+        for (idx, site) in self.sites.iter().enumerate() {
+            let share = site.live_bytes as f64 * 100.0 / total;
+            accum += share;
+            writeln!(
+                out,
+                "{:>4} {:>5.2}% {:>5.2}% {:>12} {:>7} {:>14} {:>7} {:>6}  {}",
+                idx + 1,
+                share,
+                accum,
+                site.live_bytes,
+                site.live_instances,
+                site.allocated_bytes,
+                site.allocated_instances,
+                site.stack_trace_serial,
+                site.class_name
+            )?;
+            for frame in site.frames.iter().take(max_frames) {
+                match frame {
+                    Some(frame) => writeln!(out, "\tat {}", frame)?,
+                    None => writeln!(out, "\tat <unknown frame>")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_alloc_sites() {
+        let mut dump = TestDump::new();
+        let item = dump.class("com/acme/Item", None, &[]);
+        let item_serial = dump.class_serial(item);
+        let load = dump.stack_frame(item, "load", "()V", 10);
+        dump.stack_trace(5, &[load]);
+        let site = |is_array, class_serial, bytes_alive| AllocSite {
+            is_array,
+            class_serial,
+            stack_trace_serial: 5,
+            bytes_alive,
+            instances_alive: 1,
+            bytes_allocated: bytes_alive * 2,
+            instances_allocated: 2,
+        };
+        dump.record(Record::AllocSites(AllocSitesRecord {
+            flags: FLAG_FORCED_GC,
+            cutoff_ratio: 0.0001f32.to_bits(),
+            total_live_bytes: 100,
+            total_live_instances: 3,
+            total_bytes_allocated: 200,
+            total_instances_allocated: 6,
+            sites: vec![
+                site(0, item_serial, 50),
+                site(10, 0, 30),
+                site(0, item_serial, 20),
+            ],
+        }));
+
+        let reports = AllocSitesReport::from_records(dump.records()).unwrap();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.sites[1].class_name, "int[]");
+        assert!(report.sites[1].is_array);
+        assert_eq!(
+            report.sites[0]
+                .top_frame()
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("com.acme.Item.load(Item.java:10)")
+        );
+
+        let by_class = report.by_class();
+        let by_class: Vec<(&str, u64, u64)> = by_class
+            .iter()
+            .map(|g| (g.name.as_str(), g.sites, g.live_bytes))
+            .collect();
+        assert_eq!(by_class, vec![("com.acme.Item", 2, 70), ("int[]", 1, 30)]);
+        let by_frame = report.by_top_frame();
+        assert_eq!(by_frame.len(), 1);
+        assert_eq!(by_frame[0].allocated_bytes, 200);
+
+        let mut out = Vec::new();
+        report.write_text(&mut out, 1).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.starts_with("SITES (complete, sorted by live bytes, after GC), cutoff 0.01%\n")
+        );
+        assert!(text.contains(
+            "   1 50.00% 50.00%           50       1            100       2      5  com.acme.Item\n\
+             \tat com.acme.Item.load(Item.java:10)\n"
+        ));
+    }
+}
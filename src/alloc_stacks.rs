@@ -0,0 +1,304 @@
+#![forbid(unsafe_code)]
+
+/*!
+Live objects by allocation stack trace.
+
+With allocation tracking every object record carries the serial of the
+stack trace it was allocated at; zero means unknown.  The histogram of
+objects by stack trace and class can be written in the collapsed-stack
+("folded") format of flame graph tools: one line per stack, frames from
+the outermost one separated by `;`, the class as the leaf frame, and
+the weight.
+
+```text
+java.lang.Thread.run;com.acme.Loader.load;com.acme.Item 4096
+```
+ */
+
+use crate::decl::*;
+use crate::histogram::field_data;
+use crate::registry::java_name;
+use crate::size::SizeModel;
+use crate::symbol::Symbolizer;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum ClassKey {
+    /// Instance or object array class.
+    Class(Id),
+    PrimitiveArray(FieldType),
+}
+
+/// Objects of a class allocated at a stack trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AllocStack {
+    pub stack_trace_serial: SerialNumber,
+    /// Frames as `class.method`, top frame first.
+    pub frames: Vec<String>,
+    pub class_name: String,
+    pub objects: u64,
+    pub shallow_size: u64,
+}
+
+/// Objects of a class with known allocation stacks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AllocClass {
+    pub class_name: String,
+    /// Number of different stack traces.
+    pub stacks: u64,
+    pub objects: u64,
+    pub shallow_size: u64,
+}
+
+/// Weight of a folded stack line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FoldedWeight {
+    Objects,
+    Bytes,
+}
+
+/// Objects by allocation stack, see `AllocStacksBuilder`.
+#[derive(Clone, Debug, Default)]
+pub struct AllocStacks {
+    /// Biggest shallow size first.
+    pub stacks: Vec<AllocStack>,
+    /// Objects without allocation stack trace.
+    pub untracked_objects: u64,
+    pub untracked_size: u64,
+}
+
+/// Streaming builder of `AllocStacks`.
+#[derive(Debug)]
+pub struct AllocStacksBuilder {
+    size_model: SizeModel,
+    symbolizer: Symbolizer,
+    instance_sizes: HashMap<Id, u64>,
+    counters: HashMap<(SerialNumber, ClassKey), (u64, u64)>,
+    untracked_objects: u64,
+    untracked_size: u64,
+}
+
+impl AllocStacksBuilder {
+    pub fn new(size_model: SizeModel) -> Self {
+        Self {
+            size_model,
+            symbolizer: Symbolizer::new(),
+            instance_sizes: HashMap::new(),
+            counters: HashMap::new(),
+            untracked_objects: 0,
+            untracked_size: 0,
+        }
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        let model = self.size_model;
+        let (serial, key, size) = match record {
+            Record::Dump(DumpRecord::InstanceDump(instance)) => {
+                let size = *self
+                    .instance_sizes
+                    .entry(instance.class_object_id)
+                    .or_insert_with(|| {
+                        let (payload, references) = field_data(instance.values.iter());
+                        model.instance_size(payload, references)
+                    });
+                (
+                    instance.stack_trace_serial,
+                    ClassKey::Class(instance.class_object_id),
+                    size,
+                )
+            }
+            Record::Dump(DumpRecord::ObjectArrayDump(array)) => (
+                array.stack_trace_serial,
+                ClassKey::Class(array.element_class_id),
+                model.object_array_size(array.num_elements.into()),
+            ),
+            Record::Dump(DumpRecord::PrimitiveArrayDump(array)) => (
+                array.stack_trace_serial,
+                ClassKey::PrimitiveArray(array.elem_type),
+                model.primitive_array_size(array.elem_type, array.num_elements.into()),
+            ),
+            record => {
+                self.symbolizer.add_record(record);
+                return;
+            }
+        };
+        if serial == 0 {
+            self.untracked_objects += 1;
+            self.untracked_size += size;
+        } else {
+            let counter = self.counters.entry((serial, key)).or_default();
+            counter.0 += 1;
+            counter.1 += size;
+        }
+    }
+
+    pub fn finish(self) -> AllocStacks {
+        let symbolizer = &self.symbolizer;
+        let registry = symbolizer.registry();
+        let mut frames_cache: HashMap<SerialNumber, Vec<String>> = HashMap::new();
+        let mut stacks: Vec<AllocStack> = self
+            .counters
+            .into_iter()
+            .map(|((stack_trace_serial, key), (objects, shallow_size))| {
+                let frames = frames_cache
+                    .entry(stack_trace_serial)
+                    .or_insert_with(|| {
+                        symbolizer
+                            .stack_trace(stack_trace_serial)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|frame| match frame {
+                                Some(frame) => frame.qualified_name(),
+                                None => "<unknown frame>".to_string(),
+                            })
+                            .collect()
+                    })
+                    .clone();
+                let class_name = match key {
+                    ClassKey::Class(class_id) => registry
+                        .class_name(class_id)
+                        .map(java_name)
+                        .unwrap_or_else(|| format!("<unknown class {:#x}>", u64::from(class_id))),
+                    ClassKey::PrimitiveArray(elem_type) => format!("{}[]", elem_type.java_name()),
+                };
+                AllocStack {
+                    stack_trace_serial,
+                    frames,
+                    class_name,
+                    objects,
+                    shallow_size,
+                }
+            })
+            .collect();
+        stacks.sort_by(|a, b| {
+            b.shallow_size
+                .cmp(&a.shallow_size)
+                .then_with(|| a.stack_trace_serial.cmp(&b.stack_trace_serial))
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        AllocStacks {
+            stacks,
+            untracked_objects: self.untracked_objects,
+            untracked_size: self.untracked_size,
+        }
+    }
+}
+
+impl AllocStacks {
+    pub fn from_records<I, S>(records: I, size_model: SizeModel) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = AllocStacksBuilder::new(size_model);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish())
+    }
+
+    /// Tracked objects by class, biggest shallow size first.
+    pub fn by_class(&self) -> Vec<AllocClass> {
+        let mut classes: HashMap<&str, AllocClass> = HashMap::new();
+        for stack in &self.stacks {
+            let class = classes
+                .entry(&stack.class_name)
+                .or_insert_with(|| AllocClass {
+                    class_name: stack.class_name.clone(),
+                    stacks: 0,
+                    objects: 0,
+                    shallow_size: 0,
+                });
+            class.stacks += 1;
+            class.objects += stack.objects;
+            class.shallow_size += stack.shallow_size;
+        }
+        let mut classes: Vec<AllocClass> = classes.into_values().collect();
+        classes.sort_by(|a, b| {
+            b.shallow_size
+                .cmp(&a.shallow_size)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        classes
+    }
+
+    /// Write folded stacks, one line per stack trace and class.
+    /// Stacks whose frames are equal are merged.
+    pub fn write_folded<W: Write>(&self, out: &mut W, weight: FoldedWeight) -> io::Result<()> {
+        let mut lines: HashMap<String, u64> = HashMap::new();
+        for stack in &self.stacks {
+            let mut line = String::new();
+            for frame in stack.frames.iter().rev() {
+                line.push_str(frame);
+                line.push(';');
+            }
+            line.push_str(&stack.class_name);
+            *lines.entry(line).or_default() += match weight {
+                FoldedWeight::Objects => stack.objects,
+                FoldedWeight::Bytes => stack.shallow_size,
+            };
+        }
+        let mut lines: Vec<(String, u64)> = lines.into_iter().collect();
+        lines.sort();
+        for (line, value) in lines {
+            writeln!(out, "{} {}", line, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_alloc_stacks() {
+        let mut dump = TestDump::new();
+        let item = dump.class("com/acme/Item", None, &[("value", FieldType::Int)]);
+        let load = dump.stack_frame(item, "load", "()V", 1);
+        let run = dump.stack_frame(item, "run", "()V", 1);
+        dump.stack_trace(5, &[load, run]);
+        for serial in &[5, 5, 0] {
+            let object_id = dump.instance(item, &[FieldValue::Int(0)]);
+            if let Some(Record::Dump(DumpRecord::InstanceDump(instance))) = dump.records.last_mut()
+            {
+                assert_eq!(instance.object_id, object_id);
+                instance.stack_trace_serial = *serial;
+            }
+        }
+        dump.primitive_array(ArrayValue::Long(vec![1, 2]));
+        if let Some(Record::Dump(DumpRecord::PrimitiveArrayDump(array))) = dump.records.last_mut() {
+            array.stack_trace_serial = 5;
+        }
+
+        let stacks =
+            AllocStacks::from_records(dump.records(), SizeModel::HOTSPOT_64_COMPRESSED).unwrap();
+        assert_eq!(stacks.stacks.len(), 2);
+        assert_eq!(stacks.untracked_objects, 1);
+        assert_eq!(stacks.untracked_size, 16);
+        assert_eq!(
+            stacks.stacks[0].frames,
+            vec!["com.acme.Item.load", "com.acme.Item.run"]
+        );
+        let classes: Vec<(&str, u64, u64)> = stacks
+            .stacks
+            .iter()
+            .map(|s| (s.class_name.as_str(), s.objects, s.shallow_size))
+            .collect();
+        assert_eq!(classes, vec![("com.acme.Item", 2, 32), ("long[]", 1, 32)]);
+        assert_eq!(stacks.by_class().len(), 2);
+
+        let mut out = Vec::new();
+        stacks
+            .write_folded(&mut out, FoldedWeight::Objects)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "com.acme.Item.run;com.acme.Item.load;com.acme.Item 2\n\
+             com.acme.Item.run;com.acme.Item.load;long[] 1\n"
+        );
+    }
+}
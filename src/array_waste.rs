@@ -0,0 +1,366 @@
+#![forbid(unsafe_code)]
+
+/*!
+Wasteful arrays: duplicates, zero-filled arrays and trailing slack.
+
+* Arrays of the same type with equal contents are duplicates; all
+  copies but one are wasted.  Object arrays are equal if they reference
+  the same objects.
+* Arrays with only zero (or `null`) elements are wasted entirely.
+* Zero elements at the end of an array are unused capacity, like in
+  buffers and `ArrayList.elementData`; they are reported if they take
+  at least the slack threshold of the array.
+
+Every array is counted once, in the first matching category.  Arrays
+are attributed to the classes and fields referencing them, see
+`ArrayWaste::by_owner`.
+ */
+
+use crate::decl::*;
+use crate::graph::HeapGraph;
+use crate::inbound::InboundIndex;
+use crate::size::SizeModel;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WasteKind {
+    /// A copy of another array.
+    Duplicate,
+    /// All elements are zero or `null`.
+    ZeroFilled,
+    /// Trailing zero or `null` elements.
+    TrailingSlack,
+}
+
+/// An array with wasted memory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WastedArray {
+    pub object_id: Id,
+    pub kind: WasteKind,
+    /// Shallow size for duplicates and zero-filled arrays, size of the
+    /// trailing elements for slack.
+    pub wasted_bytes: u64,
+}
+
+/// Wasted memory of arrays referenced by a class and field.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OwnerWaste {
+    /// See `HeapGraph::type_name`.
+    pub class_name: String,
+    /// Field name, or `[]` for array elements.
+    pub field: String,
+    pub arrays: u64,
+    pub duplicate_bytes: u64,
+    pub zero_filled_bytes: u64,
+    pub slack_bytes: u64,
+}
+
+impl OwnerWaste {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.duplicate_bytes + self.zero_filled_bytes + self.slack_bytes
+    }
+}
+
+/// Wasteful arrays, see `ArrayWasteBuilder`.
+#[derive(Clone, Debug, Default)]
+pub struct ArrayWaste {
+    /// Most wasted bytes first.
+    pub arrays: Vec<WastedArray>,
+}
+
+/// Array contents digest: type, length and hash.
+type Digest = (Option<FieldType>, u32, u64);
+
+/**
+Streaming collector of wasteful arrays.  Primitive arrays have to be
+loaded (see `StreamHprofReader::with_load_primitive_arrays`); only
+hashes of contents are kept.
+ */
+#[derive(Debug)]
+pub struct ArrayWasteBuilder {
+    size_model: SizeModel,
+    min_slack: f64,
+    /// Array id and size of the first array with the contents, and the
+    /// number of copies.
+    digests: HashMap<Digest, (Id, u64, u64)>,
+    /// Copies beyond the first one.
+    duplicates: Vec<(Id, u64)>,
+    arrays: Vec<WastedArray>,
+}
+
+impl ArrayWasteBuilder {
+    /// Builder with slack threshold of 50%.
+    pub fn new(size_model: SizeModel) -> Self {
+        Self {
+            size_model,
+            min_slack: 0.5,
+            digests: HashMap::new(),
+            duplicates: Vec::new(),
+            arrays: Vec::new(),
+        }
+    }
+
+    /// Minimal part of array length taken by trailing zeros to report
+    /// slack.
+    pub fn with_min_slack(mut self, min_slack: f64) -> Self {
+        self.min_slack = min_slack;
+        self
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        match record {
+            Record::Dump(DumpRecord::PrimitiveArrayDump(array)) => {
+                if let Some(values) = &array.values {
+                    let size = self
+                        .size_model
+                        .primitive_array_size(array.elem_type, array.num_elements.into());
+                    let element_size = self.size_model.field_size(array.elem_type);
+                    let (hash, trailing_zeros) = digest(values);
+                    self.add_array(
+                        array.object_id,
+                        (Some(array.elem_type), array.num_elements, hash),
+                        size,
+                        element_size,
+                        trailing_zeros,
+                    );
+                }
+            }
+            Record::Dump(DumpRecord::ObjectArrayDump(array)) => {
+                if let Some(values) = &array.values {
+                    let size = self.size_model.object_array_size(array.num_elements.into());
+                    let element_size = self.size_model.reference_size.into();
+                    let mut hasher = DefaultHasher::new();
+                    array.element_class_id.hash(&mut hasher);
+                    values.hash(&mut hasher);
+                    let trailing_zeros = values
+                        .iter()
+                        .rev()
+                        .take_while(|id| u64::from(**id) == 0)
+                        .count();
+                    self.add_array(
+                        array.object_id,
+                        (None, array.num_elements, hasher.finish()),
+                        size,
+                        element_size,
+                        trailing_zeros as u64,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_array(
+        &mut self,
+        object_id: Id,
+        digest: Digest,
+        size: u64,
+        element_size: u64,
+        trailing_zeros: u64,
+    ) {
+        let length = u64::from(digest.1);
+        if length == 0 {
+            return;
+        }
+        if trailing_zeros == length {
+            self.arrays.push(WastedArray {
+                object_id,
+                kind: WasteKind::ZeroFilled,
+                wasted_bytes: size,
+            });
+            return;
+        }
+        let entry = self.digests.entry(digest).or_insert((object_id, size, 0));
+        entry.2 += 1;
+        if entry.2 > 1 {
+            self.duplicates.push((object_id, size));
+        } else if trailing_zeros as f64 >= length as f64 * self.min_slack {
+            self.arrays.push(WastedArray {
+                object_id,
+                kind: WasteKind::TrailingSlack,
+                wasted_bytes: trailing_zeros * element_size,
+            });
+        }
+    }
+
+    pub fn finish(self) -> ArrayWaste {
+        let mut arrays = self.arrays;
+        arrays.extend(
+            self.duplicates
+                .into_iter()
+                .map(|(object_id, size)| WastedArray {
+                    object_id,
+                    kind: WasteKind::Duplicate,
+                    wasted_bytes: size,
+                }),
+        );
+        arrays.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| u64::from(a.object_id).cmp(&u64::from(b.object_id)))
+        });
+        ArrayWaste { arrays }
+    }
+}
+
+/// Hash of contents and number of trailing zero elements.
+fn digest(values: &ArrayValue) -> (u64, u64) {
+    fn scan<T: Copy, F: Fn(T) -> u64>(values: &[T], bits: F) -> (u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        let mut trailing = 0;
+        for value in values {
+            let bits = bits(*value);
+            hasher.write_u64(bits);
+            trailing = if bits == 0 { trailing + 1 } else { 0 };
+        }
+        (hasher.finish(), trailing)
+    }
+
+    match values {
+        ArrayValue::Bool(values) => scan(values, u64::from),
+        ArrayValue::Byte(values) => scan(values, |v| v as u8 as u64),
+        ArrayValue::Char(values) => scan(values, u64::from),
+        ArrayValue::Short(values) => scan(values, |v| v as u16 as u64),
+        ArrayValue::Int(values) => scan(values, |v| v as u32 as u64),
+        ArrayValue::Long(values) => scan(values, |v| v as u64),
+        ArrayValue::Float(values) => scan(values, |v| v.to_bits().into()),
+        ArrayValue::Double(values) => scan(values, f64::to_bits),
+        ArrayValue::Object(values) => scan(values, u64::from),
+    }
+}
+
+impl ArrayWaste {
+    pub fn from_records<I, S>(records: I, size_model: SizeModel) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = ArrayWasteBuilder::new(size_model);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish())
+    }
+
+    /// Total wasted bytes of the kind.
+    pub fn wasted_bytes(&self, kind: WasteKind) -> u64 {
+        self.arrays
+            .iter()
+            .filter(|array| array.kind == kind)
+            .map(|array| array.wasted_bytes)
+            .sum()
+    }
+
+    /**
+    Wasted memory by classes and fields referencing the arrays, most
+    wasted bytes first.  An array referenced from several places is
+    counted for each of them.  The graph must be built from the same
+    dump.
+     */
+    pub fn by_owner(
+        &self,
+        graph: &HeapGraph,
+        inbound: &InboundIndex,
+    ) -> io::Result<Vec<OwnerWaste>> {
+        let mut owners: HashMap<(String, String), OwnerWaste> = HashMap::new();
+        for array in &self.arrays {
+            let node = match graph.index_of(array.object_id) {
+                Some(node) => node,
+                None => continue,
+            };
+            for referrers in inbound.referrers_by_class(graph, node)? {
+                let owner = owners
+                    .entry((referrers.class_name, referrers.field))
+                    .or_default();
+                owner.arrays += 1;
+                match array.kind {
+                    WasteKind::Duplicate => owner.duplicate_bytes += array.wasted_bytes,
+                    WasteKind::ZeroFilled => owner.zero_filled_bytes += array.wasted_bytes,
+                    WasteKind::TrailingSlack => owner.slack_bytes += array.wasted_bytes,
+                }
+            }
+        }
+        let mut owners: Vec<OwnerWaste> = owners
+            .into_iter()
+            .map(|((class_name, field), owner)| OwnerWaste {
+                class_name,
+                field,
+                ..owner
+            })
+            .collect();
+        owners.sort_by(|a, b| {
+            b.wasted_bytes()
+                .cmp(&a.wasted_bytes())
+                .then_with(|| a.class_name.cmp(&b.class_name))
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        Ok(owners)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_array_waste() {
+        let mut dump = TestDump::new();
+        let buffer = dump.class("com/acme/Buffer", None, &[("data", FieldType::Object)]);
+        let objects = dump.class("[Ljava/lang/Object;", None, &[]);
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            // byte[8]: 24 bytes.
+            data.push(dump.primitive_array(ArrayValue::Byte(vec![1, 2, 3, 4, 5, 6, 7, 8])));
+        }
+        // int[4]: 32 bytes.
+        data.push(dump.primitive_array(ArrayValue::Int(vec![0; 4])));
+        // Object[10]: 56 bytes, 8 unused references.
+        let object = dump.class("java/lang/Object", None, &[]);
+        let leaf = dump.instance(object, &[]);
+        let mut elements = vec![leaf, leaf];
+        elements.resize(10, id(0));
+        data.push(dump.object_array(objects, &elements));
+        // Short slack is not reported.
+        data.push(dump.primitive_array(ArrayValue::Long(vec![1, 2, 0])));
+        for array in &data {
+            dump.instance(buffer, &[FieldValue::Object(*array)]);
+        }
+
+        let waste =
+            ArrayWaste::from_records(dump.records(), SizeModel::HOTSPOT_64_COMPRESSED).unwrap();
+        let arrays: Vec<(Id, WasteKind, u64)> = waste
+            .arrays
+            .iter()
+            .map(|a| (a.object_id, a.kind, a.wasted_bytes))
+            .collect();
+        assert_eq!(
+            arrays,
+            vec![
+                (data[2], WasteKind::ZeroFilled, 32),
+                (data[3], WasteKind::TrailingSlack, 32),
+                (data[1], WasteKind::Duplicate, 24),
+            ]
+        );
+        assert_eq!(waste.wasted_bytes(WasteKind::Duplicate), 24);
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let owners = waste.by_owner(&graph, &inbound).unwrap();
+        assert_eq!(
+            owners,
+            vec![OwnerWaste {
+                class_name: "com.acme.Buffer".to_string(),
+                field: "data".to_string(),
+                arrays: 3,
+                duplicate_bytes: 24,
+                zero_filled_bytes: 32,
+                slack_bytes: 32,
+            }]
+        );
+    }
+}
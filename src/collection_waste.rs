@@ -0,0 +1,367 @@
+#![forbid(unsafe_code)]
+
+/*!
+Inefficient collections: empty collections with allocated storage,
+sparse hash tables, lists with big unused capacity and one-element
+collections.
+
+Collections are found with the walkers from `collections`, so object
+arrays have to be loaded.  Results are attributed to the classes and
+fields referencing the collections, see `CollectionWaste::by_owner`.
+ */
+
+use crate::collections::{Collection, CollectionKind};
+use crate::decl::Id;
+use crate::graph::HeapGraph;
+use crate::inbound::InboundIndex;
+use crate::objects::ObjectStore;
+use crate::size::SizeModel;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// Default initial capacity of `HashMap` tables.
+const DEFAULT_TABLE_SIZE: u64 = 16;
+/// Default initial capacity of `ArrayList`.
+const DEFAULT_LIST_CAPACITY: u64 = 10;
+const LOAD_FACTOR: f64 = 0.75;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Inefficiency {
+    /// No elements, but the backing array is allocated.
+    EmptyAllocated,
+    /// A single element.
+    SingleElement,
+    /// A hash table filled less than the threshold.
+    LowFillRatio,
+    /// A list or deque whose unused capacity exceeds the threshold.
+    CapacitySlack,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InefficientCollection {
+    pub object_id: Id,
+    pub kind: CollectionKind,
+    pub problem: Inefficiency,
+    pub size: u64,
+    pub capacity: Option<u64>,
+    /// Memory of unused backing array slots (the whole array for empty
+    /// collections).
+    pub wasted_bytes: u64,
+}
+
+/// Inefficient collections referenced by a class and field.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OwnerCollectionWaste {
+    /// See `HeapGraph::type_name`.
+    pub class_name: String,
+    /// Field name, or `[]` for array elements.
+    pub field: String,
+    pub empty_allocated: u64,
+    pub single_element: u64,
+    pub low_fill_ratio: u64,
+    pub capacity_slack: u64,
+    pub wasted_bytes: u64,
+}
+
+impl OwnerCollectionWaste {
+    pub fn collections(&self) -> u64 {
+        self.empty_allocated + self.single_element + self.low_fill_ratio + self.capacity_slack
+    }
+}
+
+/// Inefficient collections, see `CollectionWasteAnalyzer`.
+#[derive(Clone, Debug, Default)]
+pub struct CollectionWaste {
+    /// Most wasted bytes first.
+    pub collections: Vec<InefficientCollection>,
+}
+
+/**
+Collection inefficiency analysis.
+
+```no_run
+# use hprof_dump_parser::collection_waste::CollectionWasteAnalyzer;
+# use hprof_dump_parser::objects::ObjectStore;
+# use hprof_dump_parser::size::SizeModel;
+# fn analyze(store: &ObjectStore) {
+let waste = CollectionWasteAnalyzer::new(SizeModel::HOTSPOT_64_COMPRESSED)
+    .with_min_fill_ratio(0.1)
+    .analyze(store);
+# }
+```
+ */
+#[derive(Clone, Debug)]
+pub struct CollectionWasteAnalyzer {
+    size_model: SizeModel,
+    min_fill_ratio: f64,
+    max_slack: f64,
+}
+
+impl CollectionWasteAnalyzer {
+    /// Analyzer reporting hash tables filled less than 25% and lists
+    /// with more than half of the capacity unused.
+    pub fn new(size_model: SizeModel) -> Self {
+        Self {
+            size_model,
+            min_fill_ratio: 0.25,
+            max_slack: 0.5,
+        }
+    }
+
+    /// Hash tables with lower `size / capacity` are reported.
+    pub fn with_min_fill_ratio(mut self, min_fill_ratio: f64) -> Self {
+        self.min_fill_ratio = min_fill_ratio;
+        self
+    }
+
+    /// Lists and deques with bigger unused part of capacity are
+    /// reported.
+    pub fn with_max_slack(mut self, max_slack: f64) -> Self {
+        self.max_slack = max_slack;
+        self
+    }
+
+    pub fn analyze(&self, store: &ObjectStore) -> CollectionWaste {
+        let all: Vec<Collection> = store
+            .instances()
+            .filter_map(|instance| Collection::new(store, instance.object_id))
+            .collect();
+        // Maps backing sets are reported as the sets.
+        let backing_maps: HashSet<Id> = all.iter().filter_map(Collection::backing_map).collect();
+        let mut collections: Vec<InefficientCollection> = all
+            .iter()
+            .filter(|collection| !backing_maps.contains(&collection.object_id()))
+            .filter_map(|collection| self.check(collection))
+            .collect();
+        collections.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| u64::from(a.object_id).cmp(&u64::from(b.object_id)))
+        });
+        CollectionWaste { collections }
+    }
+
+    fn check(&self, collection: &Collection) -> Option<InefficientCollection> {
+        let size = collection.size();
+        let capacity = collection.capacity();
+        let slots = capacity.unwrap_or(0);
+        let reference_size = u64::from(self.size_model.reference_size);
+        let kind = collection.kind();
+        let hashed = matches!(
+            kind,
+            CollectionKind::HashMap
+                | CollectionKind::LinkedHashMap
+                | CollectionKind::ConcurrentHashMap
+                | CollectionKind::HashSet
+                | CollectionKind::LinkedHashSet
+        );
+
+        let (problem, wasted_bytes) = if size == 0 && slots > 0 {
+            (
+                Inefficiency::EmptyAllocated,
+                self.size_model.object_array_size(slots),
+            )
+        } else if size == 1 {
+            (
+                Inefficiency::SingleElement,
+                slots.saturating_sub(1) * reference_size,
+            )
+        } else if hashed
+            && slots > DEFAULT_TABLE_SIZE
+            && (size as f64) < slots as f64 * self.min_fill_ratio
+        {
+            // Table a map of this size would have.
+            let needed = ((size as f64 / LOAD_FACTOR).ceil() as u64)
+                .next_power_of_two()
+                .max(DEFAULT_TABLE_SIZE);
+            (
+                Inefficiency::LowFillRatio,
+                slots.saturating_sub(needed) * reference_size,
+            )
+        } else if !hashed
+            && slots > DEFAULT_LIST_CAPACITY
+            && (slots - size.min(slots)) as f64 > slots as f64 * self.max_slack
+        {
+            (
+                Inefficiency::CapacitySlack,
+                (slots - size.min(slots)) * reference_size,
+            )
+        } else {
+            return None;
+        };
+        Some(InefficientCollection {
+            object_id: collection.object_id(),
+            kind,
+            problem,
+            size,
+            capacity,
+            wasted_bytes,
+        })
+    }
+}
+
+impl CollectionWaste {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.collections
+            .iter()
+            .map(|collection| collection.wasted_bytes)
+            .sum()
+    }
+
+    /**
+    Inefficient collections by classes and fields referencing them,
+    most wasted bytes first, then most collections.  A collection
+    referenced from several places is counted for each of them.  The
+    graph must be built from the same dump.
+     */
+    pub fn by_owner(
+        &self,
+        graph: &HeapGraph,
+        inbound: &InboundIndex,
+    ) -> io::Result<Vec<OwnerCollectionWaste>> {
+        let mut owners: HashMap<(String, String), OwnerCollectionWaste> = HashMap::new();
+        for collection in &self.collections {
+            let node = match graph.index_of(collection.object_id) {
+                Some(node) => node,
+                None => continue,
+            };
+            for referrers in inbound.referrers_by_class(graph, node)? {
+                let owner = owners
+                    .entry((referrers.class_name, referrers.field))
+                    .or_default();
+                match collection.problem {
+                    Inefficiency::EmptyAllocated => owner.empty_allocated += 1,
+                    Inefficiency::SingleElement => owner.single_element += 1,
+                    Inefficiency::LowFillRatio => owner.low_fill_ratio += 1,
+                    Inefficiency::CapacitySlack => owner.capacity_slack += 1,
+                }
+                owner.wasted_bytes += collection.wasted_bytes;
+            }
+        }
+        let mut owners: Vec<OwnerCollectionWaste> = owners
+            .into_iter()
+            .map(|((class_name, field), owner)| OwnerCollectionWaste {
+                class_name,
+                field,
+                ..owner
+            })
+            .collect();
+        owners.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| b.collections().cmp(&a.collections()))
+                .then_with(|| a.class_name.cmp(&b.class_name))
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        Ok(owners)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_collection_waste() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let list = dump.class(
+            "java/util/ArrayList",
+            None,
+            &[("elementData", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let map = dump.class(
+            "java/util/HashMap",
+            None,
+            &[("table", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let objects = dump.class("[Ljava/lang/Object;", None, &[]);
+        let nodes = dump.class("[Ljava/util/HashMap$Node;", None, &[]);
+        let owner = dump.class(
+            "com/acme/Order",
+            None,
+            &[
+                ("lines", FieldType::Object),
+                ("attributes", FieldType::Object),
+            ],
+        );
+        let element = dump.instance(object, &[]);
+
+        // Empty list with Object[10]: 56 bytes.
+        let data = dump.object_array(objects, &[id(0); 10]);
+        let empty = dump.instance(list, &[FieldValue::Object(data), FieldValue::Int(0)]);
+        // Two elements in Object[20]: 18 unused slots.
+        let mut elements = vec![element, element];
+        elements.resize(20, id(0));
+        let data = dump.object_array(objects, &elements);
+        let slack = dump.instance(list, &[FieldValue::Object(data), FieldValue::Int(2)]);
+        // Five entries in a 64-slot table instead of 16 slots.
+        let table = dump.object_array(nodes, &[id(0); 64]);
+        let sparse = dump.instance(map, &[FieldValue::Object(table), FieldValue::Int(5)]);
+        // Not reported.
+        let data = dump.object_array(objects, &[element, element, element]);
+        let full = dump.instance(list, &[FieldValue::Object(data), FieldValue::Int(3)]);
+
+        dump.instance(
+            owner,
+            &[FieldValue::Object(empty), FieldValue::Object(sparse)],
+        );
+        dump.instance(
+            owner,
+            &[FieldValue::Object(slack), FieldValue::Object(id(0))],
+        );
+        dump.instance(
+            owner,
+            &[FieldValue::Object(full), FieldValue::Object(id(0))],
+        );
+
+        let waste =
+            CollectionWasteAnalyzer::new(SizeModel::HOTSPOT_64_COMPRESSED).analyze(&dump.store());
+        let rows: Vec<(Id, Inefficiency, u64)> = waste
+            .collections
+            .iter()
+            .map(|c| (c.object_id, c.problem, c.wasted_bytes))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (sparse, Inefficiency::LowFillRatio, 192),
+                (slack, Inefficiency::CapacitySlack, 72),
+                (empty, Inefficiency::EmptyAllocated, 56),
+            ]
+        );
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let owners = waste.by_owner(&graph, &inbound).unwrap();
+        let rows: Vec<(&str, u64, u64)> = owners
+            .iter()
+            .map(|o| (o.field.as_str(), o.collections(), o.wasted_bytes))
+            .collect();
+        assert_eq!(rows, vec![("attributes", 1, 192), ("lines", 2, 128)]);
+    }
+
+    #[test]
+    fn test_hash_set_reported_once() {
+        let mut dump = TestDump::new();
+        let map = dump.class(
+            "java/util/HashMap",
+            None,
+            &[("table", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let set = dump.class("java/util/HashSet", None, &[("map", FieldType::Object)]);
+        let nodes = dump.class("[Ljava/util/HashMap$Node;", None, &[]);
+        // Two entries in a 64-slot table.
+        let table = dump.object_array(nodes, &[id(0); 64]);
+        let map_id = dump.instance(map, &[FieldValue::Object(table), FieldValue::Int(2)]);
+        let set_id = dump.instance(set, &[FieldValue::Object(map_id)]);
+
+        let waste =
+            CollectionWasteAnalyzer::new(SizeModel::HOTSPOT_64_COMPRESSED).analyze(&dump.store());
+        assert_eq!(waste.collections.len(), 1);
+        assert_eq!(waste.collections[0].object_id, set_id);
+        assert_eq!(waste.collections[0].problem, Inefficiency::LowFillRatio);
+    }
+}
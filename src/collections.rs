@@ -0,0 +1,727 @@
+#![forbid(unsafe_code)]
+
+/*!
+Walkers for common JDK collections.
+
+Supported are `ArrayList`, `LinkedList`, `ArrayDeque`, `HashMap`,
+`LinkedHashMap`, `ConcurrentHashMap`, `TreeMap` and the sets backed by
+them (`HashSet`, `LinkedHashSet`, `TreeSet`), including their
+subclasses.  Field layouts of JDK 8 to JDK 21 are the same for these
+classes.
+
+Walkers work with `ObjectStore`, so object arrays have to be loaded
+(see `StreamHprofReader::with_load_object_arrays`); otherwise only
+size and capacity are available.
+ */
+
+use crate::decl::*;
+use crate::objects::ObjectStore;
+use crate::registry::ClassRegistry;
+use std::iter;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CollectionKind {
+    ArrayList,
+    LinkedList,
+    ArrayDeque,
+    HashMap,
+    LinkedHashMap,
+    ConcurrentHashMap,
+    TreeMap,
+    HashSet,
+    LinkedHashSet,
+    TreeSet,
+}
+
+const KINDS: [CollectionKind; 10] = [
+    CollectionKind::ArrayList,
+    CollectionKind::LinkedList,
+    CollectionKind::ArrayDeque,
+    CollectionKind::HashMap,
+    CollectionKind::LinkedHashMap,
+    CollectionKind::ConcurrentHashMap,
+    CollectionKind::TreeMap,
+    CollectionKind::HashSet,
+    CollectionKind::LinkedHashSet,
+    CollectionKind::TreeSet,
+];
+
+impl CollectionKind {
+    /// Java binary name of the class.
+    pub fn class_name(self) -> &'static str {
+        match self {
+            CollectionKind::ArrayList => "java.util.ArrayList",
+            CollectionKind::LinkedList => "java.util.LinkedList",
+            CollectionKind::ArrayDeque => "java.util.ArrayDeque",
+            CollectionKind::HashMap => "java.util.HashMap",
+            CollectionKind::LinkedHashMap => "java.util.LinkedHashMap",
+            CollectionKind::ConcurrentHashMap => "java.util.concurrent.ConcurrentHashMap",
+            CollectionKind::TreeMap => "java.util.TreeMap",
+            CollectionKind::HashSet => "java.util.HashSet",
+            CollectionKind::LinkedHashSet => "java.util.LinkedHashSet",
+            CollectionKind::TreeSet => "java.util.TreeSet",
+        }
+    }
+
+    /// Kind by Java binary name of exactly this class.
+    pub fn from_class_name(name: &str) -> Option<Self> {
+        KINDS.iter().copied().find(|kind| kind.class_name() == name)
+    }
+
+    /// Kind of the class or its nearest supported superclass.
+    pub fn of_class(registry: &ClassRegistry, class_id: Id) -> Option<Self> {
+        registry.superclasses(class_id).find_map(|class_desc| {
+            KINDS
+                .iter()
+                .copied()
+                .find(|kind| registry.has_name(class_desc.class_id, kind.class_name()))
+        })
+    }
+
+    /**
+    Size of a collection read from its own fields, for streaming
+    analyses that cannot follow references.  `None` for sets (count
+    their backing maps instead) and `ArrayDeque`.  For
+    `ConcurrentHashMap` it is `baseCount`, which misses counts kept in
+    counter cells under contention.
+     */
+    pub fn size_from_fields(
+        self,
+        registry: &ClassRegistry,
+        instance: &InstanceDump,
+    ) -> Option<u64> {
+        let field = match self {
+            CollectionKind::ArrayList
+            | CollectionKind::LinkedList
+            | CollectionKind::HashMap
+            | CollectionKind::LinkedHashMap
+            | CollectionKind::TreeMap => "size",
+            CollectionKind::ConcurrentHashMap => "baseCount",
+            _ => return None,
+        };
+        instance
+            .values
+            .iter()
+            .find(|(info, _)| registry.field_name(info) == Some(field))
+            .and_then(|(_, value)| match value {
+                FieldValue::Int(size) => Some((*size).max(0) as u64),
+                FieldValue::Long(size) => Some((*size).max(0) as u64),
+                _ => None,
+            })
+    }
+
+    #[inline]
+    pub fn is_map(self) -> bool {
+        matches!(
+            self,
+            CollectionKind::HashMap
+                | CollectionKind::LinkedHashMap
+                | CollectionKind::ConcurrentHashMap
+                | CollectionKind::TreeMap
+        )
+    }
+
+    #[inline]
+    pub fn is_set(self) -> bool {
+        matches!(
+            self,
+            CollectionKind::HashSet | CollectionKind::LinkedHashSet | CollectionKind::TreeSet
+        )
+    }
+}
+
+/// Storage layout the walker has to deal with.
+#[derive(Clone, Copy, Debug)]
+enum Layout {
+    Array,
+    Linked,
+    Deque,
+    Hash,
+    Concurrent,
+    Tree,
+}
+
+/**
+A collection instance.  Sets are walked through their backing map.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Collection<'a> {
+    store: &'a ObjectStore,
+    kind: CollectionKind,
+    object_id: Id,
+    /// The instance that holds the data: the collection itself, or
+    /// the backing map for sets.
+    data: &'a InstanceDump,
+    layout: Layout,
+}
+
+impl<'a> Collection<'a> {
+    /// Returns `None` if the object is not an instance of a supported
+    /// collection class, or a set without backing map.
+    pub fn new(store: &'a ObjectStore, id: Id) -> Option<Self> {
+        let instance = store.instance(id)?;
+        let kind = CollectionKind::of_class(store.registry(), instance.class_object_id)?;
+        let (data, layout) = match kind {
+            CollectionKind::ArrayList => (instance, Layout::Array),
+            CollectionKind::LinkedList => (instance, Layout::Linked),
+            CollectionKind::ArrayDeque => (instance, Layout::Deque),
+            CollectionKind::HashMap | CollectionKind::LinkedHashMap => (instance, Layout::Hash),
+            CollectionKind::ConcurrentHashMap => (instance, Layout::Concurrent),
+            CollectionKind::TreeMap => (instance, Layout::Tree),
+            CollectionKind::HashSet | CollectionKind::LinkedHashSet => (
+                store.instance(store.object_field(instance, "map")?)?,
+                Layout::Hash,
+            ),
+            CollectionKind::TreeSet => {
+                let map = store.instance(store.object_field(instance, "m")?)?;
+                // TreeSet may be backed by any NavigableMap.
+                if CollectionKind::of_class(store.registry(), map.class_object_id)
+                    != Some(CollectionKind::TreeMap)
+                {
+                    return None;
+                }
+                (map, Layout::Tree)
+            }
+        };
+        Some(Self {
+            store,
+            kind,
+            object_id: id,
+            data,
+            layout,
+        })
+    }
+
+    #[inline]
+    pub fn kind(&self) -> CollectionKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn object_id(&self) -> Id {
+        self.object_id
+    }
+
+    /// The map backing a set; `None` for lists and maps.
+    pub fn backing_map(&self) -> Option<Id> {
+        if self.kind.is_set() {
+            Some(self.data.object_id)
+        } else {
+            None
+        }
+    }
+
+    fn int_field(&self, name: &str) -> Option<i64> {
+        match self.store.field(self.data, name)? {
+            FieldValue::Int(v) => Some(v.into()),
+            FieldValue::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn object_field(&self, name: &str) -> Option<Id> {
+        self.store.object_field(self.data, name)
+    }
+
+    /// Backing array: `elementData` of `ArrayList`, `elements` of
+    /// `ArrayDeque`, `table` of hash maps.  `None` for linked
+    /// structures and for not yet allocated tables.
+    pub fn backing_array(&self) -> Option<Id> {
+        match self.layout {
+            Layout::Array => self.object_field("elementData"),
+            Layout::Deque => self.object_field("elements"),
+            Layout::Hash | Layout::Concurrent => self.object_field("table"),
+            Layout::Linked | Layout::Tree => None,
+        }
+    }
+
+    fn backing_array_len(&self) -> Option<u64> {
+        self.backing_array()
+            .and_then(|id| self.store.object_array(id))
+            .map(|array| array.num_elements.into())
+    }
+
+    /// Number of elements (entries for maps).
+    pub fn size(&self) -> u64 {
+        let size = match self.layout {
+            Layout::Array | Layout::Linked | Layout::Hash | Layout::Tree => {
+                self.int_field("size").unwrap_or(0)
+            }
+            Layout::Deque => {
+                let len = self.backing_array_len().unwrap_or(0) as i64;
+                let head = self.int_field("head").unwrap_or(0);
+                let tail = self.int_field("tail").unwrap_or(0);
+                if len == 0 {
+                    0
+                } else {
+                    (tail - head).rem_euclid(len)
+                }
+            }
+            Layout::Concurrent => {
+                let counter_cells = self
+                    .object_field("counterCells")
+                    .and_then(|id| self.store.object_array(id))
+                    .and_then(|array| array.values.as_ref());
+                let cells_sum: i64 = counter_cells
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|cell| self.store.instance(*cell))
+                    .filter_map(|cell| match self.store.field(cell, "value") {
+                        Some(FieldValue::Long(v)) => Some(v),
+                        _ => None,
+                    })
+                    .sum();
+                self.int_field("baseCount").unwrap_or(0) + cells_sum
+            }
+        };
+        size.max(0) as u64
+    }
+
+    /// Length of the backing array; zero if it is not allocated yet.
+    /// `None` for linked structures.
+    pub fn capacity(&self) -> Option<u64> {
+        match self.layout {
+            Layout::Linked | Layout::Tree => None,
+            _ => Some(self.backing_array_len().unwrap_or(0)),
+        }
+    }
+
+    /// `size / capacity`; `None` for linked structures and unallocated
+    /// backing arrays.
+    pub fn fill_ratio(&self) -> Option<f64> {
+        match self.capacity() {
+            Some(capacity) if capacity > 0 => Some(self.size() as f64 / capacity as f64),
+            _ => None,
+        }
+    }
+
+    /// Walk elements of lists and sets, or keys of maps.  Null
+    /// elements are yielded as zero ids.  At most `size()` elements are
+    /// yielded, so corrupted links do not cause infinite walks.
+    pub fn elements(&self) -> Box<dyn Iterator<Item = Id> + 'a> {
+        let size = self.size() as usize;
+        match self.layout {
+            Layout::Array => Box::new(self.array_values().take(size)),
+            Layout::Deque => {
+                let values = self.array_slice();
+                let head = self.int_field("head").unwrap_or(0).max(0) as usize;
+                let len = values.len();
+                Box::new(
+                    (0..size)
+                        .filter(move |_| len > 0)
+                        .map(move |idx| values[(head + idx) % len]),
+                )
+            }
+            Layout::Linked => {
+                let store = self.store;
+                let mut node = self.object_field("first");
+                Box::new(
+                    iter::from_fn(move || {
+                        let current = store.instance(node?)?;
+                        node = store.object_field(current, "next");
+                        Some(field_id(store, current, "item"))
+                    })
+                    .take(size),
+                )
+            }
+            Layout::Hash | Layout::Concurrent | Layout::Tree => {
+                Box::new(self.map_entries().map(|(key, _)| key))
+            }
+        }
+    }
+
+    /// Walk `(key, value)` pairs of maps.  Empty for lists and sets;
+    /// walk sets with `elements`.
+    pub fn entries(&self) -> Box<dyn Iterator<Item = (Id, Id)> + 'a> {
+        if !self.kind.is_map() {
+            return Box::new(iter::empty());
+        }
+        self.map_entries()
+    }
+
+    /// Entries of the map, or of the map backing a set.
+    fn map_entries(&self) -> Box<dyn Iterator<Item = (Id, Id)> + 'a> {
+        let size = self.size() as usize;
+        let store = self.store;
+        match self.layout {
+            Layout::Hash => Box::new(
+                self.array_values()
+                    .flat_map(move |bucket| chain(store, bucket, "next"))
+                    .map(move |node| (field_id(store, node, "key"), field_id(store, node, "value")))
+                    .take(size),
+            ),
+            Layout::Concurrent => Box::new(
+                self.array_values()
+                    .flat_map(move |bucket| {
+                        let first = match store.instance(bucket) {
+                            // TreeBin keeps its nodes in a list
+                            // starting at "first".
+                            Some(bin) if store.field(bin, "first").is_some() => {
+                                field_id(store, bin, "first")
+                            }
+                            // ForwardingNode: the map is being resized.
+                            Some(bin) if store.field(bin, "nextTable").is_some() => id0(),
+                            _ => bucket,
+                        };
+                        chain(store, first, "next")
+                    })
+                    .map(move |node| (field_id(store, node, "key"), field_id(store, node, "val")))
+                    .take(size),
+            ),
+            Layout::Tree => {
+                let mut stack = Vec::new();
+                let mut node = self.object_field("root").and_then(|id| store.instance(id));
+                Box::new(
+                    iter::from_fn(move || {
+                        // In-order traversal.
+                        while let Some(current) = node {
+                            stack.push(current);
+                            node = store
+                                .object_field(current, "left")
+                                .and_then(|id| store.instance(id));
+                        }
+                        let current = stack.pop()?;
+                        node = store
+                            .object_field(current, "right")
+                            .and_then(|id| store.instance(id));
+                        Some((
+                            field_id(store, current, "key"),
+                            field_id(store, current, "value"),
+                        ))
+                    })
+                    .take(size),
+                )
+            }
+            Layout::Array | Layout::Linked | Layout::Deque => Box::new(iter::empty()),
+        }
+    }
+
+    fn array_slice(&self) -> &'a [Id] {
+        self.backing_array()
+            .and_then(|id| self.store.object_array(id))
+            .and_then(|array| array.values.as_deref())
+            .unwrap_or(&[])
+    }
+
+    fn array_values(&self) -> impl Iterator<Item = Id> + 'a {
+        self.array_slice().iter().copied()
+    }
+}
+
+fn id0() -> Id {
+    Id::from(0u64)
+}
+
+fn field_id(store: &ObjectStore, instance: &InstanceDump, name: &str) -> Id {
+    store.object_field(instance, name).unwrap_or_else(id0)
+}
+
+/// Walk a linked list of nodes.
+fn chain<'a>(
+    store: &'a ObjectStore,
+    first: Id,
+    next: &'static str,
+) -> impl Iterator<Item = &'a InstanceDump> + 'a {
+    let mut node = store.instance(first);
+    iter::from_fn(move || {
+        let current = node?;
+        node = store
+            .object_field(current, next)
+            .and_then(|id| store.instance(id));
+        Some(current)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    fn obj(id: Id) -> FieldValue {
+        FieldValue::Object(id)
+    }
+
+    #[test]
+    fn test_array_list() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let list = dump.class(
+            "java/util/ArrayList",
+            None,
+            &[("elementData", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let objects = dump.class("[Ljava/lang/Object;", None, &[]);
+        let a = dump.instance(object, &[]);
+        let b = dump.instance(object, &[]);
+        let data = dump.object_array(objects, &[a, b, id(0), id(0)]);
+        let list_id = dump.instance(list, &[obj(data), FieldValue::Int(2)]);
+        let store = dump.store();
+
+        let coll = Collection::new(&store, list_id).unwrap();
+        assert_eq!(coll.kind(), CollectionKind::ArrayList);
+        assert_eq!(coll.size(), 2);
+        assert_eq!(coll.capacity(), Some(4));
+        assert_eq!(coll.fill_ratio(), Some(0.5));
+        assert_eq!(coll.elements().collect::<Vec<_>>(), vec![a, b]);
+        assert_eq!(coll.entries().count(), 0);
+        let array = store.object_array(coll.backing_array().unwrap()).unwrap();
+        assert_eq!(
+            store
+                .registry()
+                .java_class_name(array.element_class_id)
+                .as_deref(),
+            Some("java.lang.Object[]")
+        );
+    }
+
+    #[test]
+    fn test_linked_list() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let node = dump.class(
+            "java/util/LinkedList$Node",
+            None,
+            &[
+                ("item", FieldType::Object),
+                ("next", FieldType::Object),
+                ("prev", FieldType::Object),
+            ],
+        );
+        let list = dump.class(
+            "java/util/LinkedList",
+            None,
+            &[
+                ("size", FieldType::Int),
+                ("first", FieldType::Object),
+                ("last", FieldType::Object),
+            ],
+        );
+        let a = dump.instance(object, &[]);
+        let b = dump.instance(object, &[]);
+        let n2 = dump.instance(node, &[obj(b), obj(id(0)), obj(id(0))]);
+        let n1 = dump.instance(node, &[obj(id(0)), obj(n2), obj(id(0))]);
+        let n0 = dump.instance(node, &[obj(a), obj(n1), obj(id(0))]);
+        let list_id = dump.instance(list, &[FieldValue::Int(3), obj(n0), obj(n2)]);
+        // Corrupted: the size is bigger than the chain.
+        let broken = dump.instance(list, &[FieldValue::Int(5), obj(n1), obj(n2)]);
+        let store = dump.store();
+
+        let coll = Collection::new(&store, list_id).unwrap();
+        assert_eq!(coll.kind(), CollectionKind::LinkedList);
+        assert_eq!(coll.size(), 3);
+        assert_eq!(coll.capacity(), None);
+        assert_eq!(coll.fill_ratio(), None);
+        assert_eq!(coll.elements().collect::<Vec<_>>(), vec![a, id(0), b]);
+
+        let coll = Collection::new(&store, broken).unwrap();
+        assert_eq!(coll.elements().collect::<Vec<_>>(), vec![id(0), b]);
+    }
+
+    #[test]
+    fn test_array_deque_wraps_around() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let objects = dump.class("[Ljava/lang/Object;", None, &[]);
+        let deque = dump.class(
+            "java/util/ArrayDeque",
+            None,
+            &[
+                ("elements", FieldType::Object),
+                ("head", FieldType::Int),
+                ("tail", FieldType::Int),
+            ],
+        );
+        let items: Vec<Id> = (0..3).map(|_| dump.instance(object, &[])).collect();
+        // head = 6, tail = 1: items at 6, 7 and 0.
+        let data = dump.object_array(
+            objects,
+            &[
+                items[2],
+                id(0),
+                id(0),
+                id(0),
+                id(0),
+                id(0),
+                items[0],
+                items[1],
+            ],
+        );
+        let deque_id = dump.instance(deque, &[obj(data), FieldValue::Int(6), FieldValue::Int(1)]);
+        let empty_data = dump.object_array(objects, &[id(0); 16]);
+        let empty = dump.instance(
+            deque,
+            &[obj(empty_data), FieldValue::Int(3), FieldValue::Int(3)],
+        );
+        let store = dump.store();
+
+        let coll = Collection::new(&store, deque_id).unwrap();
+        assert_eq!(coll.kind(), CollectionKind::ArrayDeque);
+        assert_eq!(coll.size(), 3);
+        assert_eq!(coll.capacity(), Some(8));
+        assert_eq!(coll.backing_array(), Some(data));
+        assert_eq!(coll.elements().collect::<Vec<_>>(), items);
+
+        let coll = Collection::new(&store, empty).unwrap();
+        assert_eq!(coll.size(), 0);
+        assert_eq!(coll.capacity(), Some(16));
+        assert_eq!(coll.elements().count(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_hash_map() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let node = dump.class(
+            "java/util/concurrent/ConcurrentHashMap$Node",
+            None,
+            &[
+                ("hash", FieldType::Int),
+                ("key", FieldType::Object),
+                ("val", FieldType::Object),
+                ("next", FieldType::Object),
+            ],
+        );
+        let nodes = dump.class("[Ljava/util/concurrent/ConcurrentHashMap$Node;", None, &[]);
+        let cell = dump.class(
+            "java/util/concurrent/ConcurrentHashMap$CounterCell",
+            None,
+            &[("value", FieldType::Long)],
+        );
+        let cells = dump.class(
+            "[Ljava/util/concurrent/ConcurrentHashMap$CounterCell;",
+            None,
+            &[],
+        );
+        let map = dump.class(
+            "java/util/concurrent/ConcurrentHashMap",
+            None,
+            &[
+                ("table", FieldType::Object),
+                ("baseCount", FieldType::Long),
+                ("counterCells", FieldType::Object),
+            ],
+        );
+        let keys: Vec<Id> = (0..3).map(|_| dump.instance(object, &[])).collect();
+        let vals: Vec<Id> = (0..3).map(|_| dump.instance(object, &[])).collect();
+        let n2 = dump.instance(
+            node,
+            &[FieldValue::Int(2), obj(keys[2]), obj(vals[2]), obj(id(0))],
+        );
+        let n1 = dump.instance(
+            node,
+            &[FieldValue::Int(1), obj(keys[1]), obj(vals[1]), obj(n2)],
+        );
+        let n0 = dump.instance(
+            node,
+            &[FieldValue::Int(0), obj(keys[0]), obj(vals[0]), obj(id(0))],
+        );
+        let table = dump.object_array(nodes, &[id(0), n0, id(0), n1]);
+        // One entry counted in baseCount, two in the counter cells.
+        let c0 = dump.instance(cell, &[FieldValue::Long(2)]);
+        let c1 = dump.instance(cell, &[FieldValue::Long(0)]);
+        let counter_cells = dump.object_array(cells, &[c0, id(0), c1]);
+        let map_id = dump.instance(map, &[obj(table), FieldValue::Long(1), obj(counter_cells)]);
+        let store = dump.store();
+
+        let coll = Collection::new(&store, map_id).unwrap();
+        assert_eq!(coll.kind(), CollectionKind::ConcurrentHashMap);
+        assert_eq!(coll.size(), 3);
+        assert_eq!(coll.capacity(), Some(4));
+        assert_eq!(
+            coll.entries().collect::<Vec<_>>(),
+            keys.iter()
+                .copied()
+                .zip(vals.iter().copied())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(coll.elements().collect::<Vec<_>>(), keys);
+    }
+
+    #[test]
+    fn test_hash_set_over_hash_map() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let node = dump.class(
+            "java/util/HashMap$Node",
+            None,
+            &[
+                ("hash", FieldType::Int),
+                ("key", FieldType::Object),
+                ("value", FieldType::Object),
+                ("next", FieldType::Object),
+            ],
+        );
+        let map = dump.class(
+            "java/util/HashMap",
+            None,
+            &[("table", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let nodes = dump.class("[Ljava/util/HashMap$Node;", None, &[]);
+        let set = dump.class("java/util/HashSet", None, &[("map", FieldType::Object)]);
+        let my_set = dump.class("com/acme/MySet", Some(set), &[]);
+
+        let keys: Vec<Id> = (0..3).map(|_| dump.instance(object, &[])).collect();
+        let present = dump.instance(object, &[]);
+        let n2 = dump.instance(
+            node,
+            &[FieldValue::Int(2), obj(keys[2]), obj(present), obj(id(0))],
+        );
+        let n1 = dump.instance(
+            node,
+            &[FieldValue::Int(1), obj(keys[1]), obj(present), obj(n2)],
+        );
+        let n0 = dump.instance(
+            node,
+            &[FieldValue::Int(0), obj(keys[0]), obj(present), obj(id(0))],
+        );
+        let table = dump.object_array(nodes, &[n0, id(0), n1, id(0)]);
+        let map_id = dump.instance(map, &[obj(table), FieldValue::Int(3)]);
+        let set_id = dump.instance(my_set, &[obj(map_id)]);
+        let store = dump.store();
+
+        let coll = Collection::new(&store, set_id).unwrap();
+        assert_eq!(coll.kind(), CollectionKind::HashSet);
+        assert_eq!(coll.size(), 3);
+        assert_eq!(coll.capacity(), Some(4));
+        assert_eq!(coll.elements().collect::<Vec<_>>(), keys);
+        assert_eq!(coll.entries().count(), 0);
+
+        let coll = Collection::new(&store, map_id).unwrap();
+        assert!(coll.kind().is_map());
+        assert_eq!(
+            coll.entries().collect::<Vec<_>>(),
+            keys.iter().map(|k| (*k, present)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_tree_map_in_order() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let entry = dump.class(
+            "java/util/TreeMap$Entry",
+            None,
+            &[
+                ("key", FieldType::Object),
+                ("value", FieldType::Object),
+                ("left", FieldType::Object),
+                ("right", FieldType::Object),
+            ],
+        );
+        let tree = dump.class(
+            "java/util/TreeMap",
+            None,
+            &[("root", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let keys: Vec<Id> = (0..3).map(|_| dump.instance(object, &[])).collect();
+        let left = dump.instance(entry, &[obj(keys[0]), obj(id(0)), obj(id(0)), obj(id(0))]);
+        let right = dump.instance(entry, &[obj(keys[2]), obj(id(0)), obj(id(0)), obj(id(0))]);
+        let root = dump.instance(entry, &[obj(keys[1]), obj(id(0)), obj(left), obj(right)]);
+        let tree_id = dump.instance(tree, &[obj(root), FieldValue::Int(3)]);
+        let store = dump.store();
+
+        let coll = Collection::new(&store, tree_id).unwrap();
+        assert_eq!(coll.capacity(), None);
+        assert_eq!(coll.elements().collect::<Vec<_>>(), keys);
+    }
+}
@@ -0,0 +1,196 @@
+#![forbid(unsafe_code)]
+
+/*!
+CPU profile of the `hprof` agent (`-agentlib:hprof=cpu=samples`):
+CPU_SAMPLES records count samples per stack trace.  A profile can be
+written as folded stacks for flame graph tools or as a pprof profile.
+ */
+
+use crate::decl::*;
+use crate::pprof::ProfileBuilder;
+use crate::symbol::{StackFrame, Symbolizer};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Samples of a stack trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceSamples {
+    pub stack_trace_serial: SerialNumber,
+    pub samples: u64,
+    /// Top frame first; `None` for missing frame records.
+    pub frames: Vec<Option<StackFrame>>,
+}
+
+/// A resolved CPU_SAMPLES record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuProfile {
+    pub total_samples: u64,
+    /// Most samples first.
+    pub traces: Vec<TraceSamples>,
+    /// Stack depth limit from the CONTROL_SETTINGS record, if any.
+    pub stack_trace_depth: Option<u16>,
+}
+
+impl CpuProfile {
+    /// Resolve the record with the symbolizer fed by the same dump.
+    pub fn new(record: &CpuSamplesRecord, symbolizer: &Symbolizer) -> Self {
+        let mut samples: HashMap<SerialNumber, u64> = HashMap::new();
+        for trace in &record.traces {
+            *samples.entry(trace.stack_trace_serial).or_default() += u64::from(trace.samples);
+        }
+        let mut traces: Vec<TraceSamples> = samples
+            .into_iter()
+            .map(|(stack_trace_serial, samples)| TraceSamples {
+                stack_trace_serial,
+                samples,
+                frames: symbolizer
+                    .stack_trace(stack_trace_serial)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        traces.sort_by(|a, b| {
+            b.samples
+                .cmp(&a.samples)
+                .then_with(|| a.stack_trace_serial.cmp(&b.stack_trace_serial))
+        });
+        Self {
+            total_samples: record.total_samples.into(),
+            traces,
+            stack_trace_depth: None,
+        }
+    }
+
+    /// Profiles for all CPU_SAMPLES records of the dump.  The agent
+    /// writes cumulative counts, so the last one is usually wanted.
+    pub fn from_records<I, S>(records: I) -> Result<Vec<Self>, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut symbolizer = Symbolizer::new();
+        let mut cpu_samples = Vec::new();
+        let mut stack_trace_depth = None;
+        for rec in records {
+            let (_, record) = rec?;
+            match record {
+                Record::CpuSamples(samples) => cpu_samples.push(samples),
+                Record::ControlSettings(settings) => {
+                    stack_trace_depth = Some(settings.stack_trace_depth)
+                }
+                record => symbolizer.add_record(&record),
+            }
+        }
+        Ok(cpu_samples
+            .iter()
+            .map(|samples| Self {
+                stack_trace_depth,
+                ..Self::new(samples, &symbolizer)
+            })
+            .collect())
+    }
+
+    /// Write folded stacks with sample counts.  Stacks whose frames
+    /// are equal are merged.
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut lines: HashMap<String, u64> = HashMap::new();
+        for trace in &self.traces {
+            let frames: Vec<String> = trace
+                .frames
+                .iter()
+                .rev()
+                .map(|frame| match frame {
+                    Some(frame) => frame.qualified_name(),
+                    None => "<unknown frame>".to_string(),
+                })
+                .collect();
+            let line = if frames.is_empty() {
+                "<no frames>".to_string()
+            } else {
+                frames.join(";")
+            };
+            *lines.entry(line).or_default() += trace.samples;
+        }
+        let mut lines: Vec<(String, u64)> = lines.into_iter().collect();
+        lines.sort();
+        for (line, samples) in lines {
+            writeln!(out, "{} {}", line, samples)?;
+        }
+        Ok(())
+    }
+
+    /// Write a pprof profile with the `samples/count` sample type.
+    pub fn write_pprof<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut builder =
+            ProfileBuilder::new(&[("samples", "count")]).with_period("samples", "count", 1);
+        for trace in &self.traces {
+            let locations = trace
+                .frames
+                .iter()
+                .map(|frame| builder.frame_location(frame.as_ref()))
+                .collect();
+            builder.add_sample(locations, vec![trace.samples as i64]);
+        }
+        builder.write(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pprof::tests::strings;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_cpu_profile() {
+        let mut dump = TestDump::new();
+        let main = dump.class("com/acme/Main", None, &[]);
+        let main_frame = dump.stack_frame(main, "main", "()V", 3);
+        let work = dump.stack_frame(main, "work", "()V", 30);
+        dump.stack_trace(1, &[work, main_frame]);
+        dump.stack_trace(2, &[main_frame]);
+        dump.record(Record::ControlSettings(ControlSettingsRecord {
+            flags: CONTROL_CPU_SAMPLING,
+            stack_trace_depth: 4,
+        }));
+        dump.record(Record::CpuSamples(CpuSamplesRecord {
+            total_samples: 10,
+            traces: vec![
+                CpuTrace {
+                    samples: 3,
+                    stack_trace_serial: 2,
+                },
+                CpuTrace {
+                    samples: 7,
+                    stack_trace_serial: 1,
+                },
+            ],
+        }));
+
+        let profiles = CpuProfile::from_records(dump.records()).unwrap();
+        assert_eq!(profiles.len(), 1);
+        let profile = &profiles[0];
+        assert_eq!(profile.total_samples, 10);
+        assert_eq!(profile.stack_trace_depth, Some(4));
+        let traces: Vec<(SerialNumber, u64)> = profile
+            .traces
+            .iter()
+            .map(|trace| (trace.stack_trace_serial, trace.samples))
+            .collect();
+        assert_eq!(traces, vec![(1, 7), (2, 3)]);
+
+        let mut out = Vec::new();
+        profile.write_folded(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "com.acme.Main.main 3\n\
+             com.acme.Main.main;com.acme.Main.work 7\n"
+        );
+
+        let mut out = Vec::new();
+        profile.write_pprof(&mut out).unwrap();
+        let strings = strings(&out);
+        assert!(strings.contains(&"com.acme.Main.work"));
+        assert!(strings.contains(&"com.acme.Main.main()V"));
+        assert!(strings.contains(&"Main.java"));
+    }
+}
@@ -13,6 +13,8 @@ pub(crate) const TAG_HEAP_SUMMARY: u8 = 0x07;
 pub(crate) const TAG_START_THREAD: u8 = 0x0A;
 pub(crate) const TAG_END_THREAD: u8 = 0x0B;
 pub(crate) const TAG_HEAP_DUMP: u8 = 0x0C;
+pub(crate) const TAG_CPU_SAMPLES: u8 = 0x0D;
+pub(crate) const TAG_CONTROL_SETTINGS: u8 = 0x0E;
 pub(crate) const TAG_HEAP_DUMP_SEGMENT: u8 = 0x1C;
 pub(crate) const TAG_HEAP_DUMP_END: u8 = 0x2C;
 
@@ -78,7 +80,7 @@ pub type Ts = u64;
 /// Class serial number
 pub type SerialNumber = u32;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Record<Str> {
     String(Id, Str),
     LoadClass(ClassRecord),
@@ -89,6 +91,8 @@ pub enum Record<Str> {
     HeapSummary(HeapSummaryRecord),
     StartThread(StartThreadRecord),
     EndThread(EndThreadRecord),
+    CpuSamples(CpuSamplesRecord),
+    ControlSettings(ControlSettingsRecord),
     Dump(DumpRecord),
 }
 
@@ -169,7 +173,30 @@ pub struct EndThreadRecord {
     pub thread_serial: SerialNumber,
 }
 
-#[derive(Clone, Copy, Debug, TryFromPrimitive)]
+#[derive(Clone, Debug)]
+pub struct CpuTrace {
+    pub samples: u32,
+    pub stack_trace_serial: SerialNumber,
+}
+
+#[derive(Clone, Debug)]
+pub struct CpuSamplesRecord {
+    pub total_samples: u32,
+    pub traces: Vec<CpuTrace>,
+}
+
+/// `ControlSettingsRecord::flags` bit: allocation traces are on.
+pub const CONTROL_ALLOC_TRACES: u32 = 0x1;
+/// `ControlSettingsRecord::flags` bit: CPU sampling is on.
+pub const CONTROL_CPU_SAMPLING: u32 = 0x2;
+
+#[derive(Clone, Debug)]
+pub struct ControlSettingsRecord {
+    pub flags: u32,
+    pub stack_trace_depth: u16,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum FieldType {
     Object = 2,
@@ -195,6 +222,22 @@ impl FieldType {
             FieldType::Double | FieldType::Long => 8,
         })
     }
+
+    /// Java source name of the type: `int`, `boolean`, etc.;
+    /// `Object` for FieldType::Object.
+    pub fn java_name(self) -> &'static str {
+        match self {
+            FieldType::Object => "Object",
+            FieldType::Bool => "boolean",
+            FieldType::Char => "char",
+            FieldType::Float => "float",
+            FieldType::Double => "double",
+            FieldType::Byte => "byte",
+            FieldType::Short => "short",
+            FieldType::Int => "int",
+            FieldType::Long => "long",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -0,0 +1,288 @@
+#![forbid(unsafe_code)]
+
+/*!
+JVM type descriptors and internal class names.
+
+HPROF strings use the JVM internal forms: class names like
+`java/lang/String` or `[Ljava/lang/Object;` for array classes, and
+method descriptors like `(ILjava/lang/String;)V`.  This module parses
+them and renders Java source syntax: `java.lang.Object[]`,
+`void bar(int, String)`.
+ */
+
+use crate::decl::FieldType;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum BaseType {
+    /// Never `FieldType::Object`.
+    Primitive(FieldType),
+    /// Class name in the internal form, e.g. `java/lang/String`.
+    Class(String),
+}
+
+/// Field type: a base type with array dimensions.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TypeDescriptor {
+    pub base: BaseType,
+    pub dimensions: u8,
+}
+
+/// Method parameter and return types.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MethodDescriptor {
+    pub parameters: Vec<TypeDescriptor>,
+    /// `None` for `void`.
+    pub return_type: Option<TypeDescriptor>,
+}
+
+/// Malformed descriptor or class name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DescriptorError {
+    pub descriptor: String,
+    /// Byte offset of the error.
+    pub position: usize,
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid descriptor {:?} at position {}",
+            self.descriptor, self.position
+        )
+    }
+}
+
+impl std::error::Error for DescriptorError {}
+
+/// Descriptor parser over a string.
+struct Parser<'a> {
+    descriptor: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(descriptor: &'a str) -> Self {
+        Self { descriptor, pos: 0 }
+    }
+
+    fn error(&self) -> DescriptorError {
+        DescriptorError {
+            descriptor: self.descriptor.to_string(),
+            position: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.descriptor.as_bytes().get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), DescriptorError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn end(&self) -> Result<(), DescriptorError> {
+        if self.pos == self.descriptor.len() {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn field_type(&mut self) -> Result<TypeDescriptor, DescriptorError> {
+        let mut dimensions = 0u8;
+        while self.peek() == Some(b'[') {
+            dimensions = dimensions.checked_add(1).ok_or_else(|| self.error())?;
+            self.pos += 1;
+        }
+        let base = match self.peek() {
+            Some(b'L') => {
+                let start = self.pos + 1;
+                let len = self.descriptor[start..]
+                    .find(';')
+                    .filter(|len| *len > 0)
+                    .ok_or_else(|| self.error())?;
+                self.pos = start + len + 1;
+                BaseType::Class(self.descriptor[start..start + len].to_string())
+            }
+            Some(code) => {
+                let field_type = primitive(code).ok_or_else(|| self.error())?;
+                self.pos += 1;
+                BaseType::Primitive(field_type)
+            }
+            None => return Err(self.error()),
+        };
+        Ok(TypeDescriptor { base, dimensions })
+    }
+}
+
+fn primitive(code: u8) -> Option<FieldType> {
+    Some(match code {
+        b'Z' => FieldType::Bool,
+        b'B' => FieldType::Byte,
+        b'C' => FieldType::Char,
+        b'S' => FieldType::Short,
+        b'I' => FieldType::Int,
+        b'J' => FieldType::Long,
+        b'F' => FieldType::Float,
+        b'D' => FieldType::Double,
+        _ => return None,
+    })
+}
+
+impl TypeDescriptor {
+    /// Parse a field descriptor: `I`, `Ljava/lang/String;`, `[[J`.
+    pub fn parse(descriptor: &str) -> Result<Self, DescriptorError> {
+        let mut parser = Parser::new(descriptor);
+        let result = parser.field_type()?;
+        parser.end()?;
+        Ok(result)
+    }
+
+    /// Parse an internal class name as used by LOAD_CLASS records:
+    /// `java/lang/String`, or a descriptor for array classes
+    /// (`[Ljava/lang/Object;`, `[[I`).
+    pub fn from_class_name(name: &str) -> Result<Self, DescriptorError> {
+        if name.starts_with('[') {
+            return Self::parse(name);
+        }
+        if name.is_empty() || name.contains(&[';', '['][..]) {
+            return Err(DescriptorError {
+                descriptor: name.to_string(),
+                position: 0,
+            });
+        }
+        Ok(Self {
+            base: BaseType::Class(name.to_string()),
+            dimensions: 0,
+        })
+    }
+
+    /// Java name: `java.lang.String[]`, `int[][]`.
+    pub fn java_name(&self) -> String {
+        let base = match &self.base {
+            BaseType::Primitive(field_type) => field_type.java_name().to_string(),
+            BaseType::Class(name) => name.replace('/', "."),
+        };
+        base + &"[]".repeat(self.dimensions.into())
+    }
+
+    /// Name without the package: `String[]`, `int[][]`.
+    pub fn simple_name(&self) -> String {
+        let base = match &self.base {
+            BaseType::Primitive(field_type) => field_type.java_name(),
+            BaseType::Class(name) => name.rsplit('/').next().unwrap_or(name),
+        };
+        base.to_string() + &"[]".repeat(self.dimensions.into())
+    }
+}
+
+/// Same as `java_name`.
+impl fmt::Display for TypeDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.java_name())
+    }
+}
+
+impl MethodDescriptor {
+    /// Parse a method descriptor: `(ILjava/lang/String;)V`.
+    pub fn parse(descriptor: &str) -> Result<Self, DescriptorError> {
+        let mut parser = Parser::new(descriptor);
+        parser.expect(b'(')?;
+        let mut parameters = Vec::new();
+        while parser.peek() != Some(b')') {
+            parameters.push(parser.field_type()?);
+        }
+        parser.expect(b')')?;
+        let return_type = if parser.peek() == Some(b'V') {
+            parser.pos += 1;
+            None
+        } else {
+            Some(parser.field_type()?)
+        };
+        parser.end()?;
+        Ok(Self {
+            parameters,
+            return_type,
+        })
+    }
+
+    /// Java declaration of a method with simple type names:
+    /// `void bar(int, String)`.
+    pub fn java_declaration(&self, method_name: &str) -> String {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(TypeDescriptor::simple_name)
+            .collect();
+        format!(
+            "{} {}({})",
+            self.return_type
+                .as_ref()
+                .map_or_else(|| "void".to_string(), TypeDescriptor::simple_name),
+            method_name,
+            parameters.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_descriptors() {
+        let string = TypeDescriptor::from_class_name("java/lang/String").unwrap();
+        assert_eq!(string.java_name(), "java.lang.String");
+        assert_eq!(
+            TypeDescriptor::from_class_name("[Ljava/lang/Object;")
+                .unwrap()
+                .java_name(),
+            "java.lang.Object[]"
+        );
+        let ints = TypeDescriptor::from_class_name("[[I").unwrap();
+        assert_eq!(ints.base, BaseType::Primitive(FieldType::Int));
+        assert_eq!(ints.to_string(), "int[][]");
+        assert_eq!(
+            TypeDescriptor::parse("[Lcom/acme/Outer$Inner;")
+                .unwrap()
+                .simple_name(),
+            "Outer$Inner[]"
+        );
+
+        assert_eq!(
+            TypeDescriptor::parse("Ljava/lang/String")
+                .unwrap_err()
+                .position,
+            0
+        );
+        assert_eq!(TypeDescriptor::parse("II").unwrap_err().position, 1);
+        assert!(TypeDescriptor::parse("[").is_err());
+        assert!(TypeDescriptor::from_class_name("[Q").is_err());
+    }
+
+    #[test]
+    fn test_method_descriptors() {
+        let method = MethodDescriptor::parse("(ILjava/lang/String;[[J)V").unwrap();
+        assert_eq!(method.parameters.len(), 3);
+        assert_eq!(method.return_type, None);
+        assert_eq!(
+            method.java_declaration("bar"),
+            "void bar(int, String, long[][])"
+        );
+        assert_eq!(
+            MethodDescriptor::parse("()[Ljava/lang/Object;")
+                .unwrap()
+                .java_declaration("toArray"),
+            "Object[] toArray()"
+        );
+        assert!(MethodDescriptor::parse("(I").is_err());
+        assert!(MethodDescriptor::parse("()VV").is_err());
+    }
+}
@@ -0,0 +1,581 @@
+#![forbid(unsafe_code)]
+
+/*!
+Comparison of two heap dumps.
+
+Object ids are not stable between dumps (objects move during GC), so
+dumps are compared by summaries: the class histogram, optionally with
+retained sizes, duplicate strings statistics and class loaders grouped
+by loader class.  A `DumpSummary` is built in one pass over a dump;
+then `HeapDiff` compares two summaries.
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::diff::{DumpSummary, HeapDiff};
+# use hprof_dump_parser::size::SizeModel;
+# let (before_data, after_data) = (vec![], vec![]);
+let hprof = StreamHprofReader::new();
+let model = SizeModel::HOTSPOT_64_COMPRESSED;
+let records = hprof.read_hprof_from_memory(&before_data).unwrap();
+let id_size = records.id_size;
+let before = DumpSummary::from_records(records, id_size, model, true).unwrap();
+let records = hprof.read_hprof_from_memory(&after_data).unwrap();
+let id_size = records.id_size;
+let after = DumpSummary::from_records(records, id_size, model, true).unwrap();
+HeapDiff::new(&before, &after)
+    .write_text(&mut std::io::stdout(), 20)
+    .unwrap();
+```
+ */
+
+use crate::collections::CollectionKind;
+use crate::decl::*;
+use crate::dominator::DominatorTree;
+use crate::graph::GraphBuilder;
+use crate::histogram::HistogramBuilder;
+use crate::registry::ClassRegistry;
+use crate::size::SizeModel;
+use crate::strings::{StringDuplicatesBuilder, StringStats};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Objects of a class.  Classes of the same name defined by different
+/// loaders are merged.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClassSummary {
+    pub class_name: String,
+    pub instances: u64,
+    pub shallow_size: u64,
+    /// See `RetainedGroup::retained_size`.
+    pub retained_size: Option<u64>,
+}
+
+/// Class loaders of the same class.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoaderSummary {
+    /// Loader class name; `<bootstrap>` for the bootstrap loader.
+    pub class_name: String,
+    pub loaders: u64,
+    /// Classes defined by these loaders.
+    pub classes: u64,
+}
+
+/// Collections of a kind, see `CollectionKind::size_from_fields`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CollectionSummary {
+    pub kind: CollectionKind,
+    pub collections: u64,
+    /// Total number of elements.
+    pub elements: u64,
+    /// Size of the biggest collection.
+    pub max_size: u64,
+}
+
+/// Summary of a dump for comparison with other dumps.
+#[derive(Clone, Debug, Default)]
+pub struct DumpSummary {
+    /// Sorted by class name.
+    pub classes: Vec<ClassSummary>,
+    pub strings: StringStats,
+    /// Sorted by loader class name.
+    pub class_loaders: Vec<LoaderSummary>,
+    /// Collections whose size is known from their fields, sorted by
+    /// kind name.
+    pub collections: Vec<CollectionSummary>,
+}
+
+/**
+Streaming `DumpSummary` builder.  Use it to build a summary in the same
+pass with other consumers of records; otherwise
+`DumpSummary::from_records` is simpler.
+ */
+#[derive(Debug)]
+pub struct SummaryBuilder {
+    id_size: u32,
+    size_model: SizeModel,
+    histogram: HistogramBuilder,
+    strings: StringDuplicatesBuilder,
+    registry: ClassRegistry,
+    /// Number of classes by defining loader.
+    loader_classes: HashMap<Id, u64>,
+    /// Classes of loader instances.
+    loader_instances: HashMap<Id, Id>,
+    /// Collection kinds by class.
+    collection_kinds: HashMap<Id, Option<CollectionKind>>,
+    collections: HashMap<CollectionKind, CollectionSummary>,
+    graph: Option<GraphBuilder>,
+}
+
+impl SummaryBuilder {
+    pub fn new(id_size: u32, size_model: SizeModel) -> Self {
+        Self {
+            id_size,
+            size_model,
+            histogram: HistogramBuilder::new(size_model),
+            strings: StringDuplicatesBuilder::new(size_model),
+            registry: ClassRegistry::new(),
+            loader_classes: HashMap::new(),
+            loader_instances: HashMap::new(),
+            collection_kinds: HashMap::new(),
+            collections: HashMap::new(),
+            graph: None,
+        }
+    }
+
+    /// Compute retained sizes of classes.  It builds the object graph,
+    /// which needs much more memory than the rest of the summary.
+    pub fn with_retained_sizes(mut self, flag: bool) -> Self {
+        self.graph = if flag {
+            Some(GraphBuilder::new(self.id_size).with_size_model(self.size_model))
+        } else {
+            None
+        };
+        self
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        self.histogram.add_record(record);
+        self.strings.add_record(record);
+        self.registry.add_record(record);
+        if let Some(graph) = &mut self.graph {
+            graph.add_record(record);
+        }
+        match record {
+            Record::Dump(DumpRecord::ClassDump(class_desc)) => {
+                *self
+                    .loader_classes
+                    .entry(class_desc.class_loader_object_id)
+                    .or_default() += 1;
+            }
+            Record::Dump(DumpRecord::InstanceDump(instance)) => {
+                // HotSpot writes class dumps before instances, so all
+                // loaders are known.
+                if self.loader_classes.contains_key(&instance.object_id) {
+                    self.loader_instances
+                        .insert(instance.object_id, instance.class_object_id);
+                }
+                self.add_collection(instance);
+            }
+            _ => {}
+        }
+    }
+
+    fn add_collection(&mut self, instance: &InstanceDump) {
+        let registry = &self.registry;
+        let kind = *self
+            .collection_kinds
+            .entry(instance.class_object_id)
+            .or_insert_with(|| CollectionKind::of_class(registry, instance.class_object_id));
+        let (kind, size) =
+            match kind.and_then(|kind| Some((kind, kind.size_from_fields(registry, instance)?))) {
+                Some(found) => found,
+                None => return,
+            };
+        let summary = self
+            .collections
+            .entry(kind)
+            .or_insert_with(|| CollectionSummary {
+                kind,
+                collections: 0,
+                elements: 0,
+                max_size: 0,
+            });
+        summary.collections += 1;
+        summary.elements += size;
+        summary.max_size = summary.max_size.max(size);
+    }
+
+    /// Fails only if retained sizes are computed and the object graph
+    /// cannot be built, see `GraphBuilder::finish`.
+    pub fn finish(self) -> Result<DumpSummary, Error> {
+        let mut classes: BTreeMap<String, ClassSummary> = BTreeMap::new();
+        for entry in self.histogram.finish().entries {
+            let class = classes
+                .entry(entry.class_name.clone())
+                .or_insert_with_key(|class_name| ClassSummary {
+                    class_name: class_name.clone(),
+                    ..ClassSummary::default()
+                });
+            class.instances += entry.instances;
+            class.shallow_size += entry.shallow_size;
+        }
+        if let Some(graph) = self.graph {
+            let graph = graph.finish()?;
+            let tree = DominatorTree::compute(&graph);
+            for group in tree.retained_by_class(&graph) {
+                if let Some(class) = classes.get_mut(&group.name) {
+                    *class.retained_size.get_or_insert(0) += group.retained_size;
+                }
+            }
+            for class in classes.values_mut() {
+                class.retained_size.get_or_insert(0);
+            }
+        }
+
+        let mut loaders: BTreeMap<String, LoaderSummary> = BTreeMap::new();
+        let registry = self.registry;
+        let loader_instances = self.loader_instances;
+        for (loader, count) in self.loader_classes {
+            let class_name = if u64::from(loader) == 0 {
+                "<bootstrap>".to_string()
+            } else {
+                loader_instances
+                    .get(&loader)
+                    .and_then(|class_id| registry.java_class_name(*class_id))
+                    .unwrap_or_else(|| "<unknown>".to_string())
+            };
+            let summary =
+                loaders
+                    .entry(class_name)
+                    .or_insert_with_key(|class_name| LoaderSummary {
+                        class_name: class_name.clone(),
+                        ..LoaderSummary::default()
+                    });
+            summary.loaders += 1;
+            summary.classes += count;
+        }
+
+        let mut collections: Vec<CollectionSummary> = self.collections.into_values().collect();
+        collections.sort_by_key(|summary| summary.kind.class_name());
+
+        Ok(DumpSummary {
+            classes: classes.into_values().collect(),
+            strings: self.strings.finish().stats,
+            class_loaders: loaders.into_values().collect(),
+            collections,
+        })
+    }
+}
+
+impl DumpSummary {
+    /// Summarize a dump; see `SummaryBuilder::with_retained_sizes` for
+    /// `retained_sizes`.
+    pub fn from_records<I, S>(
+        records: I,
+        id_size: u32,
+        size_model: SizeModel,
+        retained_sizes: bool,
+    ) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder =
+            SummaryBuilder::new(id_size, size_model).with_retained_sizes(retained_sizes);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        builder.finish()
+    }
+
+    pub fn class(&self, class_name: &str) -> Option<&ClassSummary> {
+        self.classes
+            .binary_search_by(|class| class.class_name.as_str().cmp(class_name))
+            .ok()
+            .map(|idx| &self.classes[idx])
+    }
+
+    pub fn total_instances(&self) -> u64 {
+        self.classes.iter().map(|class| class.instances).sum()
+    }
+
+    pub fn total_shallow_size(&self) -> u64 {
+        self.classes.iter().map(|class| class.shallow_size).sum()
+    }
+}
+
+/// Change of a class between two dumps.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClassDelta {
+    pub class_name: String,
+    /// `None` if there were no objects of the class.
+    pub before: Option<ClassSummary>,
+    pub after: Option<ClassSummary>,
+}
+
+fn delta(before: u64, after: u64) -> i64 {
+    after as i64 - before as i64
+}
+
+impl ClassDelta {
+    pub fn instances_delta(&self) -> i64 {
+        delta(
+            self.before.as_ref().map_or(0, |c| c.instances),
+            self.after.as_ref().map_or(0, |c| c.instances),
+        )
+    }
+
+    pub fn shallow_size_delta(&self) -> i64 {
+        delta(
+            self.before.as_ref().map_or(0, |c| c.shallow_size),
+            self.after.as_ref().map_or(0, |c| c.shallow_size),
+        )
+    }
+
+    /// `None` if retained sizes were not computed for both dumps.
+    pub fn retained_size_delta(&self) -> Option<i64> {
+        let retained = |c: &Option<ClassSummary>| match c {
+            Some(c) => c.retained_size,
+            None => Some(0),
+        };
+        Some(delta(retained(&self.before)?, retained(&self.after)?))
+    }
+
+    /// Retained size delta if known, shallow size delta otherwise.
+    pub fn size_delta(&self) -> i64 {
+        self.retained_size_delta()
+            .unwrap_or_else(|| self.shallow_size_delta())
+    }
+
+    /// The class had no objects in the first dump.
+    #[inline]
+    pub fn is_new(&self) -> bool {
+        self.before.is_none()
+    }
+}
+
+/// Change of class loaders of a class between two dumps.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoaderDelta {
+    pub class_name: String,
+    pub before: Option<LoaderSummary>,
+    pub after: Option<LoaderSummary>,
+}
+
+/// Differences between two dump summaries.
+#[derive(Clone, Debug)]
+pub struct HeapDiff {
+    /// All classes, biggest growth first (see `ClassDelta::size_delta`).
+    pub classes: Vec<ClassDelta>,
+    pub strings_before: StringStats,
+    pub strings_after: StringStats,
+    /// Loader classes whose loaders or classes changed, sorted by name.
+    pub class_loaders: Vec<LoaderDelta>,
+}
+
+/// Join two lists sorted by name.
+fn join_by_name<T: Clone>(
+    before: &[T],
+    after: &[T],
+    name: impl Fn(&T) -> &str,
+) -> Vec<(String, Option<T>, Option<T>)> {
+    let mut joined: BTreeMap<String, (Option<T>, Option<T>)> = BTreeMap::new();
+    for item in before {
+        joined.entry(name(item).to_string()).or_default().0 = Some(item.clone());
+    }
+    for item in after {
+        joined.entry(name(item).to_string()).or_default().1 = Some(item.clone());
+    }
+    joined
+        .into_iter()
+        .map(|(name, (before, after))| (name, before, after))
+        .collect()
+}
+
+impl HeapDiff {
+    pub fn new(before: &DumpSummary, after: &DumpSummary) -> Self {
+        let mut classes: Vec<ClassDelta> =
+            join_by_name(&before.classes, &after.classes, |c| &c.class_name)
+                .into_iter()
+                .map(|(class_name, before, after)| ClassDelta {
+                    class_name,
+                    before,
+                    after,
+                })
+                .collect();
+        classes.sort_by(|a, b| {
+            b.size_delta()
+                .cmp(&a.size_delta())
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        let class_loaders = join_by_name(&before.class_loaders, &after.class_loaders, |l| {
+            &l.class_name
+        })
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(class_name, before, after)| LoaderDelta {
+            class_name,
+            before,
+            after,
+        })
+        .collect();
+        Self {
+            classes,
+            strings_before: before.strings,
+            strings_after: after.strings,
+            class_loaders,
+        }
+    }
+
+    /// Classes that grew most, up to `count`.
+    pub fn top_growers(&self, count: usize) -> impl Iterator<Item = &ClassDelta> {
+        self.classes
+            .iter()
+            .take_while(|class| class.size_delta() > 0)
+            .take(count)
+    }
+
+    /// Classes without objects in the first dump, biggest growth
+    /// first.
+    pub fn new_classes(&self) -> impl Iterator<Item = &ClassDelta> {
+        self.classes.iter().filter(|class| class.is_new())
+    }
+
+    /// Write a plain text report with `top` top growers and new
+    /// classes.
+    pub fn write_text<W: Write>(&self, out: &mut W, top: usize) -> io::Result<()> {
+        let total = |f: fn(&ClassSummary) -> u64| {
+            let before: u64 = self
+                .classes
+                .iter()
+                .filter_map(|c| c.before.as_ref())
+                .map(f)
+                .sum();
+            let after: u64 = self
+                .classes
+                .iter()
+                .filter_map(|c| c.after.as_ref())
+                .map(f)
+                .sum();
+            (before, after)
+        };
+        let (before, after) = total(|c| c.shallow_size);
+        writeln!(
+            out,
+            "Shallow size: {} -> {} bytes ({:+})",
+            before,
+            after,
+            delta(before, after)
+        )?;
+        let (before, after) = total(|c| c.instances);
+        writeln!(
+            out,
+            "Objects: {} -> {} ({:+})",
+            before,
+            after,
+            delta(before, after)
+        )?;
+
+        writeln!(out, "\nTop growers:")?;
+        for class in self.top_growers(top) {
+            writeln!(
+                out,
+                "{:>+14} bytes {:>+12} objects  {}",
+                class.size_delta(),
+                class.instances_delta(),
+                class.class_name
+            )?;
+        }
+
+        writeln!(out, "\nNew classes:")?;
+        for class in self.new_classes().take(top) {
+            writeln!(
+                out,
+                "{:>+14} bytes {:>+12} objects  {}",
+                class.size_delta(),
+                class.instances_delta(),
+                class.class_name
+            )?;
+        }
+
+        let (before, after) = (&self.strings_before, &self.strings_after);
+        writeln!(
+            out,
+            "\nStrings: {} -> {}, duplicated {} -> {}, wasted {} -> {} bytes",
+            before.strings,
+            after.strings,
+            before.duplicated_strings,
+            after.duplicated_strings,
+            before.wasted_bytes,
+            after.wasted_bytes
+        )?;
+
+        if !self.class_loaders.is_empty() {
+            writeln!(out, "\nClass loaders:")?;
+            for loader in &self.class_loaders {
+                let counts = |l: &Option<LoaderSummary>| {
+                    l.as_ref().map_or((0, 0), |l| (l.loaders, l.classes))
+                };
+                let (loaders_before, classes_before) = counts(&loader.before);
+                let (loaders_after, classes_after) = counts(&loader.after);
+                writeln!(
+                    out,
+                    "  {}: {} -> {} loaders, {} -> {} classes",
+                    loader.class_name, loaders_before, loaders_after, classes_before, classes_after
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    fn summary(sessions: usize) -> DumpSummary {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let session = dump.class(
+            "com/acme/Session",
+            Some(object),
+            &[("next", FieldType::Object), ("id", FieldType::Long)],
+        );
+        if sessions > 1 {
+            let cache = dump.class("com/acme/Cache", Some(object), &[]);
+            dump.instance(cache, &[]);
+        }
+        let mut next = id(0);
+        for n in 0..sessions {
+            next = dump.instance(
+                session,
+                &[FieldValue::Object(next), FieldValue::Long(n as i64)],
+            );
+        }
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: next,
+            jni_global_ref: id(1),
+        });
+        DumpSummary::from_records(
+            dump.records(),
+            ID_SIZE,
+            SizeModel::HOTSPOT_64_COMPRESSED,
+            true,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = summary(1);
+        let after = summary(3);
+        assert_eq!(
+            after.class("com.acme.Session"),
+            Some(&ClassSummary {
+                class_name: "com.acme.Session".to_string(),
+                instances: 3,
+                shallow_size: 72,
+                retained_size: Some(72),
+            })
+        );
+        assert_eq!(after.class_loaders[0].classes, 3);
+
+        let diff = HeapDiff::new(&before, &after);
+        let growers: Vec<(&str, i64)> = diff
+            .top_growers(10)
+            .map(|c| (c.class_name.as_str(), c.size_delta()))
+            .collect();
+        // Unreachable classes have zero retained size.
+        assert_eq!(growers, vec![("com.acme.Session", 48)]);
+        let new: Vec<&str> = diff.new_classes().map(|c| c.class_name.as_str()).collect();
+        assert_eq!(new, vec!["com.acme.Cache"]);
+        assert_eq!(diff.class_loaders.len(), 1);
+
+        let mut text = Vec::new();
+        diff.write_text(&mut text, 10).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("           +48 bytes           +2 objects  com.acme.Session\n"));
+    }
+}
@@ -0,0 +1,504 @@
+#![forbid(unsafe_code)]
+
+/*!
+Dominator tree and retained sizes.
+
+An object `d` dominates `v` if every path from GC roots to `v` passes
+through `d`; the retained size of `d` is the total shallow size of
+objects it dominates, i.e. the memory that would be freed with it.  All
+GC roots are connected to a virtual super-root, so objects reachable
+from several roots are dominated by the super-root only.
+
+The tree is computed with the Lengauer–Tarjan algorithm (path
+compression variant).  Besides the graph and its inbound index, it
+takes about 40 bytes per node; all recursions are replaced with
+explicit stacks, so huge graphs are fine.  Objects unreachable from GC
+roots are not in the tree.
+ */
+
+use crate::decl::Id;
+use crate::graph::{HeapGraph, NodeIndex, NodeKind, NO_NODE};
+use crate::inbound::InboundIndex;
+use crate::value::object_id_label;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+/// Immediate dominator of top-level nodes.
+const SUPER_ROOT: NodeIndex = u32::MAX - 1;
+/// Marks unvisited nodes and empty links.
+const NONE: u32 = u32::MAX;
+
+/// Dominator tree, see module documentation.
+#[derive(Debug)]
+pub struct DominatorTree {
+    idom: Vec<NodeIndex>,
+    retained: Vec<u64>,
+    /// Children of node `n` at `child_offsets[n]..child_offsets[n+1]`;
+    /// the last range is for the super-root.
+    child_offsets: Vec<u64>,
+    children: Vec<NodeIndex>,
+}
+
+/// Retained memory of a group of objects: a class or a class loader.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetainedGroup {
+    pub name: String,
+    pub objects: u64,
+    pub shallow_size: u64,
+    /**
+    Retained size of the group's objects.  An object dominated by
+    another object of the same group is not counted twice, so this is
+    the memory that would be freed if all objects of the group were
+    gone.
+     */
+    pub retained_size: u64,
+}
+
+/// Union-find forest of the algorithm with `eval` and `link`.
+struct Forest {
+    ancestor: Vec<u32>,
+    label: Vec<u32>,
+    path: Vec<u32>,
+}
+
+impl Forest {
+    fn eval(&mut self, v: u32, semi: &[u32]) -> u32 {
+        if self.ancestor[v as usize] == NONE {
+            return v;
+        }
+        // Iterative path compression.
+        let mut x = v;
+        while self.ancestor[self.ancestor[x as usize] as usize] != NONE {
+            self.path.push(x);
+            x = self.ancestor[x as usize];
+        }
+        while let Some(x) = self.path.pop() {
+            let x = x as usize;
+            let a = self.ancestor[x] as usize;
+            if semi[self.label[a] as usize] < semi[self.label[x] as usize] {
+                self.label[x] = self.label[a];
+            }
+            self.ancestor[x] = self.ancestor[a];
+        }
+        self.label[v as usize]
+    }
+}
+
+impl DominatorTree {
+    /// Compute the tree, building an in-memory inbound index for it.
+    pub fn compute(graph: &HeapGraph) -> Self {
+        let inbound = InboundIndex::build(graph);
+        Self::compute_with_inbound(graph, &inbound).expect("in-memory inbound index does not fail")
+    }
+
+    /// Compute the tree with an existing inbound index of the graph,
+    /// e.g. one spilled to disk.
+    pub fn compute_with_inbound(graph: &HeapGraph, inbound: &InboundIndex) -> io::Result<Self> {
+        let count = graph.node_count();
+        let (idom, order) = dominators(graph, inbound)?;
+
+        let mut retained: Vec<u64> = vec![0; count];
+        for node in order.iter().rev() {
+            let node = *node as usize;
+            retained[node] += graph.shallow_size(node as NodeIndex);
+            let dom = idom[node];
+            if dom != SUPER_ROOT {
+                retained[dom as usize] += retained[node];
+            }
+        }
+
+        let slot = |dom: NodeIndex| match dom {
+            SUPER_ROOT => count,
+            dom => dom as usize,
+        };
+        let mut child_offsets = vec![0u64; count + 2];
+        for node in order.iter() {
+            child_offsets[slot(idom[*node as usize]) + 1] += 1;
+        }
+        for idx in 0..=count {
+            child_offsets[idx + 1] += child_offsets[idx];
+        }
+        let mut cursors = child_offsets.clone();
+        let mut children = vec![0; order.len()];
+        for node in order.iter() {
+            let cursor = &mut cursors[slot(idom[*node as usize])];
+            children[*cursor as usize] = *node;
+            *cursor += 1;
+        }
+        for idx in 0..=count {
+            children[child_offsets[idx] as usize..child_offsets[idx + 1] as usize]
+                .sort_unstable_by_key(|child| std::cmp::Reverse(retained[*child as usize]));
+        }
+
+        Ok(Self {
+            idom,
+            retained,
+            child_offsets,
+            children,
+        })
+    }
+
+    /// Whether the node is reachable from GC roots.
+    #[inline]
+    pub fn is_reachable(&self, node: NodeIndex) -> bool {
+        self.idom[node as usize] != NO_NODE
+    }
+
+    /// Immediate dominator of the node; `None` for unreachable nodes
+    /// and for nodes dominated by the super-root only.
+    pub fn immediate_dominator(&self, node: NodeIndex) -> Option<NodeIndex> {
+        match self.idom[node as usize] {
+            NO_NODE | SUPER_ROOT => None,
+            dom => Some(dom),
+        }
+    }
+
+    /// Retained size of the node in bytes; zero for unreachable
+    /// nodes.
+    #[inline]
+    pub fn retained_size(&self, node: NodeIndex) -> u64 {
+        self.retained[node as usize]
+    }
+
+    /// Nodes immediately dominated by the node, biggest retained size
+    /// first.
+    #[inline]
+    pub fn children(&self, node: NodeIndex) -> &[NodeIndex] {
+        self.child_range(node as usize)
+    }
+
+    /// Nodes dominated by the super-root only, biggest retained size
+    /// first.
+    #[inline]
+    pub fn root_children(&self) -> &[NodeIndex] {
+        self.child_range(self.idom.len())
+    }
+
+    /// Size of all objects reachable from GC roots.
+    pub fn total_retained_size(&self) -> u64 {
+        self.root_children()
+            .iter()
+            .map(|node| self.retained[*node as usize])
+            .sum()
+    }
+
+    fn child_range(&self, slot: usize) -> &[NodeIndex] {
+        &self.children[self.child_offsets[slot] as usize..self.child_offsets[slot + 1] as usize]
+    }
+
+    /// Retained size per type (see `HeapGraph::type_key`), biggest
+    /// first.
+    pub fn retained_by_class(&self, graph: &HeapGraph) -> Vec<RetainedGroup> {
+        let groups = self.retained_by(graph, |node| graph.type_key(node));
+        sorted_groups(
+            groups
+                .into_values()
+                .map(|(sample, stats)| (graph.type_name(sample), stats)),
+        )
+    }
+
+    /**
+    Retained size per class loader, biggest first.  Classes belong to
+    their defining loader, other objects to the loader of their class;
+    primitive arrays and objects of unknown classes belong to the
+    bootstrap loader, named `<bootstrap>`.  Other loaders are named by
+    their class and id.
+     */
+    pub fn retained_by_class_loader(&self, graph: &HeapGraph) -> Vec<RetainedGroup> {
+        let registry = graph.registry();
+        let loader_of = |class: Id| {
+            registry
+                .class(class)
+                .map_or(0, |class_desc| u64::from(class_desc.class_loader_object_id))
+        };
+        let groups = self.retained_by(graph, |node| match graph.kind(node) {
+            NodeKind::Class => loader_of(graph.id(node)),
+            NodeKind::PrimitiveArray(_) => 0,
+            NodeKind::Instance | NodeKind::ObjectArray => graph
+                .class_of(node)
+                .map_or(0, |class| loader_of(graph.id(class))),
+        });
+        sorted_groups(groups.into_iter().map(|(loader, (_, stats))| {
+            let name = if loader == 0 {
+                "<bootstrap>".to_string()
+            } else {
+                let loader = Id::from(loader);
+                let class_name = graph
+                    .index_of(loader)
+                    .map_or_else(|| "<unknown>".to_string(), |node| graph.type_name(node));
+                format!("{}@{}", class_name, object_id_label(loader))
+            };
+            (name, stats)
+        }))
+    }
+
+    /// Group reachable nodes by key, counting retained sizes of nested
+    /// nodes with the same key only once.  Returns a sample node of
+    /// each group with its stats.
+    pub(crate) fn retained_by<K, F>(
+        &self,
+        graph: &HeapGraph,
+        key_of: F,
+    ) -> HashMap<K, (NodeIndex, Stats)>
+    where
+        K: Copy + Eq + Hash,
+        F: Fn(NodeIndex) -> K,
+    {
+        let mut groups: HashMap<K, (NodeIndex, Stats)> = HashMap::new();
+        let mut active: HashMap<K, u32> = HashMap::new();
+        let mut stack: Vec<(NodeIndex, Option<K>)> = self
+            .root_children()
+            .iter()
+            .map(|node| (*node, None))
+            .collect();
+        while let Some((node, entered)) = stack.pop() {
+            if let Some(key) = entered {
+                if let Some(depth) = active.get_mut(&key) {
+                    *depth -= 1;
+                }
+                continue;
+            }
+            let key = key_of(node);
+            let depth = active.entry(key).or_insert(0);
+            let group = groups.entry(key).or_insert((node, Stats::default()));
+            group.1.objects += 1;
+            group.1.shallow_size += graph.shallow_size(node);
+            if *depth == 0 {
+                group.1.retained_size += self.retained[node as usize];
+            }
+            *depth += 1;
+            stack.push((node, Some(key)));
+            stack.extend(self.children(node).iter().map(|child| (*child, None)));
+        }
+        groups
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Stats {
+    pub(crate) objects: u64,
+    pub(crate) shallow_size: u64,
+    pub(crate) retained_size: u64,
+}
+
+fn sorted_groups(groups: impl Iterator<Item = (String, Stats)>) -> Vec<RetainedGroup> {
+    let mut groups: Vec<RetainedGroup> = groups
+        .map(|(name, stats)| RetainedGroup {
+            name,
+            objects: stats.objects,
+            shallow_size: stats.shallow_size,
+            retained_size: stats.retained_size,
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.retained_size
+            .cmp(&a.retained_size)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    groups
+}
+
+/// Immediate dominators by node (`NO_NODE` for unreachable nodes) and
+/// reachable nodes in DFS preorder.
+fn dominators(
+    graph: &HeapGraph,
+    inbound: &InboundIndex,
+) -> io::Result<(Vec<NodeIndex>, Vec<NodeIndex>)> {
+    let count = graph.node_count();
+    let super_root = count as NodeIndex;
+    let mut root_nodes: Vec<NodeIndex> = graph.roots().iter().map(|root| root.node).collect();
+    root_nodes.dedup();
+    let successors = |node: NodeIndex| {
+        if node == super_root {
+            &root_nodes[..]
+        } else {
+            graph.outgoing(node)
+        }
+    };
+
+    // DFS numbering; the super-root gets number 0.
+    let mut dfn = vec![NONE; count + 1];
+    let mut vertex: Vec<NodeIndex> = vec![super_root];
+    let mut parent: Vec<u32> = vec![NONE];
+    let mut stack: Vec<(NodeIndex, usize)> = vec![(super_root, 0)];
+    dfn[super_root as usize] = 0;
+    while let Some(top) = stack.last_mut() {
+        let (node, pos) = *top;
+        let succ = successors(node);
+        if pos == succ.len() {
+            stack.pop();
+            continue;
+        }
+        top.1 += 1;
+        let next = succ[pos];
+        if dfn[next as usize] == NONE {
+            dfn[next as usize] = vertex.len() as u32;
+            parent.push(dfn[node as usize]);
+            vertex.push(next);
+            stack.push((next, 0));
+        }
+    }
+    drop(stack);
+
+    // The rest works with DFS numbers.
+    let reached = vertex.len();
+    let mut semi: Vec<u32> = (0..reached as u32).collect();
+    let mut idom: Vec<u32> = vec![0; reached];
+    let mut bucket_head = vec![NONE; reached];
+    let mut bucket_next = vec![NONE; reached];
+    let mut forest = Forest {
+        ancestor: vec![NONE; reached],
+        label: (0..reached as u32).collect(),
+        path: Vec::new(),
+    };
+
+    for w in (1..reached).rev() {
+        let node = vertex[w];
+        for pred in inbound.referrers(node)?.iter() {
+            let v = dfn[*pred as usize];
+            if v != NONE {
+                let u = forest.eval(v, &semi);
+                if semi[u as usize] < semi[w] {
+                    semi[w] = semi[u as usize];
+                }
+            }
+        }
+        if graph.is_root(node) {
+            semi[w] = 0;
+        }
+        let s = semi[w] as usize;
+        bucket_next[w] = bucket_head[s];
+        bucket_head[s] = w as u32;
+
+        let p = parent[w];
+        forest.ancestor[w] = p;
+        let mut v = bucket_head[p as usize];
+        while v != NONE {
+            let u = forest.eval(v, &semi);
+            idom[v as usize] = if semi[u as usize] < semi[v as usize] {
+                u
+            } else {
+                p
+            };
+            v = bucket_next[v as usize];
+        }
+        bucket_head[p as usize] = NONE;
+    }
+    for w in 1..reached {
+        if idom[w] != semi[w] {
+            idom[w] = idom[idom[w] as usize];
+        }
+    }
+
+    let mut node_idom = vec![NO_NODE; count];
+    for w in 1..reached {
+        node_idom[vertex[w] as usize] = match idom[w] {
+            0 => SUPER_ROOT,
+            d => vertex[d as usize],
+        };
+    }
+    vertex.remove(0);
+    Ok((node_idom, vertex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::graph::tests::sample_dump;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_sample_dominators() {
+        let (dump, ids) = sample_dump();
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let tree = DominatorTree::compute(&graph);
+        let node = |idx: usize| graph.index_of(ids[idx]).unwrap();
+
+        assert_eq!(tree.immediate_dominator(node(0)), None);
+        assert_eq!(tree.immediate_dominator(node(1)), Some(node(0)));
+        assert_eq!(tree.immediate_dominator(node(3)), Some(node(1)));
+        assert_eq!(tree.immediate_dominator(node(5)), Some(node(2)));
+        assert_eq!(tree.children(node(1)), &[node(2), node(3)]);
+        assert_eq!(tree.root_children(), &[node(0), node(6)]);
+
+        assert_eq!(tree.retained_size(node(2)), 24 + 16 + 32);
+        assert_eq!(tree.retained_size(node(1)), 32 + 72 + 24);
+        assert_eq!(tree.retained_size(node(0)), 24 + 128);
+        assert_eq!(tree.total_retained_size(), 152 + 32);
+
+        // Item and array classes are not reachable: instances do not
+        // reference their classes.
+        let unreachable = (0..graph.node_count() as NodeIndex)
+            .filter(|node| !tree.is_reachable(*node))
+            .count();
+        assert_eq!(unreachable, 2);
+
+        let by_class = tree.retained_by_class(&graph);
+        let summary: Vec<(&str, u64, u64)> = by_class
+            .iter()
+            .map(|g| (g.name.as_str(), g.objects, g.retained_size))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("com.acme.Holder", 1, 152),
+                ("com.acme.Item[]", 1, 128),
+                ("com.acme.Item", 2, 96),
+                ("int[]", 1, 32),
+                ("java.lang.Class", 2, 32),
+                ("java.lang.Object", 1, 16),
+            ]
+        );
+
+        let by_loader = tree.retained_by_class_loader(&graph);
+        assert_eq!(by_loader.len(), 1);
+        assert_eq!(by_loader[0].name, "<bootstrap>");
+        assert_eq!(by_loader[0].retained_size, 184);
+        assert_eq!(by_loader[0].objects, 8);
+    }
+
+    #[test]
+    fn test_diamond() {
+        // root -> a, root -> b, a -> c, b -> c: c is dominated by root.
+        let mut dump = TestDump::new();
+        let node_class = dump.class(
+            "Node",
+            None,
+            &[("left", FieldType::Object), ("right", FieldType::Object)],
+        );
+        let c = dump.instance(
+            node_class,
+            &[FieldValue::Object(id(0)), FieldValue::Object(id(0))],
+        );
+        let a = dump.instance(
+            node_class,
+            &[FieldValue::Object(c), FieldValue::Object(id(0))],
+        );
+        let b = dump.instance(
+            node_class,
+            &[FieldValue::Object(id(0)), FieldValue::Object(c)],
+        );
+        let root = dump.instance(node_class, &[FieldValue::Object(a), FieldValue::Object(b)]);
+        dump.dump(DumpRecord::RootUnknown { obj_id: root });
+        // A second root makes `c` dominated by the super-root.
+        let other = dump.instance(
+            node_class,
+            &[FieldValue::Object(c), FieldValue::Object(id(0))],
+        );
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: other,
+            jni_global_ref: id(1),
+        });
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let tree = DominatorTree::compute(&graph);
+        let node = |id| graph.index_of(id).unwrap();
+        assert_eq!(tree.immediate_dominator(node(a)), Some(node(root)));
+        assert_eq!(tree.immediate_dominator(node(c)), None);
+        assert_eq!(tree.retained_size(node(root)), 3 * 24);
+        assert_eq!(tree.total_retained_size(), 5 * 24);
+        assert_eq!(tree.retained_by_class(&graph)[0].retained_size, 5 * 24);
+    }
+}
@@ -0,0 +1,828 @@
+#![forbid(unsafe_code)]
+
+/*!
+In-memory object graph.
+
+Object ids are mapped to dense `u32` node indices (nodes are ordered by
+object id, so the mapping is a binary search in a sorted array), and
+outgoing references are kept in CSR (compressed sparse row) form.  The
+graph includes references from instance fields, object array
+elements, class static fields, and superclass and class loader links
+of classes.  Null references and references to objects missing from
+the dump are dropped.
+
+Shallow sizes of nodes follow a `SizeModel`; by default it is inferred
+from the dump, see `SizeModel::infer`.
+
+The graph is built in one pass over the records:
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::graph::HeapGraph;
+# let data = vec![];
+let hprof = StreamHprofReader::new().with_load_primitive_arrays(false);
+let records = hprof.read_hprof_from_memory(&data).unwrap();
+let id_size = records.id_size;
+let graph = HeapGraph::from_records(records, id_size).unwrap();
+```
+ */
+
+use crate::decl::*;
+use crate::registry::ClassRegistry;
+use crate::size::SizeModel;
+use std::fmt;
+use std::mem;
+
+/// Dense node index.
+pub type NodeIndex = u32;
+
+/// Absent node (e.g. class of a primitive array).
+pub const NO_NODE: NodeIndex = u32::MAX;
+
+/// Edge slot of superclass link.
+const SLOT_SUPER: u32 = u32::MAX;
+/// Edge slot of class loader link.
+const SLOT_LOADER: u32 = u32::MAX - 1;
+
+/// Number of edge targets in a `GraphBuilder` chunk.
+const TARGET_CHUNK: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NodeKind {
+    Class,
+    Instance,
+    ObjectArray,
+    PrimitiveArray(FieldType),
+}
+
+/// Kind of GC root, with the details from the root record.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GcRootKind {
+    Unknown,
+    JniGlobal,
+    JniLocal {
+        thread_serial: SerialNumber,
+        frame_number: u32,
+    },
+    JavaFrame {
+        thread_serial: SerialNumber,
+        frame_number: u32,
+    },
+    NativeStack {
+        thread_serial: SerialNumber,
+    },
+    StickyClass,
+    ThreadBlock {
+        thread_serial: SerialNumber,
+    },
+    MonitorUsed,
+    ThreadObject {
+        thread_serial: SerialNumber,
+        stack_trace_serial: SerialNumber,
+    },
+}
+
+impl GcRootKind {
+    /// Name of the `DumpRecord` variant, e.g. `RootJavaFrame`.
+    pub fn name(self) -> &'static str {
+        match self {
+            GcRootKind::Unknown => "RootUnknown",
+            GcRootKind::JniGlobal => "RootJniGlobal",
+            GcRootKind::JniLocal { .. } => "RootJniLocal",
+            GcRootKind::JavaFrame { .. } => "RootJavaFrame",
+            GcRootKind::NativeStack { .. } => "RootNativeStack",
+            GcRootKind::StickyClass => "RootStickyClass",
+            GcRootKind::ThreadBlock { .. } => "RootThreadBlock",
+            GcRootKind::MonitorUsed => "RootMonitorUsed",
+            GcRootKind::ThreadObject { .. } => "RootThreadObject",
+        }
+    }
+
+    /// Split root record into object id and root kind; `None` for
+    /// object records.
+    pub fn from_record(record: &DumpRecord) -> Option<(Id, Self)> {
+        Some(match *record {
+            DumpRecord::RootUnknown { obj_id } => (obj_id, GcRootKind::Unknown),
+            DumpRecord::RootJniGlobal { obj_id, .. } => (obj_id, GcRootKind::JniGlobal),
+            DumpRecord::RootJniLocal {
+                obj_id,
+                thread_serial,
+                frame_number,
+            } => (
+                obj_id,
+                GcRootKind::JniLocal {
+                    thread_serial,
+                    frame_number,
+                },
+            ),
+            DumpRecord::RootJavaFrame {
+                obj_id,
+                thread_serial,
+                frame_number,
+            } => (
+                obj_id,
+                GcRootKind::JavaFrame {
+                    thread_serial,
+                    frame_number,
+                },
+            ),
+            DumpRecord::RootNativeStack {
+                obj_id,
+                thread_serial,
+            } => (obj_id, GcRootKind::NativeStack { thread_serial }),
+            DumpRecord::RootStickyClass { obj_id } => (obj_id, GcRootKind::StickyClass),
+            DumpRecord::RootThreadBlock {
+                obj_id,
+                thread_serial,
+            } => (obj_id, GcRootKind::ThreadBlock { thread_serial }),
+            DumpRecord::RootMonitorUsed { obj_id } => (obj_id, GcRootKind::MonitorUsed),
+            DumpRecord::RootThreadObject {
+                obj_id,
+                thread_serial,
+                stack_trace_serial,
+            } => (
+                obj_id,
+                GcRootKind::ThreadObject {
+                    thread_serial,
+                    stack_trace_serial,
+                },
+            ),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for GcRootKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GcRoot {
+    pub node: NodeIndex,
+    pub kind: GcRootKind,
+}
+
+/// How an object references another one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EdgeLabel<'a> {
+    /// Instance field.
+    Field(&'a str),
+    /// Static field of a class.
+    StaticField(&'a str),
+    /// Object array element.
+    ArrayElement(u32),
+    SuperClass,
+    ClassLoader,
+    /// Field name is not known.
+    Unknown,
+}
+
+impl fmt::Display for EdgeLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdgeLabel::Field(name) => f.write_str(name),
+            EdgeLabel::StaticField(name) => write!(f, "static {}", name),
+            EdgeLabel::ArrayElement(idx) => write!(f, "[{}]", idx),
+            EdgeLabel::SuperClass => f.write_str("<super>"),
+            EdgeLabel::ClassLoader => f.write_str("<classloader>"),
+            EdgeLabel::Unknown => f.write_str("<unknown>"),
+        }
+    }
+}
+
+/// Kind of `java.lang.ref.Reference` subclass.  Their `referent` field
+/// does not keep the object alive.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ReferenceKind {
+    Soft,
+    Weak,
+    Phantom,
+    /// `FinalReference`, i.e. `java.lang.ref.Finalizer`.
+    Final,
+}
+
+const REFERENCE_KINDS: [ReferenceKind; 4] = [
+    ReferenceKind::Soft,
+    ReferenceKind::Weak,
+    ReferenceKind::Phantom,
+    ReferenceKind::Final,
+];
+
+impl ReferenceKind {
+    /// Java binary name of the class.
+    pub fn class_name(self) -> &'static str {
+        match self {
+            ReferenceKind::Soft => "java.lang.ref.SoftReference",
+            ReferenceKind::Weak => "java.lang.ref.WeakReference",
+            ReferenceKind::Phantom => "java.lang.ref.PhantomReference",
+            ReferenceKind::Final => "java.lang.ref.FinalReference",
+        }
+    }
+
+    /// Kind of the class or its nearest reference superclass.
+    pub fn of_class(registry: &ClassRegistry, class_id: Id) -> Option<Self> {
+        registry.superclasses(class_id).find_map(|class_desc| {
+            REFERENCE_KINDS
+                .iter()
+                .copied()
+                .find(|kind| registry.has_name(class_desc.class_id, kind.class_name()))
+        })
+    }
+}
+
+/// Object graph, see module documentation.
+#[derive(Debug)]
+pub struct HeapGraph {
+    /// Only class and field names are kept.
+    registry: ClassRegistry,
+    size_model: SizeModel,
+    /// Sorted object ids.
+    ids: Vec<Id>,
+    kinds: Vec<NodeKind>,
+    classes: Vec<NodeIndex>,
+    shallow_sizes: Vec<u64>,
+    /// Position of each node in the record order; edges are kept in
+    /// the record order, so it indexes `edge_offsets`.
+    record_order: Vec<NodeIndex>,
+    edge_offsets: Vec<u64>,
+    edges: Vec<NodeIndex>,
+    edge_slots: Vec<u32>,
+    /// Sorted by node.
+    roots: Vec<GcRoot>,
+}
+
+impl HeapGraph {
+    /// Build the graph from records.  `id_size` is used for inferring
+    /// the size model; take it from `ReadHprofIterator::id_size` or
+    /// `MemoryHprofIterator::id_size`.
+    pub fn from_records<I, S>(records: I, id_size: u32) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = GraphBuilder::new(id_size);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        builder.finish()
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &ClassRegistry {
+        &self.registry
+    }
+
+    /// Size model of shallow sizes.
+    #[inline]
+    pub fn size_model(&self) -> &SizeModel {
+        &self.size_model
+    }
+
+    #[inline]
+    pub fn node_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Node of the object, if it is present in the dump.
+    pub fn index_of(&self, id: Id) -> Option<NodeIndex> {
+        let key = u64::from(id);
+        self.ids
+            .binary_search_by_key(&key, |id| u64::from(*id))
+            .ok()
+            .map(|idx| idx as NodeIndex)
+    }
+
+    #[inline]
+    pub fn id(&self, node: NodeIndex) -> Id {
+        self.ids[node as usize]
+    }
+
+    #[inline]
+    pub fn kind(&self, node: NodeIndex) -> NodeKind {
+        self.kinds[node as usize]
+    }
+
+    /// Class node of an instance, or the array class of an object
+    /// array.  `None` for classes, primitive arrays and classes
+    /// missing from the dump.
+    pub fn class_of(&self, node: NodeIndex) -> Option<NodeIndex> {
+        match self.classes[node as usize] {
+            NO_NODE => None,
+            class => Some(class),
+        }
+    }
+
+    /// Key for grouping nodes by type: instances of the same class,
+    /// object arrays of the same class, primitive arrays of the same
+    /// element type, and all classes have equal keys.
+    #[inline]
+    pub fn type_key(&self, node: NodeIndex) -> (NodeKind, NodeIndex) {
+        (self.kinds[node as usize], self.classes[node as usize])
+    }
+
+    /// Shallow size in bytes: header and field data of instances,
+    /// header and elements of arrays, or header and static fields of
+    /// classes, aligned as the size model says.
+    #[inline]
+    pub fn shallow_size(&self, node: NodeIndex) -> u64 {
+        self.shallow_sizes[node as usize]
+    }
+
+    pub fn total_shallow_size(&self) -> u64 {
+        self.shallow_sizes.iter().sum()
+    }
+
+    /// Referenced nodes.  A node may occur several times if the object
+    /// references it from several fields.
+    #[inline]
+    pub fn outgoing(&self, node: NodeIndex) -> &[NodeIndex] {
+        &self.edges[self.edge_range(node)]
+    }
+
+    /// Referenced nodes with labels.
+    pub fn outgoing_labeled(
+        &self,
+        node: NodeIndex,
+    ) -> impl Iterator<Item = (NodeIndex, EdgeLabel<'_>)> + '_ {
+        let range = self.edge_range(node);
+        self.edges[range.clone()]
+            .iter()
+            .zip(self.edge_slots[range].iter())
+            .map(move |(target, slot)| (*target, self.slot_label(node, *slot)))
+    }
+
+    /// Label of `pos`-th outgoing edge of the node.
+    pub fn edge_label(&self, node: NodeIndex, pos: usize) -> EdgeLabel<'_> {
+        let range = self.edge_range(node);
+        self.slot_label(node, self.edge_slots[range][pos])
+    }
+
+    /// Labels of all edges from `from` to `to`.
+    pub fn edge_labels_between(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+    ) -> impl Iterator<Item = EdgeLabel<'_>> + '_ {
+        self.outgoing_labeled(from)
+            .filter(move |(target, _)| *target == to)
+            .map(|(_, label)| label)
+    }
+
+    #[inline]
+    fn edge_range(&self, node: NodeIndex) -> std::ops::Range<usize> {
+        let pos = self.record_order[node as usize] as usize;
+        self.edge_offsets[pos] as usize..self.edge_offsets[pos + 1] as usize
+    }
+
+    fn slot_label(&self, node: NodeIndex, slot: u32) -> EdgeLabel<'_> {
+        match (self.kind(node), slot) {
+            (NodeKind::Class, SLOT_SUPER) => EdgeLabel::SuperClass,
+            (NodeKind::Class, SLOT_LOADER) => EdgeLabel::ClassLoader,
+            (NodeKind::Class, slot) => self
+                .registry
+                .class(self.id(node))
+                .and_then(|class_desc| class_desc.static_fields.get(slot as usize))
+                .and_then(|(info, _)| self.registry.field_name(info))
+                .map_or(EdgeLabel::Unknown, EdgeLabel::StaticField),
+            (NodeKind::ObjectArray, idx) => EdgeLabel::ArrayElement(idx),
+            (NodeKind::Instance, slot) => self
+                .class_of(node)
+                .and_then(|class| {
+                    self.registry
+                        .superclasses(self.id(class))
+                        .flat_map(|class_desc| class_desc.instance_fields.iter())
+                        .nth(slot as usize)
+                })
+                .and_then(|info| self.registry.field_name(info))
+                .map_or(EdgeLabel::Unknown, EdgeLabel::Field),
+            (NodeKind::PrimitiveArray(_), _) => EdgeLabel::Unknown,
+        }
+    }
+
+    /// All GC roots, sorted by node.  A node may be a root several
+    /// times with different root kinds.
+    #[inline]
+    pub fn roots(&self) -> &[GcRoot] {
+        &self.roots
+    }
+
+    /// GC roots of the node; empty if it is not a root.
+    pub fn roots_of(&self, node: NodeIndex) -> &[GcRoot] {
+        let start = self.roots.partition_point(|root| root.node < node);
+        let end = self.roots.partition_point(|root| root.node <= node);
+        &self.roots[start..end]
+    }
+
+    #[inline]
+    pub fn is_root(&self, node: NodeIndex) -> bool {
+        !self.roots_of(node).is_empty()
+    }
+
+    /// Java name of the node's type: class name for instances,
+    /// `int[]` for primitive arrays, `java.lang.Class` for classes.
+    pub fn type_name(&self, node: NodeIndex) -> String {
+        match self.kind(node) {
+            NodeKind::Class => "java.lang.Class".to_string(),
+            NodeKind::PrimitiveArray(elem_type) => format!("{}[]", elem_type.java_name()),
+            NodeKind::Instance | NodeKind::ObjectArray => self
+                .class_of(node)
+                .and_then(|class| self.registry.java_class_name(self.id(class)))
+                .unwrap_or_else(|| "<unknown>".to_string()),
+        }
+    }
+
+    /// Name of the class for a class node; `None` for other nodes.
+    pub fn class_name(&self, node: NodeIndex) -> Option<String> {
+        match self.kind(node) {
+            NodeKind::Class => self.registry.java_class_name(self.id(node)),
+            _ => None,
+        }
+    }
+}
+
+/**
+Incremental graph builder.  Use it to build a graph in the same pass
+with other consumers of records; otherwise `HeapGraph::from_records` is
+simpler.
+ */
+#[derive(Debug)]
+pub struct GraphBuilder {
+    id_size: u32,
+    size_model: Option<SizeModel>,
+    registry: ClassRegistry,
+    ids: Vec<Id>,
+    kinds: Vec<NodeKind>,
+    class_ids: Vec<Id>,
+    /// Size of primitive fields or array elements.
+    payload_sizes: Vec<u64>,
+    /// Number of reference fields or array elements.
+    references: Vec<u32>,
+    edge_offsets: Vec<u64>,
+    /// Edge targets in chunks of `TARGET_CHUNK`, so `finish` can free
+    /// them while resolving.
+    targets: Vec<Vec<Id>>,
+    slots: Vec<u32>,
+    roots: Vec<(Id, GcRootKind)>,
+}
+
+impl GraphBuilder {
+    pub fn new(id_size: u32) -> Self {
+        Self {
+            id_size,
+            size_model: None,
+            registry: ClassRegistry::new(),
+            ids: Vec::new(),
+            kinds: Vec::new(),
+            class_ids: Vec::new(),
+            payload_sizes: Vec::new(),
+            references: Vec::new(),
+            edge_offsets: vec![0],
+            targets: Vec::new(),
+            slots: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    /// Use the size model instead of inferring it.
+    pub fn with_size_model(mut self, size_model: SizeModel) -> Self {
+        self.size_model = Some(size_model);
+        self
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &ClassRegistry {
+        &self.registry
+    }
+
+    fn push_edge(&mut self, target: Id, slot: u32) {
+        if u64::from(target) != 0 {
+            match self.targets.last_mut() {
+                Some(chunk) if chunk.len() < TARGET_CHUNK => chunk.push(target),
+                _ => {
+                    let mut chunk = Vec::with_capacity(TARGET_CHUNK);
+                    chunk.push(target);
+                    self.targets.push(chunk);
+                }
+            }
+            self.slots.push(slot);
+        }
+    }
+
+    fn push_node(&mut self, id: Id, kind: NodeKind, class_id: Id, payload: u64, references: u32) {
+        self.ids.push(id);
+        self.kinds.push(kind);
+        self.class_ids.push(class_id);
+        self.payload_sizes.push(payload);
+        self.references.push(references);
+        self.edge_offsets.push(self.slots.len() as u64);
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        self.registry.add_record(record);
+        let dump = match record {
+            Record::Dump(dump) => dump,
+            _ => return,
+        };
+        if let Some(root) = GcRootKind::from_record(dump) {
+            self.roots.push(root);
+            return;
+        }
+        match dump {
+            DumpRecord::ClassDump(class_desc) => {
+                let mut payload = 0;
+                let mut references = 0;
+                for (slot, (info, value)) in class_desc.static_fields.iter().enumerate() {
+                    match value {
+                        FieldValue::Object(target) => {
+                            references += 1;
+                            self.push_edge(*target, slot as u32);
+                        }
+                        _ => payload += info.field_type.byte_size().unwrap_or(0),
+                    }
+                }
+                self.push_edge(class_desc.super_class_object_id, SLOT_SUPER);
+                self.push_edge(class_desc.class_loader_object_id, SLOT_LOADER);
+                self.push_node(
+                    class_desc.class_id,
+                    NodeKind::Class,
+                    Id::from(0u64),
+                    payload,
+                    references,
+                );
+            }
+            DumpRecord::InstanceDump(instance) => {
+                let mut payload = 0;
+                let mut references = 0;
+                for (slot, (info, value)) in instance.values.iter().enumerate() {
+                    match value {
+                        FieldValue::Object(target) => {
+                            references += 1;
+                            self.push_edge(*target, slot as u32);
+                        }
+                        _ => payload += info.field_type.byte_size().unwrap_or(0),
+                    }
+                }
+                self.push_node(
+                    instance.object_id,
+                    NodeKind::Instance,
+                    instance.class_object_id,
+                    payload,
+                    references,
+                );
+            }
+            DumpRecord::ObjectArrayDump(array) => {
+                if let Some(values) = &array.values {
+                    for (idx, target) in values.iter().enumerate() {
+                        self.push_edge(*target, idx as u32);
+                    }
+                }
+                self.push_node(
+                    array.object_id,
+                    NodeKind::ObjectArray,
+                    array.element_class_id,
+                    0,
+                    array.num_elements,
+                );
+            }
+            DumpRecord::PrimitiveArrayDump(array) => {
+                self.push_node(
+                    array.object_id,
+                    NodeKind::PrimitiveArray(array.elem_type),
+                    Id::from(0u64),
+                    u64::from(array.num_elements) * array.elem_type.byte_size().unwrap_or(0),
+                    0,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Fails with `Error::IntegerConversionErrror` if there are more
+    /// objects than `NodeIndex` can address.
+    pub fn finish(mut self) -> Result<HeapGraph, Error> {
+        let count = self.ids.len();
+        // `NO_NODE` is reserved, so the last index must be below it.
+        if count > NO_NODE as usize {
+            return Err(Error::IntegerConversionErrror);
+        }
+        let mut order: Vec<NodeIndex> = (0..count as NodeIndex).collect();
+        order.sort_unstable_by_key(|idx| u64::from(self.ids[*idx as usize]));
+        let ids: Vec<Id> = order.iter().map(|idx| self.ids[*idx as usize]).collect();
+
+        let lookup = |id: Id| -> NodeIndex {
+            let key = u64::from(id);
+            if key == 0 {
+                return NO_NODE;
+            }
+            ids.binary_search_by_key(&key, |id| u64::from(*id))
+                .map_or(NO_NODE, |idx| idx as NodeIndex)
+        };
+
+        // Edges stay in the record order.  Target chunks are freed as
+        // they are resolved, and slots and offsets are compacted in
+        // place as references to missing objects are dropped.
+        let mut targets = mem::take(&mut self.targets).into_iter().flatten();
+        let mut edge_slots = mem::take(&mut self.slots);
+        let mut edge_offsets = mem::take(&mut self.edge_offsets);
+        let mut edges = Vec::with_capacity(edge_slots.len());
+        let mut start = 0;
+        for offset in edge_offsets.iter_mut().skip(1) {
+            let end = *offset as usize;
+            for (slot, target) in (start..end).zip(&mut targets) {
+                let target = lookup(target);
+                if target != NO_NODE {
+                    edge_slots[edges.len()] = edge_slots[slot];
+                    edges.push(target);
+                }
+            }
+            start = end;
+            *offset = edges.len() as u64;
+        }
+        edge_slots.truncate(edges.len());
+        edge_slots.shrink_to_fit();
+
+        let kinds = order.iter().map(|idx| self.kinds[*idx as usize]).collect();
+        let classes = order
+            .iter()
+            .map(|idx| lookup(self.class_ids[*idx as usize]))
+            .collect();
+        let size_model = self
+            .size_model
+            .unwrap_or_else(|| SizeModel::infer(self.id_size, ids.iter().copied()));
+        let shallow_sizes = order
+            .iter()
+            .map(|idx| {
+                let idx = *idx as usize;
+                let payload = self.payload_sizes[idx];
+                let references = u64::from(self.references[idx]);
+                match self.kinds[idx] {
+                    NodeKind::Class | NodeKind::Instance => {
+                        size_model.instance_size(payload, references)
+                    }
+                    NodeKind::ObjectArray => size_model.object_array_size(references),
+                    NodeKind::PrimitiveArray(_) => {
+                        size_model.align(u64::from(size_model.array_header_size) + payload)
+                    }
+                }
+            })
+            .collect();
+        let mut roots: Vec<GcRoot> = self
+            .roots
+            .iter()
+            .map(|(id, kind)| GcRoot {
+                node: lookup(*id),
+                kind: *kind,
+            })
+            .filter(|root| root.node != NO_NODE)
+            .collect();
+        roots.sort_by_key(|root| root.node);
+        let mut registry = self.registry;
+        registry.retain_class_strings();
+
+        Ok(HeapGraph {
+            registry,
+            size_model,
+            ids,
+            kinds,
+            classes,
+            shallow_sizes,
+            record_order: order,
+            edge_offsets,
+            edges,
+            edge_slots,
+            roots,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    /// Sample heap: a root `Holder` with an array of two `Item`s, one of
+    /// which references a `Leaf`.
+    pub(crate) fn sample_dump() -> (TestDump, Vec<Id>) {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let holder = dump.class_with_statics(
+            "com/acme/Holder",
+            Some(object),
+            &[("items", FieldType::Object), ("count", FieldType::Int)],
+            &[("INSTANCE", FieldValue::Object(id(0)))],
+        );
+        let item = dump.class(
+            "com/acme/Item",
+            Some(object),
+            &[("next", FieldType::Object), ("payload", FieldType::Object)],
+        );
+        let items_class = dump.class("[Lcom/acme/Item;", None, &[]);
+        let leaf = dump.instance(object, &[]);
+        let payload = dump.primitive_array(ArrayValue::Int(vec![1, 2, 3]));
+        let first = dump.instance(
+            item,
+            &[FieldValue::Object(leaf), FieldValue::Object(payload)],
+        );
+        let second = dump.instance(
+            item,
+            &[FieldValue::Object(id(0)), FieldValue::Object(id(0))],
+        );
+        let array = dump.object_array(items_class, &[first, id(0), second]);
+        let holder_id = dump.instance(holder, &[FieldValue::Object(array), FieldValue::Int(2)]);
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: holder_id,
+            jni_global_ref: id(1),
+        });
+        dump.dump(DumpRecord::RootStickyClass { obj_id: holder });
+        (
+            dump,
+            vec![holder_id, array, first, second, leaf, payload, holder],
+        )
+    }
+
+    #[test]
+    fn test_graph_edges() {
+        let (dump, ids) = sample_dump();
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let node = |idx: usize| graph.index_of(ids[idx]).unwrap();
+
+        assert_eq!(graph.node_count(), 10);
+        assert_eq!(graph.outgoing(node(0)), &[node(1)]);
+        assert_eq!(
+            graph
+                .edge_labels_between(node(0), node(1))
+                .collect::<Vec<_>>(),
+            vec![EdgeLabel::Field("items")]
+        );
+        assert_eq!(
+            graph.outgoing_labeled(node(1)).collect::<Vec<_>>(),
+            vec![
+                (node(2), EdgeLabel::ArrayElement(0)),
+                (node(3), EdgeLabel::ArrayElement(2))
+            ]
+        );
+        assert_eq!(graph.outgoing(node(2)), &[node(4), node(5)]);
+        assert_eq!(graph.edge_label(node(2), 1), EdgeLabel::Field("payload"));
+        assert_eq!(graph.outgoing(node(3)), &[]);
+        // Holder class references java.lang.Object as superclass.
+        assert_eq!(
+            graph
+                .outgoing_labeled(node(6))
+                .map(|(_, label)| label)
+                .collect::<Vec<_>>(),
+            vec![EdgeLabel::SuperClass]
+        );
+
+        assert_eq!(graph.type_name(node(0)), "com.acme.Holder");
+        assert_eq!(graph.type_name(node(5)), "int[]");
+        assert_eq!(graph.size_model(), &SizeModel::HOTSPOT_64_COMPRESSED);
+        // Header, three compressed references, padding.
+        assert_eq!(graph.shallow_size(node(1)), 32);
+        assert_eq!(graph.shallow_size(node(2)), 24);
+        assert_eq!(graph.shallow_size(node(5)), 32);
+        assert_eq!(graph.kind(node(6)), NodeKind::Class);
+
+        assert_eq!(graph.roots().len(), 2);
+        assert_eq!(graph.roots_of(node(0))[0].kind, GcRootKind::JniGlobal);
+        assert!(!graph.is_root(node(1)));
+    }
+
+    #[test]
+    fn test_graph_drops_missing_targets_and_strings() {
+        let mut dump = TestDump::new();
+        let node_class = dump.class(
+            "com/acme/Node",
+            None,
+            &[("left", FieldType::Object), ("right", FieldType::Object)],
+        );
+        let method = dump.string("run");
+        let leaf = dump.instance(
+            node_class,
+            &[FieldValue::Object(id(0)), FieldValue::Object(id(0))],
+        );
+        let parent = dump.instance(
+            node_class,
+            &[FieldValue::Object(id(999)), FieldValue::Object(leaf)],
+        );
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let parent = graph.index_of(parent).unwrap();
+        let leaf = graph.index_of(leaf).unwrap();
+
+        assert_eq!(graph.edge_count(), 1);
+        assert_eq!(
+            graph.outgoing_labeled(parent).collect::<Vec<_>>(),
+            vec![(leaf, EdgeLabel::Field("right"))]
+        );
+        assert_eq!(graph.outgoing(leaf), &[]);
+        assert_eq!(graph.type_name(parent), "com.acme.Node");
+        assert_eq!(graph.registry().string(method), None);
+    }
+}
@@ -0,0 +1,460 @@
+#![forbid(unsafe_code)]
+
+/*!
+Export of a small part of the object graph as a picture: Graphviz DOT
+or GraphML.
+
+A `Subgraph` is either the neighbourhood of some objects, limited by
+depth and node count, or the union of paths to GC roots found by
+`PathFinder`.  Nodes are labelled by class and id, shallow size,
+retained size if the dominator tree is given, and a short value
+preview if the object store is given; GC roots are marked.  Edges are
+labelled by field name or array index.
+
+```no_run
+# use hprof_dump_parser::graph::HeapGraph;
+# use hprof_dump_parser::graph_export::{SubgraphBuilder, SubgraphWriter};
+# fn export(graph: &HeapGraph, node: u32) -> std::io::Result<()> {
+let subgraph = SubgraphBuilder::new(graph)
+    .with_max_depth(2)
+    .with_max_nodes(50)
+    .build(&[node]);
+SubgraphWriter::new(graph, &subgraph).write_dot(&mut std::io::stdout())?;
+# Ok(())
+# }
+```
+ */
+
+use crate::dominator::DominatorTree;
+use crate::graph::{HeapGraph, NodeIndex};
+use crate::objects::ObjectStore;
+use crate::paths::RootPath;
+use crate::value::{object_id_label, render_object};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::io::{self, Write};
+
+/// Value previews longer than this are truncated.
+const MAX_PREVIEW: usize = 40;
+
+/// A labelled reference between subgraph nodes.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct SubgraphEdge {
+    pub from: NodeIndex,
+    pub to: NodeIndex,
+    /// Field name, `[index]` or another `EdgeLabel`.
+    pub label: String,
+}
+
+/// Selected nodes and references between them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Subgraph {
+    /// Sorted.
+    pub nodes: Vec<NodeIndex>,
+    /// Sorted, without duplicates.
+    pub edges: Vec<SubgraphEdge>,
+    /// Whether the node limit cut the subgraph.
+    pub truncated: bool,
+}
+
+impl Subgraph {
+    /// Union of paths to GC roots; only the references the paths
+    /// follow are included.
+    pub fn from_paths(paths: &[RootPath]) -> Self {
+        let mut nodes = BTreeSet::new();
+        let mut edges = BTreeSet::new();
+        for path in paths {
+            for (step, next) in path.steps.iter().zip(path.steps.iter().skip(1)) {
+                edges.insert(SubgraphEdge {
+                    from: step.node,
+                    to: next.node,
+                    label: step.via.clone().unwrap_or_default(),
+                });
+            }
+            nodes.extend(path.steps.iter().map(|step| step.node));
+        }
+        Self {
+            nodes: nodes.into_iter().collect(),
+            edges: edges.into_iter().collect(),
+            truncated: false,
+        }
+    }
+}
+
+/// Builder of the neighbourhood of objects, following outgoing
+/// references breadth first.
+#[derive(Debug)]
+pub struct SubgraphBuilder<'a> {
+    graph: &'a HeapGraph,
+    max_depth: u32,
+    max_nodes: usize,
+}
+
+impl<'a> SubgraphBuilder<'a> {
+    /// Builder with depth 3 and 100 nodes limits.
+    pub fn new(graph: &'a HeapGraph) -> Self {
+        Self {
+            graph,
+            max_depth: 3,
+            max_nodes: 100,
+        }
+    }
+
+    /// Maximal number of references from the start nodes.
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    /// Subgraph reachable from the nodes (see `HeapGraph::index_of`),
+    /// with all references between its nodes.
+    pub fn build(&self, start: &[NodeIndex]) -> Subgraph {
+        let graph = self.graph;
+        let mut nodes: HashSet<NodeIndex> = HashSet::new();
+        let mut queue: VecDeque<(NodeIndex, u32)> = VecDeque::new();
+        let mut truncated = false;
+        for node in start {
+            if nodes.len() >= self.max_nodes {
+                truncated = true;
+                break;
+            }
+            if nodes.insert(*node) {
+                queue.push_back((*node, 0));
+            }
+        }
+        'search: while let Some((node, depth)) = queue.pop_front() {
+            if depth == self.max_depth {
+                continue;
+            }
+            for target in graph.outgoing(node) {
+                if nodes.contains(target) {
+                    continue;
+                }
+                if nodes.len() >= self.max_nodes {
+                    truncated = true;
+                    break 'search;
+                }
+                nodes.insert(*target);
+                queue.push_back((*target, depth + 1));
+            }
+        }
+
+        let mut edges = BTreeSet::new();
+        for node in &nodes {
+            for (target, label) in graph.outgoing_labeled(*node) {
+                if nodes.contains(&target) {
+                    edges.insert(SubgraphEdge {
+                        from: *node,
+                        to: target,
+                        label: label.to_string(),
+                    });
+                }
+            }
+        }
+        let mut nodes: Vec<NodeIndex> = nodes.into_iter().collect();
+        nodes.sort_unstable();
+        Subgraph {
+            nodes,
+            edges: edges.into_iter().collect(),
+            truncated,
+        }
+    }
+}
+
+/// Node attributes shown in the exported graph.
+struct NodeInfo {
+    /// `class@0x...`.
+    name: String,
+    shallow_size: u64,
+    retained_size: Option<u64>,
+    preview: Option<String>,
+    /// Root kind names, empty if the node is not a GC root.
+    roots: Vec<&'static str>,
+}
+
+impl NodeInfo {
+    /// Multi-line label.
+    fn label(&self) -> String {
+        let mut label = self.name.clone();
+        label.push_str(&format!("\nshallow {}", self.shallow_size));
+        if let Some(retained_size) = self.retained_size {
+            label.push_str(&format!(", retained {}", retained_size));
+        }
+        if let Some(preview) = &self.preview {
+            label.push('\n');
+            label.push_str(preview);
+        }
+        if !self.roots.is_empty() {
+            label.push_str(&format!("\nGC root: {}", self.roots.join(", ")));
+        }
+        label
+    }
+}
+
+/// Writer of a subgraph in DOT or GraphML format.
+#[derive(Debug)]
+pub struct SubgraphWriter<'a> {
+    graph: &'a HeapGraph,
+    subgraph: &'a Subgraph,
+    tree: Option<&'a DominatorTree>,
+    store: Option<&'a ObjectStore>,
+}
+
+impl<'a> SubgraphWriter<'a> {
+    pub fn new(graph: &'a HeapGraph, subgraph: &'a Subgraph) -> Self {
+        Self {
+            graph,
+            subgraph,
+            tree: None,
+            store: None,
+        }
+    }
+
+    /// Show retained sizes.
+    pub fn with_dominator_tree(mut self, tree: &'a DominatorTree) -> Self {
+        self.tree = Some(tree);
+        self
+    }
+
+    /// Show values of strings, boxed primitives and other well-known
+    /// classes, see `value::render_object`.
+    pub fn with_object_store(mut self, store: &'a ObjectStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    fn node_info(&self, node: NodeIndex) -> NodeInfo {
+        let graph = self.graph;
+        let id = graph.id(node);
+        let mut roots: Vec<&'static str> = graph
+            .roots_of(node)
+            .iter()
+            .map(|root| root.kind.name())
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        NodeInfo {
+            name: format!("{}@{}", graph.type_name(node), object_id_label(id)),
+            shallow_size: graph.shallow_size(node),
+            retained_size: self.tree.map(|tree| tree.retained_size(node)),
+            preview: self
+                .store
+                .and_then(|store| render_object(store, id))
+                .map(|preview| truncate(&preview)),
+            roots,
+        }
+    }
+
+    /// Write a `digraph`; GC roots are drawn with a double border.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph heap {{")?;
+        writeln!(out, "  node [shape=box, fontname=\"monospace\"];")?;
+        for node in &self.subgraph.nodes {
+            let info = self.node_info(*node);
+            write!(out, "  n{} [label=\"{}\"", node, dot_escape(&info.label()))?;
+            if !info.roots.is_empty() {
+                write!(out, ", peripheries=2")?;
+            }
+            writeln!(out, "];")?;
+        }
+        for edge in &self.subgraph.edges {
+            writeln!(
+                out,
+                "  n{} -> n{} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                dot_escape(&edge.label)
+            )?;
+        }
+        if self.subgraph.truncated {
+            writeln!(out, "  truncated [shape=plaintext, label=\"(truncated)\"];")?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Write a GraphML document with node attributes as data keys.
+    pub fn write_graphml<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            out,
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+        )?;
+        for (key, domain, attr_type) in &[
+            ("class", "node", "string"),
+            ("object_id", "node", "string"),
+            ("shallow_size", "node", "long"),
+            ("retained_size", "node", "long"),
+            ("preview", "node", "string"),
+            ("gc_roots", "node", "string"),
+            ("label", "edge", "string"),
+        ] {
+            writeln!(
+                out,
+                "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"{2}\"/>",
+                key, domain, attr_type
+            )?;
+        }
+        writeln!(out, "  <graph id=\"heap\" edgedefault=\"directed\">")?;
+        for node in &self.subgraph.nodes {
+            let info = self.node_info(*node);
+            writeln!(out, "    <node id=\"n{}\">", node)?;
+            let data = |out: &mut W, key: &str, value: &str| {
+                writeln!(
+                    out,
+                    "      <data key=\"{}\">{}</data>",
+                    key,
+                    xml_escape(value)
+                )
+            };
+            data(out, "class", &self.graph.type_name(*node))?;
+            data(out, "object_id", &object_id_label(self.graph.id(*node)))?;
+            data(out, "shallow_size", &info.shallow_size.to_string())?;
+            if let Some(retained_size) = info.retained_size {
+                data(out, "retained_size", &retained_size.to_string())?;
+            }
+            if let Some(preview) = &info.preview {
+                data(out, "preview", preview)?;
+            }
+            if !info.roots.is_empty() {
+                data(out, "gc_roots", &info.roots.join(", "))?;
+            }
+            writeln!(out, "    </node>")?;
+        }
+        for edge in &self.subgraph.edges {
+            writeln!(
+                out,
+                "    <edge source=\"n{}\" target=\"n{}\"><data key=\"label\">{}</data></edge>",
+                edge.from,
+                edge.to,
+                xml_escape(&edge.label)
+            )?;
+        }
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")
+    }
+}
+
+fn truncate(s: &str) -> String {
+    match s.char_indices().nth(MAX_PREVIEW) {
+        Some((idx, _)) => format!("{}...", &s[..idx]),
+        None => s.to_string(),
+    }
+}
+
+/// Escape a DOT quoted string; newlines become `\n` line breaks.
+fn dot_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            // Not allowed in XML 1.0, even as character references.
+            c if (c as u32) < 0x20 && c != '\n' && c != '\t' => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::inbound::InboundIndex;
+    use crate::paths::PathFinder;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_export_subgraph() {
+        let mut dump = TestDump::new();
+        let node_class = dump.class(
+            "com/acme/Node",
+            None,
+            &[("next", FieldType::Object), ("name", FieldType::Object)],
+        );
+        let name = dump.java_string("tail <1>");
+        let tail = dump.instance(
+            node_class,
+            &[FieldValue::Object(id(0)), FieldValue::Object(name)],
+        );
+        let middle = dump.instance(
+            node_class,
+            &[FieldValue::Object(tail), FieldValue::Object(id(0))],
+        );
+        let head = dump.instance(
+            node_class,
+            &[FieldValue::Object(middle), FieldValue::Object(id(0))],
+        );
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: head,
+            jni_global_ref: id(1),
+        });
+        dump.dump(DumpRecord::RootMonitorUsed { obj_id: head });
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: head,
+            jni_global_ref: id(2),
+        });
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let node = |object_id| graph.index_of(object_id).unwrap();
+
+        let subgraph = SubgraphBuilder::new(&graph)
+            .with_max_depth(1)
+            .build(&[node(head)]);
+        assert_eq!(subgraph.nodes, vec![node(middle), node(head)]);
+        assert!(!subgraph.truncated);
+        let truncated = SubgraphBuilder::new(&graph)
+            .with_max_nodes(2)
+            .build(&[node(head)]);
+        assert!(truncated.truncated);
+        assert_eq!(truncated.nodes.len(), 2);
+
+        let inbound = InboundIndex::build(&graph);
+        let paths = PathFinder::new(&graph, &inbound).find(node(name)).unwrap();
+        let subgraph = Subgraph::from_paths(&paths);
+        assert_eq!(subgraph.nodes.len(), 4);
+        let labels: Vec<&str> = subgraph.edges.iter().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, vec!["name", "next", "next"]);
+
+        let store = dump.store();
+        let tree = DominatorTree::compute(&graph);
+        let writer = SubgraphWriter::new(&graph, &subgraph)
+            .with_dominator_tree(&tree)
+            .with_object_store(&store);
+        let mut out = Vec::new();
+        writer.write_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph heap {\n"));
+        assert!(dot.contains(&format!(
+            "  n{} -> n{} [label=\"name\"];\n",
+            node(tail),
+            node(name)
+        )));
+        assert!(dot.contains("\\n\\\"tail <1>\\\""));
+        assert!(dot.contains("GC root: RootJniGlobal, RootMonitorUsed\", peripheries=2];"));
+
+        let mut out = Vec::new();
+        writer.write_graphml(&mut out).unwrap();
+        let graphml = String::from_utf8(out).unwrap();
+        assert!(graphml.contains("<data key=\"preview\">&quot;tail &lt;1&gt;&quot;</data>"));
+        assert!(graphml.contains("<data key=\"class\">com.acme.Node</data>"));
+    }
+}
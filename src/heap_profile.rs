@@ -0,0 +1,286 @@
+#![forbid(unsafe_code)]
+
+/*!
+Heap dump as a pprof heap profile.
+
+Objects are grouped by type and allocation stack trace.  Each group is
+a sample with the `inuse_objects` and `inuse_space` values, and with
+`retained_space` if the dominator tree is given.  The sample locations
+are the allocation stack with the type as the leaf frame; objects
+without allocation trace get a synthetic stack of their package and its
+parents, so a profile viewer shows a package tree:
+
+```text
+com -> com.acme -> com.acme.Item
+```
+ */
+
+use crate::decl::*;
+use crate::dominator::DominatorTree;
+use crate::graph::{HeapGraph, NodeIndex};
+use crate::pprof::ProfileBuilder;
+use crate::symbol::{StackFrame, Symbolizer};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Objects of a type allocated at a stack trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapSample {
+    pub type_name: String,
+    /// Zero if the objects have no allocation trace.
+    pub stack_trace_serial: SerialNumber,
+    /// Top frame first; `None` for missing frame records.
+    pub frames: Vec<Option<StackFrame>>,
+    pub objects: u64,
+    pub shallow_size: u64,
+    /// Zero unless the profile has retained sizes.  Objects dominated
+    /// by another object of the sample are counted once.
+    pub retained_size: u64,
+}
+
+/// Heap profile, see the module documentation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapProfile {
+    /// Biggest shallow size first.
+    pub samples: Vec<HeapSample>,
+    /// Whether retained sizes are computed; only objects reachable
+    /// from GC roots are counted then.
+    pub has_retained_sizes: bool,
+}
+
+/**
+Streaming collector of allocation traces for `HeapProfile`: feed it the
+records of the dump the graph is built from, then `finish` with the
+graph.
+ */
+#[derive(Debug, Default)]
+pub struct HeapProfileBuilder {
+    symbolizer: Symbolizer,
+    /// Stack trace serials of objects, if not zero.
+    traces: HashMap<Id, SerialNumber>,
+}
+
+impl HeapProfileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        let (object_id, serial) = match record {
+            Record::Dump(DumpRecord::InstanceDump(instance)) => {
+                (instance.object_id, instance.stack_trace_serial)
+            }
+            Record::Dump(DumpRecord::ObjectArrayDump(array)) => {
+                (array.object_id, array.stack_trace_serial)
+            }
+            Record::Dump(DumpRecord::PrimitiveArrayDump(array)) => {
+                (array.object_id, array.stack_trace_serial)
+            }
+            record => {
+                self.symbolizer.add_record(record);
+                return;
+            }
+        };
+        if serial != 0 {
+            self.traces.insert(object_id, serial);
+        }
+    }
+
+    /// Group the graph nodes; with the dominator tree, only reachable
+    /// nodes are counted and retained sizes are computed.
+    pub fn finish(self, graph: &HeapGraph, tree: Option<&DominatorTree>) -> HeapProfile {
+        let traces = &self.traces;
+        let key_of = |node: NodeIndex| {
+            (
+                traces.get(&graph.id(node)).copied().unwrap_or(0),
+                graph.type_key(node),
+            )
+        };
+        let groups: Vec<(NodeIndex, SerialNumber, u64, u64, u64)> = match tree {
+            Some(tree) => tree
+                .retained_by(graph, key_of)
+                .into_iter()
+                .map(|((serial, _), (node, stats))| {
+                    (
+                        node,
+                        serial,
+                        stats.objects,
+                        stats.shallow_size,
+                        stats.retained_size,
+                    )
+                })
+                .collect(),
+            None => {
+                let mut groups: HashMap<_, (NodeIndex, u64, u64)> = HashMap::new();
+                for node in 0..graph.node_count() as NodeIndex {
+                    let group = groups.entry(key_of(node)).or_insert((node, 0, 0));
+                    group.1 += 1;
+                    group.2 += graph.shallow_size(node);
+                }
+                groups
+                    .into_iter()
+                    .map(|((serial, _), (node, objects, shallow_size))| {
+                        (node, serial, objects, shallow_size, 0)
+                    })
+                    .collect()
+            }
+        };
+
+        let mut samples: Vec<HeapSample> = groups
+            .into_iter()
+            .map(
+                |(node, stack_trace_serial, objects, shallow_size, retained_size)| HeapSample {
+                    type_name: graph.type_name(node),
+                    stack_trace_serial,
+                    frames: self
+                        .symbolizer
+                        .stack_trace(stack_trace_serial)
+                        .unwrap_or_default(),
+                    objects,
+                    shallow_size,
+                    retained_size,
+                },
+            )
+            .collect();
+        samples.sort_by(|a, b| {
+            b.shallow_size
+                .cmp(&a.shallow_size)
+                .then_with(|| a.type_name.cmp(&b.type_name))
+                .then_with(|| a.stack_trace_serial.cmp(&b.stack_trace_serial))
+        });
+        HeapProfile {
+            samples,
+            has_retained_sizes: tree.is_some(),
+        }
+    }
+}
+
+impl HeapProfile {
+    /// Profile of the records the graph is built from.
+    pub fn from_records<I, S>(
+        records: I,
+        graph: &HeapGraph,
+        tree: Option<&DominatorTree>,
+    ) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = HeapProfileBuilder::new();
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish(graph, tree))
+    }
+
+    /// Write a pprof profile; `inuse_space` is the default sample
+    /// type.  `time_nanos` is the dump time, zero if unknown.
+    pub fn write_pprof<W: Write>(&self, out: &mut W, time_nanos: i64) -> io::Result<()> {
+        let mut sample_types = vec![("inuse_objects", "count"), ("inuse_space", "bytes")];
+        if self.has_retained_sizes {
+            sample_types.push(("retained_space", "bytes"));
+        }
+        let mut builder = ProfileBuilder::new(&sample_types)
+            .with_period("space", "bytes", 1)
+            .with_default_sample_type("inuse_space")
+            .with_time_nanos(time_nanos);
+        for sample in &self.samples {
+            let mut locations = vec![builder.location(&sample.type_name, "", None, 0)];
+            if sample.frames.is_empty() {
+                locations.extend(
+                    packages(&sample.type_name)
+                        .map(|package| builder.location(package, "", None, 0)),
+                );
+            } else {
+                locations.extend(
+                    sample
+                        .frames
+                        .iter()
+                        .map(|frame| builder.frame_location(frame.as_ref())),
+                );
+            }
+            let mut values = vec![sample.objects as i64, sample.shallow_size as i64];
+            if self.has_retained_sizes {
+                values.push(sample.retained_size as i64);
+            }
+            builder.add_sample(locations, values);
+        }
+        builder.write(out)
+    }
+}
+
+/// Packages of a Java type name, innermost first: `com.acme`, `com`
+/// for `com.acme.Item[]`; nothing for `int[]`.
+fn packages(type_name: &str) -> impl Iterator<Item = &str> {
+    let mut package = type_name;
+    std::iter::from_fn(move || {
+        package = &package[..package.rfind('.')?];
+        Some(package)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pprof::tests::strings;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_heap_profile() {
+        let mut dump = TestDump::new();
+        let item = dump.class("com/acme/Item", None, &[("value", FieldType::Int)]);
+        let create = dump.stack_frame(item, "create", "()Lcom/acme/Item;", 12);
+        dump.stack_trace(5, &[create]);
+        for serial in &[5, 5, 0] {
+            let object_id = dump.instance(item, &[FieldValue::Int(0)]);
+            if let Some(Record::Dump(DumpRecord::InstanceDump(instance))) = dump.records.last_mut()
+            {
+                instance.stack_trace_serial = *serial;
+            }
+            dump.dump(DumpRecord::RootJniGlobal {
+                obj_id: object_id,
+                jni_global_ref: id(1),
+            });
+        }
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let tree = DominatorTree::compute(&graph);
+        let profile = HeapProfile::from_records(dump.records(), &graph, Some(&tree)).unwrap();
+        let samples: Vec<(&str, SerialNumber, u64, u64, u64)> = profile
+            .samples
+            .iter()
+            .map(|s| {
+                (
+                    s.type_name.as_str(),
+                    s.stack_trace_serial,
+                    s.objects,
+                    s.shallow_size,
+                    s.retained_size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                ("com.acme.Item", 5, 2, 32, 32),
+                ("com.acme.Item", 0, 1, 16, 16)
+            ]
+        );
+        assert_eq!(
+            packages("com.acme.Item[]").collect::<Vec<_>>(),
+            vec!["com.acme", "com"]
+        );
+
+        let mut out = Vec::new();
+        profile.write_pprof(&mut out, 0).unwrap();
+        let strings = strings(&out);
+        assert!(strings.contains(&"retained_space"));
+        assert!(strings.contains(&"com.acme.Item.create"));
+        assert!(strings.contains(&"com.acme"));
+
+        let profile = HeapProfile::from_records(dump.records(), &graph, None).unwrap();
+        assert!(!profile.has_retained_sizes);
+        assert_eq!(profile.samples.iter().map(|s| s.objects).sum::<u64>(), 4);
+    }
+}
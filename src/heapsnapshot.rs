@@ -0,0 +1,367 @@
+#![forbid(unsafe_code)]
+
+/*!
+Conversion to the V8 heap snapshot format (`.heapsnapshot`), which the
+memory panel of Chrome DevTools loads.
+
+The snapshot is JSON with flat `nodes` and `edges` number arrays
+described by `snapshot.meta`, and a `strings` table for node and edge
+names.  Java objects are mapped to V8 node types this way:
+
+* instances are `object` nodes named by their class, the viewer groups
+  them by name like constructors;
+* `java.lang.String` instances are `string` nodes named by their value
+  when an object store is given;
+* object arrays are `array` nodes, primitive arrays are `native` nodes
+  (raw memory without references);
+* classes are `closure` nodes named by the class, the Java counterpart
+  of constructor functions.
+
+Fields are `property` edges, array elements are `element` edges, and
+`referent` fields of `java.lang.ref.Reference` subclasses are `weak`
+edges.  The synthetic root node references one `synthetic` node per
+GC root kind, e.g. `(RootJavaFrame)`, which references the roots.
+
+DevTools keeps node fields as 32-bit integers, so node ids are derived
+from node positions rather than object ids, and sizes above 4 GiB are
+clamped.
+ */
+
+use crate::graph::{EdgeLabel, HeapGraph, NodeIndex, NodeKind, ReferenceKind};
+use crate::json::write_string;
+use crate::objects::ObjectStore;
+use crate::strings::java_string;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+const NODE_TYPES: [&str; 14] = [
+    "hidden",
+    "array",
+    "string",
+    "object",
+    "code",
+    "closure",
+    "regexp",
+    "number",
+    "native",
+    "synthetic",
+    "concatenated string",
+    "sliced string",
+    "symbol",
+    "bigint",
+];
+const NODE_ARRAY: u32 = 1;
+const NODE_STRING: u32 = 2;
+const NODE_OBJECT: u32 = 3;
+const NODE_CLOSURE: u32 = 5;
+const NODE_NATIVE: u32 = 8;
+const NODE_SYNTHETIC: u32 = 9;
+
+const EDGE_TYPES: [&str; 7] = [
+    "context", "element", "property", "internal", "hidden", "shortcut", "weak",
+];
+const EDGE_ELEMENT: u32 = 1;
+const EDGE_PROPERTY: u32 = 2;
+const EDGE_INTERNAL: u32 = 3;
+const EDGE_HIDDEN: u32 = 4;
+const EDGE_WEAK: u32 = 6;
+
+/// Number of `node_fields`.
+const NODE_FIELDS: u64 = 6;
+
+/// String table of the snapshot.
+#[derive(Default)]
+struct Strings {
+    strings: Vec<String>,
+    index: HashMap<String, u32>,
+}
+
+impl Strings {
+    fn get(&mut self, s: &str) -> u32 {
+        if let Some(idx) = self.index.get(s) {
+            return *idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.index.insert(s.to_string(), idx);
+        idx
+    }
+}
+
+/**
+Heap snapshot writer.
+
+```no_run
+# use hprof_dump_parser::graph::HeapGraph;
+# use hprof_dump_parser::heapsnapshot::HeapSnapshotWriter;
+# fn convert(graph: &HeapGraph) -> std::io::Result<()> {
+let mut out = std::io::BufWriter::new(std::fs::File::create("dump.heapsnapshot")?);
+HeapSnapshotWriter::new(graph).write(&mut out)?;
+# Ok(())
+# }
+```
+ */
+#[derive(Debug)]
+pub struct HeapSnapshotWriter<'a> {
+    graph: &'a HeapGraph,
+    store: Option<&'a ObjectStore>,
+}
+
+impl<'a> HeapSnapshotWriter<'a> {
+    pub fn new(graph: &'a HeapGraph) -> Self {
+        Self { graph, store: None }
+    }
+
+    /// Name string nodes by their values.
+    pub fn with_object_store(mut self, store: &'a ObjectStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let graph = self.graph;
+        let mut root_groups: BTreeMap<&'static str, Vec<NodeIndex>> = BTreeMap::new();
+        for root in graph.roots() {
+            root_groups
+                .entry(root.kind.name())
+                .or_default()
+                .push(root.node);
+        }
+        for nodes in root_groups.values_mut() {
+            nodes.sort_unstable();
+            nodes.dedup();
+        }
+        // The root node and root groups go before the graph nodes.
+        let first_node = 1 + root_groups.len() as u64;
+        let node_count = first_node + graph.node_count() as u64;
+        let edge_count = root_groups.len()
+            + root_groups.values().map(Vec::len).sum::<usize>()
+            + (0..graph.node_count() as NodeIndex)
+                .map(|node| graph.outgoing(node).len())
+                .sum::<usize>();
+
+        out.write_all(b"{\"snapshot\":{\"meta\":{")?;
+        out.write_all(
+            b"\"node_fields\":[\"type\",\"name\",\"id\",\"self_size\",\"edge_count\",\"trace_node_id\"],",
+        )?;
+        out.write_all(b"\"node_types\":[[")?;
+        write_names(out, &NODE_TYPES)?;
+        out.write_all(b"],\"string\",\"number\",\"number\",\"number\",\"number\"],")?;
+        out.write_all(b"\"edge_fields\":[\"type\",\"name_or_index\",\"to_node\"],")?;
+        out.write_all(b"\"edge_types\":[[")?;
+        write_names(out, &EDGE_TYPES)?;
+        out.write_all(b"],\"string_or_number\",\"node\"],")?;
+        out.write_all(
+            b"\"trace_function_info_fields\":[\"function_id\",\"name\",\"script_name\",\"script_id\",\"line\",\"column\"],\
+              \"trace_node_fields\":[\"id\",\"function_info_index\",\"count\",\"size\",\"children\"],\
+              \"sample_fields\":[\"timestamp_us\",\"last_assigned_id\"],\
+              \"location_fields\":[\"object_index\",\"script_id\",\"line\",\"column\"]},",
+        )?;
+        writeln!(
+            out,
+            "\"node_count\":{},\"edge_count\":{},\"trace_function_count\":0}},",
+            node_count, edge_count
+        )?;
+
+        let mut strings = Strings::default();
+        let mut string_classes: HashMap<NodeIndex, bool> = HashMap::new();
+        out.write_all(b"\"nodes\":[")?;
+        write_node(
+            out,
+            0,
+            NODE_SYNTHETIC,
+            strings.get(""),
+            0,
+            root_groups.len(),
+        )?;
+        for (idx, (kind, nodes)) in root_groups.iter().enumerate() {
+            let name = strings.get(&format!("({})", kind));
+            write_node(out, idx as u64 + 1, NODE_SYNTHETIC, name, 0, nodes.len())?;
+        }
+        for node in 0..graph.node_count() as NodeIndex {
+            let (node_type, name) = match graph.kind(node) {
+                NodeKind::Class => (
+                    NODE_CLOSURE,
+                    graph
+                        .class_name(node)
+                        .unwrap_or_else(|| "<unknown>".to_string()),
+                ),
+                NodeKind::Instance => {
+                    let is_string = graph.class_of(node).is_some_and(|class| {
+                        *string_classes.entry(class).or_insert_with(|| {
+                            graph
+                                .registry()
+                                .has_name(graph.id(class), "java.lang.String")
+                        })
+                    });
+                    if is_string {
+                        let value = self
+                            .store
+                            .and_then(|store| java_string(store, graph.id(node)));
+                        (NODE_STRING, value.unwrap_or_else(|| graph.type_name(node)))
+                    } else {
+                        (NODE_OBJECT, graph.type_name(node))
+                    }
+                }
+                NodeKind::ObjectArray => (NODE_ARRAY, graph.type_name(node)),
+                NodeKind::PrimitiveArray(_) => (NODE_NATIVE, graph.type_name(node)),
+            };
+            write_node(
+                out,
+                first_node + u64::from(node),
+                node_type,
+                strings.get(&name),
+                graph.shallow_size(node),
+                graph.outgoing(node).len(),
+            )?;
+        }
+        out.write_all(b"],\n\"edges\":[")?;
+
+        let to_node = |node: NodeIndex| (first_node + u64::from(node)) * NODE_FIELDS;
+        let mut first = true;
+        let mut write_edge = |out: &mut W, edge_type: u32, name: u64, to: u64| {
+            let sep = if first { "" } else { "," };
+            first = false;
+            writeln!(out, "{}{},{},{}", sep, edge_type, name, to)
+        };
+        for idx in 0..root_groups.len() {
+            write_edge(
+                out,
+                EDGE_ELEMENT,
+                idx as u64,
+                (idx as u64 + 1) * NODE_FIELDS,
+            )?;
+        }
+        for nodes in root_groups.values() {
+            for (idx, node) in nodes.iter().enumerate() {
+                write_edge(out, EDGE_ELEMENT, idx as u64, to_node(*node))?;
+            }
+        }
+        let mut reference_classes: HashMap<NodeIndex, bool> = HashMap::new();
+        for node in 0..graph.node_count() as NodeIndex {
+            let weak = graph.kind(node) == NodeKind::Instance
+                && graph.class_of(node).is_some_and(|class| {
+                    *reference_classes.entry(class).or_insert_with(|| {
+                        ReferenceKind::of_class(graph.registry(), graph.id(class)).is_some()
+                    })
+                });
+            for (pos, (target, label)) in graph.outgoing_labeled(node).enumerate() {
+                let (edge_type, name) = match label {
+                    EdgeLabel::Field("referent") if weak => {
+                        (EDGE_WEAK, strings.get("referent").into())
+                    }
+                    EdgeLabel::Field(name) | EdgeLabel::StaticField(name) => {
+                        (EDGE_PROPERTY, strings.get(name).into())
+                    }
+                    EdgeLabel::ArrayElement(idx) => (EDGE_ELEMENT, idx.into()),
+                    EdgeLabel::SuperClass => (EDGE_INTERNAL, strings.get("super").into()),
+                    EdgeLabel::ClassLoader => (EDGE_INTERNAL, strings.get("classloader").into()),
+                    EdgeLabel::Unknown => (EDGE_HIDDEN, pos as u64),
+                };
+                write_edge(out, edge_type, name, to_node(target))?;
+            }
+        }
+
+        out.write_all(
+            b"],\n\"trace_function_infos\":[],\"trace_tree\":[],\"samples\":[],\"locations\":[],\n\"strings\":[",
+        )?;
+        for (idx, s) in strings.strings.iter().enumerate() {
+            if idx > 0 {
+                out.write_all(b",\n")?;
+            }
+            write_string(out, s)?;
+        }
+        out.write_all(b"]}\n")
+    }
+}
+
+fn write_names<W: Write>(out: &mut W, names: &[&str]) -> io::Result<()> {
+    for (idx, name) in names.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b",")?;
+        }
+        write_string(out, name)?;
+    }
+    Ok(())
+}
+
+/// Write a node with the id derived from its position.
+fn write_node<W: Write>(
+    out: &mut W,
+    pos: u64,
+    node_type: u32,
+    name: u32,
+    self_size: u64,
+    edge_count: usize,
+) -> io::Result<()> {
+    let sep = if pos == 0 { "" } else { "," };
+    writeln!(
+        out,
+        "{}{},{},{},{},{},0",
+        sep,
+        node_type,
+        name,
+        pos * 2 + 1,
+        u32::try_from(self_size).unwrap_or(u32::MAX),
+        edge_count
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_heap_snapshot() {
+        let mut dump = TestDump::new();
+        let weak_ref = dump.class(
+            "java/lang/ref/WeakReference",
+            None,
+            &[("referent", FieldType::Object)],
+        );
+        let holder = dump.class("com/acme/Holder", None, &[("name", FieldType::Object)]);
+        let name = dump.java_string("cache");
+        let holder = dump.instance(holder, &[FieldValue::Object(name)]);
+        let weak_ref = dump.instance(weak_ref, &[FieldValue::Object(holder)]);
+        dump.dump(DumpRecord::RootJniGlobal {
+            obj_id: weak_ref,
+            jni_global_ref: id(1),
+        });
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let store = dump.store();
+        let mut out = Vec::new();
+        HeapSnapshotWriter::new(&graph)
+            .with_object_store(&store)
+            .write(&mut out)
+            .unwrap();
+        let snapshot = String::from_utf8(out).unwrap();
+
+        let node_count = 2 + graph.node_count();
+        assert!(snapshot.contains(&format!("\"node_count\":{},", node_count)));
+        let strings = &snapshot[snapshot.find("\"strings\":[").unwrap()..];
+        for name in &[
+            "\"(RootJniGlobal)\"",
+            "\"com.acme.Holder\"",
+            "\"cache\"",
+            "\"referent\"",
+            "\"byte[]\"",
+        ] {
+            assert!(strings.contains(name), "{} not in {}", name, strings);
+        }
+        let edges = &snapshot[snapshot.find("\"edges\":[").unwrap() + 9..];
+        let edges: Vec<u64> = edges[..edges.find(']').unwrap()]
+            .split(',')
+            .map(|value| value.trim().parse().unwrap())
+            .collect();
+        assert!(snapshot.contains(&format!("\"edge_count\":{},", edges.len() / 3)));
+        let weak_edges = edges
+            .chunks(3)
+            .filter(|edge| edge[0] == u64::from(EDGE_WEAK))
+            .count();
+        assert_eq!(weak_edges, 1);
+    }
+}
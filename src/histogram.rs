@@ -0,0 +1,337 @@
+#![forbid(unsafe_code)]
+
+/*!
+Class histogram, like `jmap -histo`: number of objects and their
+shallow size per class.
+
+The histogram is built in one pass over the records without keeping
+objects.  HPROF strings are kept only until the first heap dump record:
+by then HotSpot has written all LOAD_CLASS records, and only names of
+loaded classes are needed afterwards.
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::histogram::Histogram;
+# use hprof_dump_parser::size::SizeModel;
+# let data = vec![];
+let hprof = StreamHprofReader::new().with_load_primitive_arrays(false);
+let records = hprof.read_hprof_from_memory(&data).unwrap();
+let histogram = Histogram::from_records(records, SizeModel::HOTSPOT_64_COMPRESSED).unwrap();
+histogram.write_table(&mut std::io::stdout()).unwrap();
+```
+ */
+
+use crate::decl::*;
+use crate::json;
+use crate::registry::java_name;
+use crate::size::SizeModel;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// What objects are counted together.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Key {
+    /// Class objects.
+    Class,
+    Instance(Id),
+    /// Object arrays by array class.
+    ObjectArray(Id),
+    PrimitiveArray(FieldType),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counter {
+    objects: u64,
+    shallow_size: u64,
+}
+
+/// Histogram row.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistogramEntry {
+    /// Class name in the Java form (`java.lang.String`, `int[]`);
+    /// classes without LOAD_CLASS record are named by their id.
+    pub class_name: String,
+    pub instances: u64,
+    pub shallow_size: u64,
+}
+
+/// Class histogram, biggest shallow size first.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    pub entries: Vec<HistogramEntry>,
+}
+
+/**
+Streaming histogram builder.  Use it to build a histogram in the same
+pass with other consumers of records; otherwise
+`Histogram::from_records` is simpler.
+ */
+#[derive(Debug)]
+pub struct HistogramBuilder {
+    size_model: SizeModel,
+    /// Dropped at the first heap dump record.
+    strings: Option<HashMap<Id, String>>,
+    /// Class name string ids for classes loaded before names could be
+    /// resolved.
+    pending_names: Vec<(Id, Id)>,
+    class_names: HashMap<Id, String>,
+    /// Shallow size of instances by class: all instances of a class
+    /// have the same size.
+    instance_sizes: HashMap<Id, u64>,
+    counters: HashMap<Key, Counter>,
+}
+
+impl HistogramBuilder {
+    pub fn new(size_model: SizeModel) -> Self {
+        Self {
+            size_model,
+            strings: Some(HashMap::new()),
+            pending_names: Vec::new(),
+            class_names: HashMap::new(),
+            instance_sizes: HashMap::new(),
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        match record {
+            Record::String(id, data) => {
+                if let Some(strings) = &mut self.strings {
+                    strings.insert(*id, String::from_utf8_lossy(data.as_ref()).into_owned());
+                }
+            }
+            // After the first heap dump record the name strings are gone.
+            Record::LoadClass(class_record) if self.strings.is_some() => {
+                self.pending_names
+                    .push((class_record.class_obj_id, class_record.class_name_string_id));
+            }
+            Record::Dump(dump) => {
+                if let Some(strings) = self.strings.take() {
+                    for (class_id, name_id) in self.pending_names.drain(..) {
+                        if let Some(name) = strings.get(&name_id) {
+                            self.class_names.insert(class_id, java_name(name));
+                        }
+                    }
+                }
+                self.add_dump_record(dump);
+            }
+            _ => {}
+        }
+    }
+
+    fn add_dump_record(&mut self, dump: &DumpRecord) {
+        let model = self.size_model;
+        let (key, size) = match dump {
+            DumpRecord::ClassDump(class_desc) => {
+                let (payload, references) = field_data(class_desc.static_fields.iter());
+                (Key::Class, model.instance_size(payload, references))
+            }
+            DumpRecord::InstanceDump(instance) => {
+                let size = *self
+                    .instance_sizes
+                    .entry(instance.class_object_id)
+                    .or_insert_with(|| {
+                        let (payload, references) = field_data(instance.values.iter());
+                        model.instance_size(payload, references)
+                    });
+                (Key::Instance(instance.class_object_id), size)
+            }
+            DumpRecord::ObjectArrayDump(array) => (
+                Key::ObjectArray(array.element_class_id),
+                model.object_array_size(array.num_elements.into()),
+            ),
+            DumpRecord::PrimitiveArrayDump(array) => (
+                Key::PrimitiveArray(array.elem_type),
+                model.primitive_array_size(array.elem_type, array.num_elements.into()),
+            ),
+            _ => return,
+        };
+        let counter = self.counters.entry(key).or_default();
+        counter.objects += 1;
+        counter.shallow_size += size;
+    }
+
+    pub fn finish(self) -> Histogram {
+        let class_names = self.class_names;
+        let name = |class_id: Id| {
+            class_names
+                .get(&class_id)
+                .cloned()
+                .unwrap_or_else(|| format!("<unknown class {:#x}>", u64::from(class_id)))
+        };
+        let mut entries: Vec<HistogramEntry> = self
+            .counters
+            .into_iter()
+            .map(|(key, counter)| HistogramEntry {
+                class_name: match key {
+                    Key::Class => "java.lang.Class".to_string(),
+                    Key::Instance(class_id) | Key::ObjectArray(class_id) => name(class_id),
+                    Key::PrimitiveArray(elem_type) => format!("{}[]", elem_type.java_name()),
+                },
+                instances: counter.objects,
+                shallow_size: counter.shallow_size,
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.shallow_size
+                .cmp(&a.shallow_size)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        Histogram { entries }
+    }
+}
+
+/// Primitive data size and number of references.
+pub(crate) fn field_data<'a, I: Iterator<Item = &'a (FieldInfo, FieldValue)>>(
+    fields: I,
+) -> (u64, u64) {
+    let mut payload = 0;
+    let mut references = 0;
+    for (info, _) in fields {
+        match info.field_type {
+            FieldType::Object => references += 1,
+            other => payload += other.byte_size().unwrap_or(0),
+        }
+    }
+    (payload, references)
+}
+
+impl Histogram {
+    pub fn from_records<I, S>(records: I, size_model: SizeModel) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = HistogramBuilder::new(size_model);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish())
+    }
+
+    pub fn total_instances(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.instances).sum()
+    }
+
+    pub fn total_shallow_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.shallow_size).sum()
+    }
+
+    /// Entry by class name.
+    pub fn get(&self, class_name: &str) -> Option<&HistogramEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.class_name == class_name)
+    }
+
+    /// Write a `jmap -histo`-like table.
+    pub fn write_table<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, " num     #instances         #bytes  class name")?;
+        writeln!(out, "----------------------------------------------")?;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            writeln!(
+                out,
+                "{:>4}: {:>14} {:>14}  {}",
+                idx + 1,
+                entry.instances,
+                entry.shallow_size,
+                entry.class_name
+            )?;
+        }
+        writeln!(
+            out,
+            "Total {:>14} {:>14}",
+            self.total_instances(),
+            self.total_shallow_size()
+        )
+    }
+
+    /// Write CSV with a header row.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "class_name,instances,shallow_size")?;
+        for entry in &self.entries {
+            write_csv_field(out, &entry.class_name)?;
+            writeln!(out, ",{},{}", entry.instances, entry.shallow_size)?;
+        }
+        Ok(())
+    }
+
+    /// Write a JSON array of `{"class_name", "instances",
+    /// "shallow_size"}` objects.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"[")?;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if idx > 0 {
+                out.write_all(b",")?;
+            }
+            out.write_all(b"\n  {\"class_name\": ")?;
+            json::write_string(out, &entry.class_name)?;
+            write!(
+                out,
+                ", \"instances\": {}, \"shallow_size\": {}}}",
+                entry.instances, entry.shallow_size
+            )?;
+        }
+        out.write_all(b"\n]\n")
+    }
+}
+
+/// Write a CSV field, quoting it if needed.
+pub(crate) fn write_csv_field<W: Write>(out: &mut W, field: &str) -> io::Result<()> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        write!(out, "\"{}\"", field.replace('"', "\"\""))
+    } else {
+        out.write_all(field.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut dump = TestDump::new();
+        let point = dump.class(
+            "com/acme/Point",
+            None,
+            &[("x", FieldType::Int), ("y", FieldType::Int)],
+        );
+        let points_class = dump.class("[Lcom/acme/Point;", None, &[]);
+        let first = dump.instance(point, &[FieldValue::Int(1), FieldValue::Int(2)]);
+        let second = dump.instance(point, &[FieldValue::Int(3), FieldValue::Int(4)]);
+        dump.object_array(points_class, &[first, second]);
+        dump.primitive_array(ArrayValue::Long(vec![1, 2, 3]));
+
+        let histogram =
+            Histogram::from_records(dump.records(), SizeModel::HOTSPOT_64_COMPRESSED).unwrap();
+        let rows: Vec<(&str, u64, u64)> = histogram
+            .entries
+            .iter()
+            .map(|e| (e.class_name.as_str(), e.instances, e.shallow_size))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("com.acme.Point", 2, 48),
+                ("long[]", 1, 40),
+                ("java.lang.Class", 2, 32),
+                ("com.acme.Point[]", 1, 24),
+            ]
+        );
+
+        let mut csv = Vec::new();
+        histogram.write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv)
+            .unwrap()
+            .starts_with("class_name,instances,shallow_size\ncom.acme.Point,2,48\n"));
+
+        let mut table = Vec::new();
+        histogram.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.contains("   1:              2             48  com.acme.Point\n"));
+        assert!(table.ends_with("Total              6            144\n"));
+    }
+}
@@ -0,0 +1,301 @@
+#![forbid(unsafe_code)]
+
+/*!
+Inbound references (reverse edges) of the object graph.
+
+The index is a transposed CSR of `HeapGraph`.  It takes 4 bytes per
+edge and 8 bytes per node.  For graphs with billions of edges the edge
+array may be kept in a file instead of memory; then it is built in
+several passes over the graph, each one filling a buffer of bounded
+size.
+ */
+
+use crate::graph::{EdgeLabel, HeapGraph, NodeIndex};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+const EDGE_BYTES: u64 = std::mem::size_of::<NodeIndex>() as u64;
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<NodeIndex>),
+    File(Mutex<File>),
+}
+
+/// Inbound references index, see module documentation.
+#[derive(Debug)]
+pub struct InboundIndex {
+    offsets: Vec<u64>,
+    storage: Storage,
+}
+
+/// Referrers of the same class referencing an object through the same
+/// field.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReferrerGroup {
+    /// Type name of the referrers, see `HeapGraph::type_name`.
+    pub class_name: String,
+    /// Field name, array index or other label (see `EdgeLabel`).  All
+    /// array elements are grouped under `[]`.
+    pub field: String,
+    pub referrers: Vec<NodeIndex>,
+}
+
+fn inbound_offsets(graph: &HeapGraph) -> Vec<u64> {
+    let count = graph.node_count();
+    let mut offsets = vec![0u64; count + 1];
+    for node in 0..count as NodeIndex {
+        for target in graph.outgoing(node) {
+            offsets[*target as usize + 1] += 1;
+        }
+    }
+    for idx in 0..count {
+        offsets[idx + 1] += offsets[idx];
+    }
+    offsets
+}
+
+/// Fill `buffer` with referrers of nodes `lo..hi`; `buffer` starts at
+/// `offsets[lo]`.
+fn fill_referrers(
+    graph: &HeapGraph,
+    offsets: &[u64],
+    lo: NodeIndex,
+    hi: NodeIndex,
+    buffer: &mut [NodeIndex],
+) {
+    let base = offsets[lo as usize];
+    let mut cursors: Vec<u64> = offsets[lo as usize..hi as usize]
+        .iter()
+        .map(|offset| offset - base)
+        .collect();
+    for node in 0..graph.node_count() as NodeIndex {
+        for target in graph.outgoing(node) {
+            if (lo..hi).contains(target) {
+                let cursor = &mut cursors[(target - lo) as usize];
+                buffer[*cursor as usize] = node;
+                *cursor += 1;
+            }
+        }
+    }
+}
+
+impl InboundIndex {
+    /// Build the index in memory.
+    pub fn build(graph: &HeapGraph) -> Self {
+        let offsets = inbound_offsets(graph);
+        let mut edges = vec![0; graph.edge_count()];
+        fill_referrers(
+            graph,
+            &offsets,
+            0,
+            graph.node_count() as NodeIndex,
+            &mut edges,
+        );
+        Self {
+            offsets,
+            storage: Storage::Memory(edges),
+        }
+    }
+
+    /**
+    Build the index keeping edges in `file` (it is truncated).  At most
+    `max_edges_in_memory` edges are buffered at once, unless a single
+    object has more referrers; the graph is scanned once per buffer.
+    A temporary file is a good choice, e.g. one created with the
+    `tempfile` crate.
+     */
+    pub fn build_spilled(
+        graph: &HeapGraph,
+        mut file: File,
+        max_edges_in_memory: usize,
+    ) -> io::Result<Self> {
+        let offsets = inbound_offsets(graph);
+        let count = graph.node_count() as NodeIndex;
+        let budget = max_edges_in_memory.max(1) as u64;
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut buffer = Vec::new();
+        let mut bytes = Vec::new();
+        let mut lo: NodeIndex = 0;
+        while lo < count {
+            let mut hi = lo + 1;
+            while hi < count && offsets[hi as usize + 1] - offsets[lo as usize] <= budget {
+                hi += 1;
+            }
+            let len = (offsets[hi as usize] - offsets[lo as usize]) as usize;
+            buffer.clear();
+            buffer.resize(len, 0);
+            fill_referrers(graph, &offsets, lo, hi, &mut buffer);
+
+            bytes.clear();
+            bytes.extend(buffer.iter().flat_map(|node| node.to_le_bytes()));
+            file.write_all(&bytes)?;
+            lo = hi;
+        }
+        file.flush()?;
+
+        Ok(Self {
+            offsets,
+            storage: Storage::File(Mutex::new(file)),
+        })
+    }
+
+    /// Number of references to the node.
+    #[inline]
+    pub fn referrer_count(&self, node: NodeIndex) -> usize {
+        let node = node as usize;
+        (self.offsets[node + 1] - self.offsets[node]) as usize
+    }
+
+    /// Nodes referencing the node.  An object referencing the node
+    /// from several fields is listed several times.
+    pub fn referrers(&self, node: NodeIndex) -> io::Result<Cow<'_, [NodeIndex]>> {
+        let start = self.offsets[node as usize];
+        let end = self.offsets[node as usize + 1];
+        match &self.storage {
+            Storage::Memory(edges) => Ok(Cow::Borrowed(&edges[start as usize..end as usize])),
+            Storage::File(file) => {
+                let mut file = file.lock().map_err(|_| io::Error::other("poisoned lock"))?;
+                let mut bytes = vec![0; ((end - start) * EDGE_BYTES) as usize];
+                file.seek(SeekFrom::Start(start * EDGE_BYTES))?;
+                file.read_exact(&mut bytes)?;
+                Ok(Cow::Owned(
+                    bytes
+                        .chunks_exact(EDGE_BYTES as usize)
+                        .map(|chunk| {
+                            NodeIndex::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    /// Referrers grouped by class and field name, largest groups
+    /// first.
+    pub fn referrers_by_class(
+        &self,
+        graph: &HeapGraph,
+        node: NodeIndex,
+    ) -> io::Result<Vec<ReferrerGroup>> {
+        let referrers = self.referrers(node)?;
+        let mut groups: HashMap<(String, String), Vec<NodeIndex>> = HashMap::new();
+        let mut prev = None;
+        for referrer in referrers.iter().copied() {
+            // Several edges from the same object are adjacent.
+            if prev == Some(referrer) {
+                continue;
+            }
+            prev = Some(referrer);
+            let class_name = graph.type_name(referrer);
+            let mut fields: Vec<String> = graph
+                .edge_labels_between(referrer, node)
+                .map(|label| match label {
+                    EdgeLabel::ArrayElement(_) => "[]".to_string(),
+                    other => other.to_string(),
+                })
+                .collect();
+            fields.dedup();
+            for field in fields {
+                groups
+                    .entry((class_name.clone(), field))
+                    .or_default()
+                    .push(referrer);
+            }
+        }
+        let mut groups: Vec<ReferrerGroup> = groups
+            .into_iter()
+            .map(|((class_name, field), referrers)| ReferrerGroup {
+                class_name,
+                field,
+                referrers,
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            b.referrers
+                .len()
+                .cmp(&a.referrers.len())
+                .then_with(|| a.class_name.cmp(&b.class_name))
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::testdata::*;
+
+    fn shared_target() -> (HeapGraph, Id) {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let holder = dump.class(
+            "com/acme/Holder",
+            Some(object),
+            &[("a", FieldType::Object), ("b", FieldType::Object)],
+        );
+        let target = dump.instance(object, &[]);
+        dump.instance(
+            holder,
+            &[FieldValue::Object(target), FieldValue::Object(target)],
+        );
+        dump.instance(
+            holder,
+            &[FieldValue::Object(target), FieldValue::Object(id(0))],
+        );
+        dump.object_array(object, &[target, target]);
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        (graph, target)
+    }
+
+    #[test]
+    fn test_spilled_matches_memory() {
+        let (graph, _) = shared_target();
+        let memory = InboundIndex::build(&graph);
+        let path = std::env::temp_dir().join(format!("hprof-inbound-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let spilled = InboundIndex::build_spilled(&graph, file, 2).unwrap();
+        for node in 0..graph.node_count() as NodeIndex {
+            assert_eq!(
+                memory.referrers(node).unwrap(),
+                spilled.referrers(node).unwrap()
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_referrers_by_class() {
+        let (graph, target) = shared_target();
+        let index = InboundIndex::build(&graph);
+        let node = graph.index_of(target).unwrap();
+        assert_eq!(index.referrer_count(node), 5);
+
+        let groups = index.referrers_by_class(&graph, node).unwrap();
+        let summary: Vec<(&str, &str, usize)> = groups
+            .iter()
+            .map(|g| (g.class_name.as_str(), g.field.as_str(), g.referrers.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("com.acme.Holder", "a", 2),
+                ("com.acme.Holder", "b", 1),
+                ("java.lang.Object", "[]", 1),
+            ]
+        );
+    }
+}
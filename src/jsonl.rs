@@ -0,0 +1,611 @@
+#![forbid(unsafe_code)]
+
+/*!
+JSON Lines export of records: one JSON object per record or heap dump
+subrecord, for `jq` and other tools.
+
+Every object has `"type"` and `"timestamp"` keys.  Ids are hex strings
+like `"0x7f3a10"`; null references in field values and array elements
+are `null`.  Names are resolved from the records seen before, so
+`"name"` style keys are `null` if the string or class is not known.
+Class names are in the Java form (`java.lang.String[]`).
+
+Primitive values are JSON numbers and booleans, except that `char`
+values and arrays are strings, and non-finite floats are the strings
+`"NaN"`, `"Infinity"` and `"-Infinity"`.  Note that `long` values
+beyond 2^53 lose precision in some JSON readers.
+
+| `type` | keys |
+|---|---|
+| `string` | `id`, `value` |
+| `load_class` | `serial`, `class_id`, `stack_trace_serial`, `name` |
+| `unload_class` | `serial` |
+| `stack_frame` | `frame_id`, `method_name`, `signature`, `source_file`, `class_serial`, `class_name`, `line_number` |
+| `stack_trace` | `serial`, `thread_serial`, `frame_ids` |
+| `alloc_sites` | `flags`, `cutoff_ratio`, `total_live_bytes`, `total_live_instances`, `total_bytes_allocated`, `total_instances_allocated`, `sites` (objects with `is_array`, `class_serial`, `class_name`, `stack_trace_serial`, `bytes_alive`, `instances_alive`, `bytes_allocated`, `instances_allocated`) |
+| `heap_summary` | `total_live_bytes`, `total_live_instances`, `total_bytes_allocated`, `total_instances_allocated` |
+| `start_thread` | `thread_serial`, `object_id`, `stack_trace_serial`, `name`, `group_name`, `parent_group_name` |
+| `end_thread` | `thread_serial` |
+| `cpu_samples` | `total_samples`, `traces` (objects with `samples`, `stack_trace_serial`) |
+| `control_settings` | `flags`, `stack_trace_depth` |
+| `root` | `kind` (`HeapGraph` root kind name, e.g. `RootJavaFrame`), `object_id`, and `thread_serial`, `frame_number`, `stack_trace_serial`, `jni_global_ref` when the root kind has them |
+| `class_dump` | `class_id`, `name`, `stack_trace_serial`, `super_class_id`, `class_loader_id`, `instance_size`, `constants` (objects with `index`, `type`, `value`), `static_fields` (objects with `name`, `type`, `value`), `instance_fields` (objects with `name`, `type`) |
+| `instance` | `object_id`, `class_id`, `class_name`, `stack_trace_serial`, `fields` (objects with `name`, `type`, `value`, the class's own fields first) |
+| `object_array` | `object_id`, `class_id`, `class_name`, `stack_trace_serial`, `length`, `values` |
+| `primitive_array` | `object_id`, `element_type`, `stack_trace_serial`, `length`, `values` |
+
+Field types are Java names: `int`, `boolean`, `Object`.  Array
+`values` are omitted if the arrays were not loaded (see
+`StreamHprofReader::with_load_object_arrays`) or the writer is told to
+skip them.
+ */
+
+use crate::decl::*;
+use crate::json::write_string;
+use crate::registry::ClassRegistry;
+use crate::value::object_id_label;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// Writer of a JSON object.
+struct Fields<'a, W: Write> {
+    out: &'a mut W,
+    first: bool,
+}
+
+impl<'a, W: Write> Fields<'a, W> {
+    fn begin(out: &'a mut W) -> io::Result<Self> {
+        out.write_all(b"{")?;
+        Ok(Self { out, first: true })
+    }
+
+    /// Write the key; the caller writes the value.
+    fn key(&mut self, key: &str) -> io::Result<&mut W> {
+        if !self.first {
+            self.out.write_all(b",")?;
+        }
+        self.first = false;
+        write!(self.out, "\"{}\":", key)?;
+        Ok(self.out)
+    }
+
+    fn str(&mut self, key: &str, value: &str) -> io::Result<()> {
+        write_string(self.key(key)?, value)
+    }
+
+    fn opt_str(&mut self, key: &str, value: Option<&str>) -> io::Result<()> {
+        match value {
+            Some(value) => self.str(key, value),
+            None => self.key(key)?.write_all(b"null"),
+        }
+    }
+
+    fn num<T: Display>(&mut self, key: &str, value: T) -> io::Result<()> {
+        write!(self.key(key)?, "{}", value)
+    }
+
+    fn id(&mut self, key: &str, id: Id) -> io::Result<()> {
+        write_string(self.key(key)?, &object_id_label(id))
+    }
+
+    fn value(&mut self, key: &str, value: FieldValue) -> io::Result<()> {
+        write_value(self.key(key)?, value)
+    }
+
+    fn end(self) -> io::Result<()> {
+        self.out.write_all(b"}")
+    }
+}
+
+fn write_ref<W: Write>(out: &mut W, id: Id) -> io::Result<()> {
+    if u64::from(id) == 0 {
+        out.write_all(b"null")
+    } else {
+        write_string(out, &object_id_label(id))
+    }
+}
+
+fn write_float<W: Write>(out: &mut W, value: f64, display: &dyn Display) -> io::Result<()> {
+    if value.is_nan() {
+        out.write_all(b"\"NaN\"")
+    } else if value.is_infinite() {
+        out.write_all(if value > 0.0 {
+            b"\"Infinity\""
+        } else {
+            b"\"-Infinity\""
+        })
+    } else {
+        write!(out, "{}", display)
+    }
+}
+
+fn write_value<W: Write>(out: &mut W, value: FieldValue) -> io::Result<()> {
+    match value {
+        FieldValue::Bool(v) => write!(out, "{}", v),
+        FieldValue::Byte(v) => write!(out, "{}", v),
+        FieldValue::Char(v) => write_string(out, &String::from_utf16_lossy(&[v])),
+        FieldValue::Short(v) => write!(out, "{}", v),
+        FieldValue::Int(v) => write!(out, "{}", v),
+        FieldValue::Long(v) => write!(out, "{}", v),
+        FieldValue::Float(v) => write_float(out, v.into(), &v),
+        FieldValue::Double(v) => write_float(out, v, &v),
+        FieldValue::Object(id) => write_ref(out, id),
+    }
+}
+
+/// Write a JSON array with an element writer.
+fn write_array<W, T, F>(out: &mut W, items: &[T], mut write_item: F) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(&mut W, &T) -> io::Result<()>,
+{
+    out.write_all(b"[")?;
+    for (idx, item) in items.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b",")?;
+        }
+        write_item(out, item)?;
+    }
+    out.write_all(b"]")
+}
+
+fn write_array_value<W: Write>(out: &mut W, values: &ArrayValue) -> io::Result<()> {
+    match values {
+        ArrayValue::Bool(v) => write_array(out, v, |out, v| write!(out, "{}", v)),
+        ArrayValue::Byte(v) => write_array(out, v, |out, v| write!(out, "{}", v)),
+        ArrayValue::Char(v) => write_string(out, &String::from_utf16_lossy(v)),
+        ArrayValue::Short(v) => write_array(out, v, |out, v| write!(out, "{}", v)),
+        ArrayValue::Int(v) => write_array(out, v, |out, v| write!(out, "{}", v)),
+        ArrayValue::Long(v) => write_array(out, v, |out, v| write!(out, "{}", v)),
+        ArrayValue::Float(v) => write_array(out, v, |out, v| write_float(out, (*v).into(), v)),
+        ArrayValue::Double(v) => write_array(out, v, |out, v| write_float(out, *v, v)),
+        ArrayValue::Object(v) => write_array(out, v, |out, id| write_ref(out, *id)),
+    }
+}
+
+/**
+JSON Lines writer, see the module documentation for the schema.
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::jsonl::JsonLinesWriter;
+# let data = vec![];
+let hprof = StreamHprofReader::new();
+let records = hprof.read_hprof_from_memory(&data).unwrap();
+let out = std::io::BufWriter::new(std::io::stdout());
+let mut writer = JsonLinesWriter::new(out).with_array_values(false);
+writer.write_records(records).unwrap();
+```
+ */
+#[derive(Debug)]
+pub struct JsonLinesWriter<W> {
+    out: W,
+    registry: ClassRegistry,
+    array_values: bool,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            registry: ClassRegistry::new(),
+            array_values: true,
+        }
+    }
+
+    /// Write array elements; `true` by default.
+    pub fn with_array_values(mut self, array_values: bool) -> Self {
+        self.array_values = array_values;
+        self
+    }
+
+    /// Write all records, returning their number.
+    pub fn write_records<I, S>(&mut self, records: I) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut count = 0;
+        for rec in records {
+            let (timestamp, record) = rec?;
+            self.write_record(timestamp, &record)?;
+            count += 1;
+        }
+        self.out.flush()?;
+        Ok(count)
+    }
+
+    /// Write a record as a line.
+    pub fn write_record<S: AsRef<[u8]>>(
+        &mut self,
+        timestamp: Ts,
+        record: &Record<S>,
+    ) -> io::Result<()> {
+        self.registry.add_record(record);
+        let registry = &self.registry;
+        let class_name = |class_id: Id| registry.java_class_name(class_id);
+        let class_name_by_serial = |serial: SerialNumber| {
+            registry
+                .class_id_by_serial(serial)
+                .and_then(|class_id| registry.java_class_name(class_id))
+        };
+        let mut line = Fields::begin(&mut self.out)?;
+
+        match record {
+            Record::String(id, data) => {
+                line.str("type", "string")?;
+                line.num("timestamp", timestamp)?;
+                line.id("id", *id)?;
+                line.str("value", &String::from_utf8_lossy(data.as_ref()))?;
+            }
+            Record::LoadClass(class) => {
+                line.str("type", "load_class")?;
+                line.num("timestamp", timestamp)?;
+                line.num("serial", class.serial)?;
+                line.id("class_id", class.class_obj_id)?;
+                line.num("stack_trace_serial", class.stack_trace_serial)?;
+                line.opt_str("name", class_name(class.class_obj_id).as_deref())?;
+            }
+            Record::UnloadClass(serial) => {
+                line.str("type", "unload_class")?;
+                line.num("timestamp", timestamp)?;
+                line.num("serial", serial)?;
+            }
+            Record::StackFrame(frame) => {
+                line.str("type", "stack_frame")?;
+                line.num("timestamp", timestamp)?;
+                line.id("frame_id", frame.stack_frame_id)?;
+                line.opt_str("method_name", registry.string(frame.method_name_id))?;
+                line.opt_str("signature", registry.string(frame.method_signature_id))?;
+                line.opt_str("source_file", registry.string(frame.source_file_name_id))?;
+                line.num("class_serial", frame.class_serial)?;
+                line.opt_str(
+                    "class_name",
+                    class_name_by_serial(frame.class_serial).as_deref(),
+                )?;
+                line.num("line_number", frame.line_number)?;
+            }
+            Record::StackTrace(trace) => {
+                line.str("type", "stack_trace")?;
+                line.num("timestamp", timestamp)?;
+                line.num("serial", trace.stack_trace_serial)?;
+                line.num("thread_serial", trace.thread_serial)?;
+                write_array(line.key("frame_ids")?, &trace.stack_frame_ids, |out, id| {
+                    write_string(out, &object_id_label(*id))
+                })?;
+            }
+            Record::AllocSites(sites) => {
+                line.str("type", "alloc_sites")?;
+                line.num("timestamp", timestamp)?;
+                line.num("flags", sites.flags)?;
+                let cutoff_ratio = f32::from_bits(sites.cutoff_ratio);
+                write_float(
+                    line.key("cutoff_ratio")?,
+                    cutoff_ratio.into(),
+                    &cutoff_ratio,
+                )?;
+                line.num("total_live_bytes", sites.total_live_bytes)?;
+                line.num("total_live_instances", sites.total_live_instances)?;
+                line.num("total_bytes_allocated", sites.total_bytes_allocated)?;
+                line.num("total_instances_allocated", sites.total_instances_allocated)?;
+                write_array(line.key("sites")?, &sites.sites, |out, site| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.num("is_array", site.is_array)?;
+                    fields.num("class_serial", site.class_serial)?;
+                    fields.opt_str(
+                        "class_name",
+                        class_name_by_serial(site.class_serial).as_deref(),
+                    )?;
+                    fields.num("stack_trace_serial", site.stack_trace_serial)?;
+                    fields.num("bytes_alive", site.bytes_alive)?;
+                    fields.num("instances_alive", site.instances_alive)?;
+                    fields.num("bytes_allocated", site.bytes_allocated)?;
+                    fields.num("instances_allocated", site.instances_allocated)?;
+                    fields.end()
+                })?;
+            }
+            Record::HeapSummary(summary) => {
+                line.str("type", "heap_summary")?;
+                line.num("timestamp", timestamp)?;
+                line.num("total_live_bytes", summary.total_live_bytes)?;
+                line.num("total_live_instances", summary.total_live_instances)?;
+                line.num("total_bytes_allocated", summary.total_bytes_allocated)?;
+                line.num(
+                    "total_instances_allocated",
+                    summary.total_instances_allocated,
+                )?;
+            }
+            Record::StartThread(thread) => {
+                line.str("type", "start_thread")?;
+                line.num("timestamp", timestamp)?;
+                line.num("thread_serial", thread.thread_serial)?;
+                line.id("object_id", thread.thead_object_id)?;
+                line.num("stack_trace_serial", thread.stack_trace_serial)?;
+                line.opt_str("name", registry.string(thread.thread_name_id))?;
+                line.opt_str("group_name", registry.string(thread.thread_group_name_id))?;
+                line.opt_str(
+                    "parent_group_name",
+                    registry.string(thread.thread_group_parent_name_id),
+                )?;
+            }
+            Record::EndThread(thread) => {
+                line.str("type", "end_thread")?;
+                line.num("timestamp", timestamp)?;
+                line.num("thread_serial", thread.thread_serial)?;
+            }
+            Record::CpuSamples(samples) => {
+                line.str("type", "cpu_samples")?;
+                line.num("timestamp", timestamp)?;
+                line.num("total_samples", samples.total_samples)?;
+                write_array(line.key("traces")?, &samples.traces, |out, trace| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.num("samples", trace.samples)?;
+                    fields.num("stack_trace_serial", trace.stack_trace_serial)?;
+                    fields.end()
+                })?;
+            }
+            Record::ControlSettings(settings) => {
+                line.str("type", "control_settings")?;
+                line.num("timestamp", timestamp)?;
+                line.num("flags", settings.flags)?;
+                line.num("stack_trace_depth", settings.stack_trace_depth)?;
+            }
+            Record::Dump(dump) => {
+                write_dump_record(&mut line, timestamp, dump, registry, self.array_values)?
+            }
+        }
+        line.end()?;
+        self.out.write_all(b"\n")
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn write_root<W: Write>(
+    line: &mut Fields<'_, W>,
+    timestamp: Ts,
+    kind: &str,
+    object_id: Id,
+) -> io::Result<()> {
+    line.str("type", "root")?;
+    line.num("timestamp", timestamp)?;
+    line.str("kind", kind)?;
+    line.id("object_id", object_id)
+}
+
+fn write_dump_record<W: Write>(
+    line: &mut Fields<'_, W>,
+    timestamp: Ts,
+    record: &DumpRecord,
+    registry: &ClassRegistry,
+    array_values: bool,
+) -> io::Result<()> {
+    match record {
+        DumpRecord::RootUnknown { obj_id } => write_root(line, timestamp, "RootUnknown", *obj_id),
+        DumpRecord::RootJniGlobal {
+            obj_id,
+            jni_global_ref,
+        } => {
+            write_root(line, timestamp, "RootJniGlobal", *obj_id)?;
+            line.id("jni_global_ref", *jni_global_ref)
+        }
+        DumpRecord::RootJniLocal {
+            obj_id,
+            thread_serial,
+            frame_number,
+        } => {
+            write_root(line, timestamp, "RootJniLocal", *obj_id)?;
+            line.num("thread_serial", thread_serial)?;
+            line.num("frame_number", frame_number)
+        }
+        DumpRecord::RootJavaFrame {
+            obj_id,
+            thread_serial,
+            frame_number,
+        } => {
+            write_root(line, timestamp, "RootJavaFrame", *obj_id)?;
+            line.num("thread_serial", thread_serial)?;
+            line.num("frame_number", frame_number)
+        }
+        DumpRecord::RootNativeStack {
+            obj_id,
+            thread_serial,
+        } => {
+            write_root(line, timestamp, "RootNativeStack", *obj_id)?;
+            line.num("thread_serial", thread_serial)
+        }
+        DumpRecord::RootStickyClass { obj_id } => {
+            write_root(line, timestamp, "RootStickyClass", *obj_id)
+        }
+        DumpRecord::RootThreadBlock {
+            obj_id,
+            thread_serial,
+        } => {
+            write_root(line, timestamp, "RootThreadBlock", *obj_id)?;
+            line.num("thread_serial", thread_serial)
+        }
+        DumpRecord::RootMonitorUsed { obj_id } => {
+            write_root(line, timestamp, "RootMonitorUsed", *obj_id)
+        }
+        DumpRecord::RootThreadObject {
+            obj_id,
+            thread_serial,
+            stack_trace_serial,
+        } => {
+            write_root(line, timestamp, "RootThreadObject", *obj_id)?;
+            line.num("thread_serial", thread_serial)?;
+            line.num("stack_trace_serial", stack_trace_serial)
+        }
+        DumpRecord::ClassDump(class_desc) => {
+            line.str("type", "class_dump")?;
+            line.num("timestamp", timestamp)?;
+            line.id("class_id", class_desc.class_id)?;
+            line.opt_str(
+                "name",
+                registry.java_class_name(class_desc.class_id).as_deref(),
+            )?;
+            line.num("stack_trace_serial", class_desc.stack_trace_serial)?;
+            line.id("super_class_id", class_desc.super_class_object_id)?;
+            line.id("class_loader_id", class_desc.class_loader_object_id)?;
+            line.num("instance_size", class_desc.instance_size)?;
+            write_array(
+                line.key("constants")?,
+                &class_desc.const_fields,
+                |out, (info, value)| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.num("index", info.const_pool_idx)?;
+                    fields.str("type", info.const_type.java_name())?;
+                    fields.value("value", *value)?;
+                    fields.end()
+                },
+            )?;
+            write_array(
+                line.key("static_fields")?,
+                &class_desc.static_fields,
+                |out, (info, value)| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.opt_str("name", registry.field_name(info))?;
+                    fields.str("type", info.field_type.java_name())?;
+                    fields.value("value", *value)?;
+                    fields.end()
+                },
+            )?;
+            write_array(
+                line.key("instance_fields")?,
+                &class_desc.instance_fields,
+                |out, info| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.opt_str("name", registry.field_name(info))?;
+                    fields.str("type", info.field_type.java_name())?;
+                    fields.end()
+                },
+            )
+        }
+        DumpRecord::InstanceDump(instance) => {
+            line.str("type", "instance")?;
+            line.num("timestamp", timestamp)?;
+            line.id("object_id", instance.object_id)?;
+            line.id("class_id", instance.class_object_id)?;
+            line.opt_str(
+                "class_name",
+                registry
+                    .java_class_name(instance.class_object_id)
+                    .as_deref(),
+            )?;
+            line.num("stack_trace_serial", instance.stack_trace_serial)?;
+            write_array(
+                line.key("fields")?,
+                &instance.values,
+                |out, (info, value)| {
+                    let mut fields = Fields::begin(out)?;
+                    fields.opt_str("name", registry.field_name(info))?;
+                    fields.str("type", info.field_type.java_name())?;
+                    fields.value("value", *value)?;
+                    fields.end()
+                },
+            )
+        }
+        DumpRecord::ObjectArrayDump(array) => {
+            line.str("type", "object_array")?;
+            line.num("timestamp", timestamp)?;
+            line.id("object_id", array.object_id)?;
+            line.id("class_id", array.element_class_id)?;
+            line.opt_str(
+                "class_name",
+                registry.java_class_name(array.element_class_id).as_deref(),
+            )?;
+            line.num("stack_trace_serial", array.stack_trace_serial)?;
+            line.num("length", array.num_elements)?;
+            match &array.values {
+                Some(values) if array_values => {
+                    write_array(line.key("values")?, values, |out, id| write_ref(out, *id))
+                }
+                _ => Ok(()),
+            }
+        }
+        DumpRecord::PrimitiveArrayDump(array) => {
+            line.str("type", "primitive_array")?;
+            line.num("timestamp", timestamp)?;
+            line.id("object_id", array.object_id)?;
+            line.str("element_type", array.elem_type.java_name())?;
+            line.num("stack_trace_serial", array.stack_trace_serial)?;
+            line.num("length", array.num_elements)?;
+            match &array.values {
+                Some(values) if array_values => write_array_value(line.key("values")?, values),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_json_lines() {
+        let mut dump = TestDump::new();
+        let point = dump.class(
+            "com/acme/Point",
+            None,
+            &[
+                ("x", FieldType::Double),
+                ("label", FieldType::Char),
+                ("next", FieldType::Object),
+            ],
+        );
+        let object_id = dump.instance(
+            point,
+            &[
+                FieldValue::Double(f64::NAN),
+                FieldValue::Char(u16::from(b'"')),
+                FieldValue::Object(id(0)),
+            ],
+        );
+        dump.primitive_array(ArrayValue::Int(vec![1, -2]));
+        dump.dump(DumpRecord::RootJavaFrame {
+            obj_id: object_id,
+            thread_serial: 1,
+            frame_number: 2,
+        });
+
+        let mut writer = JsonLinesWriter::new(Vec::new());
+        let count = writer.write_records(dump.records()).unwrap();
+        let text = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len() as u64, count);
+        let instance = lines
+            .iter()
+            .find(|line| line.starts_with("{\"type\":\"instance\""))
+            .unwrap();
+        assert_eq!(
+            *instance,
+            format!(
+                "{{\"type\":\"instance\",\"timestamp\":0,\"object_id\":\"{}\",\
+                 \"class_id\":\"{}\",\"class_name\":\"com.acme.Point\",\
+                 \"stack_trace_serial\":0,\"fields\":[\
+                 {{\"name\":\"x\",\"type\":\"double\",\"value\":\"NaN\"}},\
+                 {{\"name\":\"label\",\"type\":\"char\",\"value\":\"\\\"\"}},\
+                 {{\"name\":\"next\",\"type\":\"Object\",\"value\":null}}]}}",
+                object_id_label(object_id),
+                object_id_label(point)
+            )
+        );
+        assert!(lines.iter().any(|line| line.ends_with(
+            "\"element_type\":\"int\",\"stack_trace_serial\":0,\"length\":2,\"values\":[1,-2]}"
+        )));
+        assert!(lines
+            .last()
+            .unwrap()
+            .contains("\"kind\":\"RootJavaFrame\",\"object_id\":\"0x"));
+
+        let mut writer = JsonLinesWriter::new(Vec::new()).with_array_values(false);
+        writer.write_records(dump.records()).unwrap();
+        let text = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(text.contains("\"length\":2}\n"));
+    }
+}
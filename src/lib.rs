@@ -1,12 +1,51 @@
 #![forbid(unsafe_code)]
 
+pub mod alloc_sites;
+pub mod alloc_stacks;
+pub mod array_waste;
+pub mod collection_waste;
+pub mod collections;
+pub mod cpu;
 pub mod decl;
+pub mod descriptor;
+pub mod diff;
+pub mod dominator;
+pub mod graph;
+pub mod graph_export;
+pub mod heap_profile;
+pub mod heapsnapshot;
+pub mod histogram;
+pub mod inbound;
+mod json;
+pub mod jsonl;
+pub mod mapping;
+pub mod objects;
+pub mod paths;
+pub mod pprof;
 mod reader;
 mod records;
+pub mod registry;
+pub mod size;
 pub mod stream;
+pub mod strings;
+pub mod suspects;
+pub mod symbol;
+#[cfg(test)]
+mod testdata;
+pub mod threads;
+pub mod trend;
 mod try_byteorder;
+pub mod value;
 
 #[macro_use]
 extern crate static_assert_macro;
 
+// Lets unit tests use `#[derive(FromJavaObject)]`, whose expansion
+// refers to `::hprof_dump_parser`.
+#[cfg(test)]
+extern crate self as hprof_dump_parser;
+
 pub use stream::{MemoryHprofIterator, ReadHprofIterator, StreamHprofReader};
+
+#[cfg(feature = "derive")]
+pub use hprof_dump_parser_derive::FromJavaObject;
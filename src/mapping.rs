@@ -0,0 +1,492 @@
+#![forbid(unsafe_code)]
+
+/*!
+Mapping Java objects onto Rust values.
+
+`FromJavaObject` is usually derived with `#[derive(FromJavaObject)]`
+(enable the `derive` feature):
+
+```ignore
+#[derive(FromJavaObject)]
+#[hprof(class = "com.acme.Session")]
+struct Session {
+    #[hprof(field = "userId")]
+    user_id: i64,
+    name: Option<String>,
+    attributes: Vec<Attribute>,
+}
+```
+
+Fields are looked up by name (the Rust field name unless overridden
+with `#[hprof(field = "...")]`); primitives, `String`, `Vec<T>` for
+arrays, `Option<T>` for nullable references, `Box<T>`, raw `Id`s and
+other derived types are supported.
+ */
+
+use crate::decl::*;
+use crate::objects::{ObjectRef, ObjectStore};
+use crate::strings::java_string;
+use std::fmt;
+
+/// Error of mapping Java object onto Rust value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MappingError {
+    /// Object is not found in the dump.
+    UnknownObject(Id),
+    /// Object's class differs from expected one.
+    ClassMismatch {
+        object_id: Id,
+        expected: String,
+        found: String,
+    },
+    /// Class has no field with such name.
+    MissingField { class: String, field: String },
+    /// Value is of a different type.
+    TypeMismatch {
+        expected: &'static str,
+        found: String,
+    },
+    /// Null reference for a non-`Option` value.
+    UnexpectedNull { expected: &'static str },
+    /// Array contents were not loaded by the parser.
+    ArrayNotLoaded(Id),
+    /// Error while mapping a field.
+    InField {
+        class: String,
+        field: String,
+        error: Box<MappingError>,
+    },
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingError::UnknownObject(id) => {
+                write!(f, "object {:#x} not found in dump", u64::from(*id))
+            }
+            MappingError::ClassMismatch {
+                object_id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "object {:#x} is {}, expected {}",
+                u64::from(*object_id),
+                found,
+                expected
+            ),
+            MappingError::MissingField { class, field } => {
+                write!(f, "class {} has no field {}", class, field)
+            }
+            MappingError::TypeMismatch { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            MappingError::UnexpectedNull { expected } => {
+                write!(f, "expected {}, found null", expected)
+            }
+            MappingError::ArrayNotLoaded(id) => {
+                write!(f, "array {:#x} contents were not loaded", u64::from(*id))
+            }
+            MappingError::InField {
+                class,
+                field,
+                error,
+            } => write!(f, "{}.{}: {}", class, field, error),
+        }
+    }
+}
+
+impl std::error::Error for MappingError {}
+
+/// Type that can be created from a Java instance.
+pub trait FromJavaObject: Sized {
+    fn from_java_object(store: &ObjectStore, id: Id) -> Result<Self, MappingError>;
+}
+
+/// Type that can be created from a field value or an array element.
+pub trait FromJavaValue: Sized {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError>;
+
+    /// Convert primitive array contents; `None` if the array is of
+    /// another type.  Only primitive types implement it.
+    #[doc(hidden)]
+    fn from_primitive_array(_values: &ArrayValue) -> Option<Result<Vec<Self>, MappingError>> {
+        None
+    }
+}
+
+fn value_type_name(value: &FieldValue) -> String {
+    match value {
+        FieldValue::Bool(_) => "boolean",
+        FieldValue::Byte(_) => "byte",
+        FieldValue::Char(_) => "char",
+        FieldValue::Short(_) => "short",
+        FieldValue::Int(_) => "int",
+        FieldValue::Long(_) => "long",
+        FieldValue::Float(_) => "float",
+        FieldValue::Double(_) => "double",
+        FieldValue::Object(_) => "object",
+    }
+    .to_string()
+}
+
+fn object_type_name(store: &ObjectStore, id: Id) -> String {
+    match store.get(id) {
+        Some(ObjectRef::Instance(instance)) => store
+            .registry()
+            .java_class_name(instance.class_object_id)
+            .unwrap_or_else(|| "unknown class".to_string()),
+        Some(ObjectRef::Class(_)) => "java.lang.Class".to_string(),
+        Some(ObjectRef::ObjectArray(_)) => "object array".to_string(),
+        Some(ObjectRef::PrimitiveArray(array)) => {
+            format!("{:?} array", array.elem_type).to_lowercase()
+        }
+        None => "unknown object".to_string(),
+    }
+}
+
+macro_rules! impl_primitive {
+    ($ty:ty, $name:literal, $variant:ident) => {
+        impl FromJavaValue for $ty {
+            fn from_java_value(
+                _store: &ObjectStore,
+                value: FieldValue,
+            ) -> Result<Self, MappingError> {
+                match value {
+                    FieldValue::$variant(v) => Ok(v),
+                    other => Err(MappingError::TypeMismatch {
+                        expected: $name,
+                        found: value_type_name(&other),
+                    }),
+                }
+            }
+
+            fn from_primitive_array(
+                values: &ArrayValue,
+            ) -> Option<Result<Vec<Self>, MappingError>> {
+                match values {
+                    ArrayValue::$variant(v) => Some(Ok(v.clone())),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_primitive!(bool, "boolean", Bool);
+impl_primitive!(i8, "byte", Byte);
+impl_primitive!(u16, "char", Char);
+impl_primitive!(i16, "short", Short);
+impl_primitive!(i32, "int", Int);
+impl_primitive!(i64, "long", Long);
+impl_primitive!(f32, "float", Float);
+impl_primitive!(f64, "double", Double);
+
+fn java_char(code: u16) -> Result<char, MappingError> {
+    std::char::from_u32(code.into()).ok_or_else(|| MappingError::TypeMismatch {
+        expected: "char",
+        found: format!("surrogate {:#x}", code),
+    })
+}
+
+impl FromJavaValue for char {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        java_char(u16::from_java_value(store, value)?)
+    }
+
+    fn from_primitive_array(values: &ArrayValue) -> Option<Result<Vec<Self>, MappingError>> {
+        match values {
+            ArrayValue::Char(v) => Some(v.iter().copied().map(java_char).collect()),
+            _ => None,
+        }
+    }
+}
+
+/// Raw reference, null included.
+impl FromJavaValue for Id {
+    fn from_java_value(_store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        match value {
+            FieldValue::Object(id) => Ok(id),
+            other => Err(MappingError::TypeMismatch {
+                expected: "object",
+                found: value_type_name(&other),
+            }),
+        }
+    }
+}
+
+impl FromJavaValue for String {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        let id = non_null_reference(value, "java.lang.String")?;
+        java_string(store, id).ok_or_else(|| match store.get(id) {
+            None => MappingError::UnknownObject(id),
+            Some(ObjectRef::Instance(instance))
+                if store
+                    .registry()
+                    .has_name(instance.class_object_id, "java.lang.String") =>
+            {
+                MappingError::ArrayNotLoaded(id)
+            }
+            Some(_) => MappingError::ClassMismatch {
+                object_id: id,
+                expected: "java.lang.String".to_string(),
+                found: object_type_name(store, id),
+            },
+        })
+    }
+}
+
+impl<T: FromJavaValue> FromJavaValue for Option<T> {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        match value {
+            FieldValue::Object(id) if u64::from(id) == 0 => Ok(None),
+            other => T::from_java_value(store, other).map(Some),
+        }
+    }
+}
+
+impl<T: FromJavaValue> FromJavaValue for Box<T> {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        T::from_java_value(store, value).map(Box::new)
+    }
+}
+
+impl<T: FromJavaValue> FromJavaValue for Vec<T> {
+    fn from_java_value(store: &ObjectStore, value: FieldValue) -> Result<Self, MappingError> {
+        let id = non_null_reference(value, "array")?;
+        match store.get(id) {
+            Some(ObjectRef::ObjectArray(array)) => array
+                .values
+                .as_ref()
+                .ok_or(MappingError::ArrayNotLoaded(id))?
+                .iter()
+                .map(|elt| T::from_java_value(store, FieldValue::Object(*elt)))
+                .collect(),
+            Some(ObjectRef::PrimitiveArray(array)) => {
+                let values = array
+                    .values
+                    .as_ref()
+                    .ok_or(MappingError::ArrayNotLoaded(id))?;
+                T::from_primitive_array(values).unwrap_or_else(|| {
+                    Err(MappingError::TypeMismatch {
+                        expected: "array",
+                        found: object_type_name(store, id),
+                    })
+                })
+            }
+            Some(_) => Err(MappingError::TypeMismatch {
+                expected: "array",
+                found: object_type_name(store, id),
+            }),
+            None => Err(MappingError::UnknownObject(id)),
+        }
+    }
+}
+
+fn non_null_reference(value: FieldValue, expected: &'static str) -> Result<Id, MappingError> {
+    match value {
+        FieldValue::Object(id) if u64::from(id) == 0 => {
+            Err(MappingError::UnexpectedNull { expected })
+        }
+        FieldValue::Object(id) => Ok(id),
+        other => Err(MappingError::TypeMismatch {
+            expected,
+            found: value_type_name(&other),
+        }),
+    }
+}
+
+/// Find an instance and check its class.  `class_name` is a Java binary
+/// name; instances of subclasses are accepted.  Used by the derived code.
+pub fn instance_of<'a>(
+    store: &'a ObjectStore,
+    id: Id,
+    class_name: Option<&str>,
+) -> Result<&'a InstanceDump, MappingError> {
+    let instance = match store.get(id) {
+        Some(ObjectRef::Instance(instance)) => instance,
+        Some(_) => {
+            return Err(MappingError::ClassMismatch {
+                object_id: id,
+                expected: class_name.unwrap_or("instance").to_string(),
+                found: object_type_name(store, id),
+            })
+        }
+        None => return Err(MappingError::UnknownObject(id)),
+    };
+    if let Some(class_name) = class_name {
+        if !store
+            .registry()
+            .is_subclass_of(instance.class_object_id, class_name)
+        {
+            return Err(MappingError::ClassMismatch {
+                object_id: id,
+                expected: class_name.to_string(),
+                found: object_type_name(store, id),
+            });
+        }
+    }
+    Ok(instance)
+}
+
+/// Map a field of the instance by its name.  Used by the derived code.
+pub fn field<T: FromJavaValue>(
+    store: &ObjectStore,
+    instance: &InstanceDump,
+    name: &str,
+) -> Result<T, MappingError> {
+    let class = || object_type_name(store, instance.object_id);
+    let value = store
+        .field(instance, name)
+        .ok_or_else(|| MappingError::MissingField {
+            class: class(),
+            field: name.to_string(),
+        })?;
+    T::from_java_value(store, value).map_err(|error| MappingError::InField {
+        class: class(),
+        field: name.to_string(),
+        error: Box::new(error),
+    })
+}
+
+/// Map a non-null reference with `FromJavaObject`.  Used by the
+/// derived code for `FromJavaValue` implementation.
+pub fn object_value<T: FromJavaObject>(
+    store: &ObjectStore,
+    value: FieldValue,
+) -> Result<T, MappingError> {
+    T::from_java_object(store, non_null_reference(value, "object")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+    use hprof_dump_parser_derive::FromJavaObject;
+
+    #[derive(Debug, FromJavaObject)]
+    #[hprof(class = "com.acme.User")]
+    struct User {
+        login: String,
+    }
+
+    #[derive(Debug, FromJavaObject)]
+    #[hprof(class = "com.acme.Session")]
+    struct Session {
+        #[hprof(field = "userId")]
+        user_id: i64,
+        user: User,
+        previous: Option<Box<Session>>,
+        scores: Vec<i32>,
+        tags: Vec<Option<String>>,
+        initials: Vec<char>,
+    }
+
+    #[derive(Debug, FromJavaObject)]
+    #[hprof(class = "com.acme.Session")]
+    struct BrokenSession {
+        #[hprof(field = "sessionId")]
+        _session_id: i64,
+    }
+
+    fn sample() -> (ObjectStore, Id, Id) {
+        let mut dump = TestDump::new();
+        let user = dump.class("com/acme/User", None, &[("login", FieldType::Object)]);
+        let session = dump.class(
+            "com/acme/Session",
+            None,
+            &[
+                ("userId", FieldType::Long),
+                ("user", FieldType::Object),
+                ("previous", FieldType::Object),
+                ("scores", FieldType::Object),
+                ("tags", FieldType::Object),
+                ("initials", FieldType::Object),
+            ],
+        );
+        let strings = dump.class("[Ljava/lang/String;", None, &[]);
+
+        let login = dump.java_string("alice");
+        let tag = dump.java_string("x");
+        let alice = dump.instance(user, &[FieldValue::Object(login)]);
+        let scores = dump.primitive_array(ArrayValue::Int(vec![1, 2, 3]));
+        let tags = dump.object_array(strings, &[tag, id(0)]);
+        let initials = dump.primitive_array(ArrayValue::Char(vec![b'A' as u16, b'L' as u16]));
+        let old = dump.instance(
+            session,
+            &[
+                FieldValue::Long(1),
+                FieldValue::Object(alice),
+                FieldValue::Object(id(0)),
+                FieldValue::Object(scores),
+                FieldValue::Object(tags),
+                FieldValue::Object(initials),
+            ],
+        );
+        let current = dump.instance(
+            session,
+            &[
+                FieldValue::Long(42),
+                FieldValue::Object(alice),
+                FieldValue::Object(old),
+                FieldValue::Object(scores),
+                FieldValue::Object(tags),
+                FieldValue::Object(initials),
+            ],
+        );
+        (dump.store(), current, alice)
+    }
+
+    #[test]
+    fn test_derive_nested() {
+        let (store, current, _) = sample();
+        let session = Session::from_java_object(&store, current).unwrap();
+
+        assert_eq!(session.user_id, 42);
+        assert_eq!(session.user.login, "alice");
+        assert_eq!(session.scores, vec![1, 2, 3]);
+        assert_eq!(session.tags, vec![Some("x".to_string()), None]);
+        assert_eq!(session.initials, vec!['A', 'L']);
+        let previous = session.previous.unwrap();
+        assert_eq!(previous.user_id, 1);
+        assert!(previous.previous.is_none());
+    }
+
+    #[test]
+    fn test_char_array_surrogate() {
+        let mut dump = TestDump::new();
+        let chars = dump.primitive_array(ArrayValue::Char(vec![b'a' as u16, 0xd800]));
+        let store = dump.store();
+        match Vec::<char>::from_java_value(&store, FieldValue::Object(chars)) {
+            Err(MappingError::TypeMismatch { expected, found }) => {
+                assert_eq!(expected, "char");
+                assert_eq!(found, "surrogate 0xd800");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_derive_class_mismatch() {
+        let (store, _, alice) = sample();
+        match Session::from_java_object(&store, alice) {
+            Err(MappingError::ClassMismatch {
+                expected, found, ..
+            }) => {
+                assert_eq!(expected, "com.acme.Session");
+                assert_eq!(found, "com.acme.User");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_derive_missing_field() {
+        let (store, current, _) = sample();
+        let err = BrokenSession::from_java_object(&store, current).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "class com.acme.Session has no field sessionId"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+#![forbid(unsafe_code)]
+
+use crate::decl::*;
+use crate::registry::ClassRegistry;
+use std::collections::HashMap;
+
+/**
+In-memory object store: all instances and arrays of a dump, indexed
+by object id, together with the class registry.
+
+It keeps every object of the dump, so it needs roughly as much memory
+as the dump itself; it is intended for dumps of moderate size or for
+domain-specific analyses that need field values.
+ */
+#[derive(Debug, Default)]
+pub struct ObjectStore {
+    registry: ClassRegistry,
+    instances: HashMap<Id, InstanceDump>,
+    object_arrays: HashMap<Id, ObjectArrayDump>,
+    primitive_arrays: HashMap<Id, PrimitiveArrayDump>,
+}
+
+/// Reference to an object in the `ObjectStore`.
+#[derive(Clone, Copy, Debug)]
+pub enum ObjectRef<'a> {
+    Class(&'a ClassDescription),
+    Instance(&'a InstanceDump),
+    ObjectArray(&'a ObjectArrayDump),
+    PrimitiveArray(&'a PrimitiveArrayDump),
+}
+
+impl ObjectStore {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect all records.  Both `ReadHprofIterator` and
+    /// `MemoryHprofIterator` can be used as a source.
+    pub fn from_records<I, S>(records: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut store = Self::new();
+        for rec in records {
+            let (_, record) = rec?;
+            store.add_record(record);
+        }
+        Ok(store)
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: Record<S>) {
+        self.registry.add_record(&record);
+        if let Record::Dump(dump) = record {
+            match dump {
+                DumpRecord::InstanceDump(instance) => {
+                    self.instances.insert(instance.object_id, instance);
+                }
+                DumpRecord::ObjectArrayDump(array) => {
+                    self.object_arrays.insert(array.object_id, array);
+                }
+                DumpRecord::PrimitiveArrayDump(array) => {
+                    self.primitive_arrays.insert(array.object_id, array);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &ClassRegistry {
+        &self.registry
+    }
+
+    pub fn get(&self, id: Id) -> Option<ObjectRef<'_>> {
+        if let Some(instance) = self.instances.get(&id) {
+            Some(ObjectRef::Instance(instance))
+        } else if let Some(array) = self.object_arrays.get(&id) {
+            Some(ObjectRef::ObjectArray(array))
+        } else if let Some(array) = self.primitive_arrays.get(&id) {
+            Some(ObjectRef::PrimitiveArray(array))
+        } else {
+            self.registry.class(id).map(ObjectRef::Class)
+        }
+    }
+
+    #[inline]
+    pub fn instance(&self, id: Id) -> Option<&InstanceDump> {
+        self.instances.get(&id)
+    }
+
+    #[inline]
+    pub fn object_array(&self, id: Id) -> Option<&ObjectArrayDump> {
+        self.object_arrays.get(&id)
+    }
+
+    #[inline]
+    pub fn primitive_array(&self, id: Id) -> Option<&PrimitiveArrayDump> {
+        self.primitive_arrays.get(&id)
+    }
+
+    pub fn instances(&self) -> impl Iterator<Item = &InstanceDump> {
+        self.instances.values()
+    }
+
+    pub fn object_arrays(&self) -> impl Iterator<Item = &ObjectArrayDump> {
+        self.object_arrays.values()
+    }
+
+    pub fn primitive_arrays(&self) -> impl Iterator<Item = &PrimitiveArrayDump> {
+        self.primitive_arrays.values()
+    }
+
+    /// Field value by name.  Fields of a subclass shadow fields of its
+    /// superclasses with the same name.
+    pub fn field(&self, instance: &InstanceDump, name: &str) -> Option<FieldValue> {
+        instance
+            .values
+            .iter()
+            .find(|(info, _)| self.registry.field_name(info) == Some(name))
+            .map(|(_, value)| *value)
+    }
+
+    /// Value of an object field by name; `None` if there is no such
+    /// field, it is not a reference or it is null.
+    pub fn object_field(&self, instance: &InstanceDump, name: &str) -> Option<Id> {
+        match self.field(instance, name) {
+            Some(FieldValue::Object(id)) if u64::from(id) != 0 => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Java binary name of the object's class.  Arrays do not have
+    /// class information in dump records, so they get `None`.
+    pub fn class_name_of(&self, id: Id) -> Option<String> {
+        match self.instances.get(&id) {
+            Some(instance) => self.registry.java_class_name(instance.class_object_id),
+            None => None,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+#![forbid(unsafe_code)]
+
+/*!
+Paths from GC roots to an object.
+
+The search goes backwards from the object over the inbound index in
+BFS order, so the shortest paths are found first.  To find `k`
+shortest paths, every object may be visited up to `k` times; paths do
+not contain cycles.  The search does not go through GC roots: the head
+of every path is the only root on it.
+ */
+
+use crate::decl::Id;
+use crate::graph::{EdgeLabel, GcRootKind, HeapGraph, NodeIndex, NodeKind, ReferenceKind};
+use crate::inbound::InboundIndex;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+const NO_PARENT: u32 = u32::MAX;
+
+/// An object on a path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PathStep {
+    pub node: NodeIndex,
+    pub object_id: Id,
+    /// See `HeapGraph::type_name`.
+    pub class_name: String,
+    /// Field name or `[index]` referencing the next object; `None` for
+    /// the last step, the object itself.
+    pub via: Option<String>,
+}
+
+/// Path from a GC root to an object.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RootPath {
+    /// Root kinds of the first object.
+    pub roots: Vec<GcRootKind>,
+    /// Steps from the root to the object.
+    pub steps: Vec<PathStep>,
+}
+
+/**
+Query for paths to GC roots.
+
+```no_run
+# use hprof_dump_parser::graph::HeapGraph;
+# use hprof_dump_parser::inbound::InboundIndex;
+# use hprof_dump_parser::paths::PathFinder;
+# fn paths(graph: &HeapGraph, node: u32) -> std::io::Result<()> {
+let inbound = InboundIndex::build(graph);
+let paths = PathFinder::new(graph, &inbound)
+    .with_max_paths(3)
+    .with_exclude_weak_references(true)
+    .find(node)?;
+# Ok(())
+# }
+```
+ */
+#[derive(Debug)]
+pub struct PathFinder<'a> {
+    graph: &'a HeapGraph,
+    inbound: &'a InboundIndex,
+    max_paths: usize,
+    exclude_weak_references: bool,
+}
+
+impl<'a> PathFinder<'a> {
+    /// Query for the shortest path only, including weak references.
+    pub fn new(graph: &'a HeapGraph, inbound: &'a InboundIndex) -> Self {
+        Self {
+            graph,
+            inbound,
+            max_paths: 1,
+            exclude_weak_references: false,
+        }
+    }
+
+    /// Find up to `max_paths` shortest paths.
+    pub fn with_max_paths(mut self, max_paths: usize) -> Self {
+        self.max_paths = max_paths.max(1);
+        self
+    }
+
+    /// Ignore `referent` fields of soft, weak, phantom and final
+    /// references.
+    pub fn with_exclude_weak_references(mut self, exclude: bool) -> Self {
+        self.exclude_weak_references = exclude;
+        self
+    }
+
+    /// Shortest paths to the node, shortest first.  Empty if the node
+    /// is not reachable from GC roots.
+    pub fn find(&self, target: NodeIndex) -> io::Result<Vec<RootPath>> {
+        let mut paths = Vec::new();
+        // Search tree: node and the entry of the next object towards
+        // the target.
+        let mut entries: Vec<(NodeIndex, u32)> = vec![(target, NO_PARENT)];
+        let mut visits: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut reference_classes: HashMap<NodeIndex, bool> = HashMap::new();
+        let mut queue = VecDeque::from(vec![0u32]);
+        visits.insert(target, 1);
+
+        while let Some(entry) = queue.pop_front() {
+            let node = entries[entry as usize].0;
+            if self.graph.is_root(node) {
+                paths.push(self.path(entry, &entries));
+                if paths.len() == self.max_paths {
+                    break;
+                }
+                continue;
+            }
+            let referrers = self.inbound.referrers(node)?;
+            let mut prev = None;
+            for referrer in referrers.iter().copied() {
+                if prev == Some(referrer) {
+                    continue;
+                }
+                prev = Some(referrer);
+                let count = visits.entry(referrer).or_insert(0);
+                if *count >= self.max_paths
+                    || on_path(&entries, entry, referrer)
+                    || (self.exclude_weak_references
+                        && self.label(referrer, node, &mut reference_classes).is_none())
+                {
+                    continue;
+                }
+                *count += 1;
+                entries.push((referrer, entry));
+                queue.push_back(entries.len() as u32 - 1);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn path(&self, head: u32, entries: &[(NodeIndex, u32)]) -> RootPath {
+        let mut steps = Vec::new();
+        let mut reference_classes = HashMap::new();
+        let mut entry = head;
+        while entry != NO_PARENT {
+            let (node, next) = entries[entry as usize];
+            let via = match next {
+                NO_PARENT => None,
+                next => self
+                    .label(node, entries[next as usize].0, &mut reference_classes)
+                    .map(|label| label.to_string()),
+            };
+            steps.push(PathStep {
+                node,
+                object_id: self.graph.id(node),
+                class_name: self.graph.type_name(node),
+                via,
+            });
+            entry = next;
+        }
+        RootPath {
+            roots: self
+                .graph
+                .roots_of(entries[head as usize].0)
+                .iter()
+                .map(|root| root.kind)
+                .collect(),
+            steps,
+        }
+    }
+
+    /// Label of the first edge from `from` to `to` the query follows.
+    fn label(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+        reference_classes: &mut HashMap<NodeIndex, bool>,
+    ) -> Option<EdgeLabel<'a>> {
+        let graph = self.graph;
+        let weak = self.exclude_weak_references
+            && graph.kind(from) == NodeKind::Instance
+            && graph.class_of(from).is_some_and(|class| {
+                *reference_classes.entry(class).or_insert_with(|| {
+                    ReferenceKind::of_class(graph.registry(), graph.id(class)).is_some()
+                })
+            });
+        graph
+            .edge_labels_between(from, to)
+            .find(|label| !(weak && *label == EdgeLabel::Field("referent")))
+    }
+}
+
+/// Whether the node is on the path from the entry to the target.
+fn on_path(entries: &[(NodeIndex, u32)], mut entry: u32, node: NodeIndex) -> bool {
+    while entry != NO_PARENT {
+        let (entry_node, next) = entries[entry as usize];
+        if entry_node == node {
+            return true;
+        }
+        entry = next;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::graph::tests::sample_dump;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_paths_to_roots() {
+        let (mut dump, ids) = sample_dump();
+        let leaf = ids[4];
+        let reference = dump.class(
+            "java/lang/ref/Reference",
+            None,
+            &[("referent", FieldType::Object)],
+        );
+        let weak = dump.class("java/lang/ref/WeakReference", Some(reference), &[]);
+        let weak_ref = dump.instance(weak, &[FieldValue::Object(leaf)]);
+        dump.dump(DumpRecord::RootJavaFrame {
+            obj_id: weak_ref,
+            thread_serial: 1,
+            frame_number: 0,
+        });
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let target = graph.index_of(leaf).unwrap();
+
+        let all = PathFinder::new(&graph, &inbound)
+            .with_max_paths(5)
+            .find(target)
+            .unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(
+            all[0].roots,
+            vec![GcRootKind::JavaFrame {
+                thread_serial: 1,
+                frame_number: 0
+            }]
+        );
+        assert_eq!(all[0].steps[0].via.as_deref(), Some("referent"));
+
+        let strong = PathFinder::new(&graph, &inbound)
+            .with_max_paths(5)
+            .with_exclude_weak_references(true)
+            .find(target)
+            .unwrap();
+        assert_eq!(strong.len(), 1);
+        assert_eq!(strong[0].roots, vec![GcRootKind::JniGlobal]);
+        let steps: Vec<(&str, Option<&str>)> = strong[0]
+            .steps
+            .iter()
+            .map(|step| (step.class_name.as_str(), step.via.as_deref()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("com.acme.Holder", Some("items")),
+                ("com.acme.Item[]", Some("[0]")),
+                ("com.acme.Item", Some("next")),
+                ("java.lang.Object", None),
+            ]
+        );
+        assert_eq!(strong[0].steps[3].object_id, leaf);
+    }
+}
@@ -0,0 +1,379 @@
+#![forbid(unsafe_code)]
+
+/*!
+Writer of the pprof `Profile` protobuf message (`profile.proto` of
+<https://github.com/google/pprof>).
+
+The message is encoded by hand, without compression; `pprof` and
+most profile viewers accept both plain and gzipped files.  Strings,
+functions and locations are deduplicated by the builder.
+ */
+
+use crate::symbol::{LineNumber, StackFrame};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+/// Protobuf message encoder.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.raw_varint(u64::from(field) << 3 | wire_type);
+    }
+
+    /// `uint64` or `int64` field; zero is the default and is omitted.
+    fn varint(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.key(field, WIRE_VARINT);
+            self.raw_varint(value);
+        }
+    }
+
+    fn int64(&mut self, field: u32, value: i64) {
+        self.varint(field, value as u64);
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, WIRE_LEN);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn message(&mut self, field: u32, message: &Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed<I: IntoIterator<Item = u64>>(&mut self, field: u32, values: I) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.raw_varint(value);
+        }
+        if !packed.0.is_empty() {
+            self.bytes(field, &packed.0);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Function {
+    name: i64,
+    system_name: i64,
+    filename: i64,
+}
+
+#[derive(Clone, Debug)]
+struct Sample {
+    /// Leaf first.
+    location_ids: Vec<u64>,
+    values: Vec<i64>,
+}
+
+/// Builder of a pprof profile.
+#[derive(Clone, Debug)]
+pub struct ProfileBuilder {
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    sample_types: Vec<(i64, i64)>,
+    default_sample_type: i64,
+    period_type: Option<(i64, i64)>,
+    period: i64,
+    time_nanos: i64,
+    functions: Vec<Function>,
+    function_ids: HashMap<(i64, i64, i64), u64>,
+    /// `(function_id, line)`, the id is the index plus one.
+    locations: Vec<(u64, i64)>,
+    location_ids: HashMap<(u64, i64), u64>,
+    samples: Vec<Sample>,
+}
+
+impl ProfileBuilder {
+    /// New profile with `(type, unit)` pairs of sample values, e.g.
+    /// `("samples", "count")`.
+    pub fn new(sample_types: &[(&str, &str)]) -> Self {
+        let mut builder = Self {
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            sample_types: Vec::new(),
+            default_sample_type: 0,
+            period_type: None,
+            period: 0,
+            time_nanos: 0,
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+            samples: Vec::new(),
+        };
+        // The string table must start with the empty string.
+        builder.string("");
+        builder.sample_types = sample_types
+            .iter()
+            .map(|(sample_type, unit)| (builder.string(sample_type), builder.string(unit)))
+            .collect();
+        builder
+    }
+
+    /// Sampling period, e.g. `("cpu", "nanoseconds", 10_000_000)`.
+    pub fn with_period(mut self, period_type: &str, unit: &str, period: i64) -> Self {
+        self.period_type = Some((self.string(period_type), self.string(unit)));
+        self.period = period;
+        self
+    }
+
+    /// Sample type shown by default; the viewers pick the last one
+    /// otherwise.
+    pub fn with_default_sample_type(mut self, sample_type: &str) -> Self {
+        self.default_sample_type = self.string(sample_type);
+        self
+    }
+
+    /// Collection time, nanoseconds since the epoch.
+    pub fn with_time_nanos(mut self, time_nanos: i64) -> Self {
+        self.time_nanos = time_nanos;
+        self
+    }
+
+    fn string(&mut self, s: &str) -> i64 {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as i64;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    /// Location id of a function and line; `line` is zero if unknown.
+    pub fn location(
+        &mut self,
+        function_name: &str,
+        system_name: &str,
+        filename: Option<&str>,
+        line: i64,
+    ) -> u64 {
+        let function = Function {
+            name: self.string(function_name),
+            system_name: self.string(system_name),
+            filename: self.string(filename.unwrap_or_default()),
+        };
+        let functions = &mut self.functions;
+        let function_id = *self
+            .function_ids
+            .entry((function.name, function.system_name, function.filename))
+            .or_insert_with(|| {
+                functions.push(function);
+                functions.len() as u64
+            });
+        let locations = &mut self.locations;
+        *self
+            .location_ids
+            .entry((function_id, line))
+            .or_insert_with(|| {
+                locations.push((function_id, line));
+                locations.len() as u64
+            })
+    }
+
+    /// Location of a Java stack frame; the function is named
+    /// `class.method`, the system name has the signature appended.
+    pub fn frame_location(&mut self, frame: Option<&StackFrame>) -> u64 {
+        match frame {
+            Some(frame) => {
+                let name = frame.qualified_name();
+                let line = match frame.line {
+                    LineNumber::Line(line) => line.into(),
+                    _ => 0,
+                };
+                self.location(
+                    &name,
+                    &format!("{}{}", name, frame.signature),
+                    frame.source_file.as_deref(),
+                    line,
+                )
+            }
+            None => self.location("<unknown frame>", "", None, 0),
+        }
+    }
+
+    /// Add a sample with locations leaf first and one value per sample
+    /// type.
+    pub fn add_sample(&mut self, location_ids: Vec<u64>, values: Vec<i64>) {
+        debug_assert_eq!(values.len(), self.sample_types.len());
+        self.samples.push(Sample {
+            location_ids,
+            values,
+        });
+    }
+
+    /// Write the encoded `Profile` message.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut profile = Message::default();
+        for (sample_type, unit) in &self.sample_types {
+            profile.message(1, &value_type(*sample_type, *unit));
+        }
+        for sample in &self.samples {
+            let mut message = Message::default();
+            message.packed(1, sample.location_ids.iter().copied());
+            message.packed(2, sample.values.iter().map(|value| *value as u64));
+            profile.message(2, &message);
+        }
+        for (idx, (function_id, line)) in self.locations.iter().enumerate() {
+            let mut line_message = Message::default();
+            line_message.varint(1, *function_id);
+            line_message.int64(2, *line);
+            let mut message = Message::default();
+            message.varint(1, idx as u64 + 1);
+            message.message(4, &line_message);
+            profile.message(4, &message);
+        }
+        for (idx, function) in self.functions.iter().enumerate() {
+            let mut message = Message::default();
+            message.varint(1, idx as u64 + 1);
+            message.int64(2, function.name);
+            message.int64(3, function.system_name);
+            message.int64(4, function.filename);
+            profile.message(5, &message);
+        }
+        for s in &self.strings {
+            profile.bytes(6, s.as_bytes());
+        }
+        profile.int64(9, self.time_nanos);
+        if let Some((period_type, unit)) = self.period_type {
+            profile.message(11, &value_type(period_type, unit));
+        }
+        profile.int64(12, self.period);
+        profile.int64(14, self.default_sample_type);
+        out.write_all(&profile.0)
+    }
+}
+
+fn value_type(value_type: i64, unit: i64) -> Message {
+    let mut message = Message::default();
+    message.int64(1, value_type);
+    message.int64(2, unit);
+    message
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum Value<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(data: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Decode fields of a message; only varint and length-delimited
+    /// fields are supported.
+    pub(crate) fn fields(data: &[u8]) -> Vec<(u32, Value<'_>)> {
+        let mut pos = 0;
+        let mut fields = Vec::new();
+        while pos < data.len() {
+            let key = read_varint(data, &mut pos);
+            let value = match key & 7 {
+                WIRE_VARINT => Value::Varint(read_varint(data, &mut pos)),
+                WIRE_LEN => {
+                    let len = read_varint(data, &mut pos) as usize;
+                    pos += len;
+                    Value::Bytes(&data[pos - len..pos])
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    pub(crate) fn packed(data: &[u8]) -> Vec<u64> {
+        let mut pos = 0;
+        let mut values = Vec::new();
+        while pos < data.len() {
+            values.push(read_varint(data, &mut pos));
+        }
+        values
+    }
+
+    /// The profile string table.
+    pub(crate) fn strings(profile: &[u8]) -> Vec<&str> {
+        fields(profile)
+            .into_iter()
+            .filter_map(|field| match field {
+                (6, Value::Bytes(s)) => Some(std::str::from_utf8(s).unwrap()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_profile_builder() {
+        let mut builder =
+            ProfileBuilder::new(&[("samples", "count")]).with_period("samples", "count", 1);
+        let main = builder.location("Main.main", "Main.main([Ljava/lang/String;)V", None, 3);
+        let run = builder.location("Main.run", "Main.run()V", Some("Main.java"), 300);
+        assert_eq!(
+            builder.location("Main.main", "Main.main([Ljava/lang/String;)V", None, 3),
+            main
+        );
+        builder.add_sample(vec![run, main], vec![200]);
+        let mut out = Vec::new();
+        builder.write(&mut out).unwrap();
+
+        assert_eq!(
+            strings(&out),
+            vec![
+                "",
+                "samples",
+                "count",
+                "Main.main",
+                "Main.main([Ljava/lang/String;)V",
+                "Main.run",
+                "Main.run()V",
+                "Main.java"
+            ]
+        );
+        let profile = fields(&out);
+        let sample = profile
+            .iter()
+            .find_map(|field| match field {
+                (2, Value::Bytes(sample)) => Some(fields(sample)),
+                _ => None,
+            })
+            .unwrap();
+        match &sample[..] {
+            [(1, Value::Bytes(locations)), (2, Value::Bytes(values))] => {
+                assert_eq!(packed(locations), vec![run, main]);
+                assert_eq!(packed(values), vec![200]);
+            }
+            other => panic!("unexpected sample {:?}", other),
+        }
+        assert_eq!(profile.iter().filter(|(field, _)| *field == 4).count(), 2);
+        assert!(profile.contains(&(12, Value::Varint(1))));
+    }
+}
@@ -112,6 +112,7 @@ pub(crate) trait TakeState<'a, Main> {
     fn reader(&mut self) -> &mut Self::Stream;
 }
 
+#[allow(dead_code)]
 pub(crate) struct MainMemory<'a> {
     data: Memory<'a>,
 }
@@ -142,6 +143,7 @@ impl<'a> MainState<'a, TakeMemory<'a>> for MainMemory<'a> {
     }
 }
 
+#[allow(dead_code)]
 pub(crate) struct TakeMemory<'a> {
     data: Memory<'a>,
     rest: &'a [u8],
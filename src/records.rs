@@ -188,6 +188,37 @@ pub(crate) fn read_0b_end_thread<T: Read>(stream: &mut T) -> Result<EndThreadRec
     })
 }
 
+pub(crate) fn read_0d_cpu_samples<T: Read>(stream: &mut T) -> Result<CpuSamplesRecord, Error> {
+    let total_samples = stream.read_u32::<NetworkEndian>()?;
+    let num_traces = stream.read_u32::<NetworkEndian>()?;
+    let mut traces = Vec::with_capacity(
+        num_traces
+            .try_into()
+            .or(Err(Error::IntegerConversionErrror))?,
+    );
+
+    for _i in 0..num_traces {
+        traces.push(CpuTrace {
+            samples: stream.read_u32::<NetworkEndian>()?,
+            stack_trace_serial: stream.read_u32::<NetworkEndian>()?,
+        });
+    }
+
+    Ok(CpuSamplesRecord {
+        total_samples,
+        traces,
+    })
+}
+
+pub(crate) fn read_0e_control_settings<T: Read>(
+    stream: &mut T,
+) -> Result<ControlSettingsRecord, Error> {
+    Ok(ControlSettingsRecord {
+        flags: stream.read_u32::<NetworkEndian>()?,
+        stack_trace_depth: stream.read_u16::<NetworkEndian>()?,
+    })
+}
+
 pub(crate) fn read_data_ff_root_unknown<T: Read>(
     stream: &mut T,
     id_reader: IdReader,
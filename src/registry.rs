@@ -0,0 +1,206 @@
+#![forbid(unsafe_code)]
+
+use crate::decl::*;
+use crate::descriptor::TypeDescriptor;
+use std::collections::{HashMap, HashSet};
+
+/// Convert an internal class name (`java/lang/String`,
+/// `[Ljava/lang/Object;`) to the Java form used in reports
+/// (`java.lang.String`, `java.lang.Object[]`).
+pub(crate) fn java_name(internal: &str) -> String {
+    match TypeDescriptor::from_class_name(internal) {
+        Ok(descriptor) => descriptor.java_name(),
+        Err(_) => internal.replace('/', "."),
+    }
+}
+
+/**
+Class registry: string dictionary, LOAD_CLASS names and class dumps
+collected from a record stream.
+
+The parser itself keeps only class layouts; the registry additionally
+keeps the id -> string mapping, so class and field names can be
+resolved.  Feed it every record with `ClassRegistry::add_record`.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct ClassRegistry {
+    strings: HashMap<Id, String>,
+    class_name_ids: HashMap<Id, Id>,
+    class_serials: HashMap<SerialNumber, Id>,
+    classes: HashMap<Id, ClassDescription>,
+}
+
+impl ClassRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update registry with a record.  Records that do not describe
+    /// strings or classes are ignored.
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        match record {
+            Record::String(id, data) => {
+                self.strings
+                    .insert(*id, String::from_utf8_lossy(data.as_ref()).into_owned());
+            }
+            Record::LoadClass(class_record) => {
+                self.class_name_ids
+                    .insert(class_record.class_obj_id, class_record.class_name_string_id);
+                self.class_serials
+                    .insert(class_record.serial, class_record.class_obj_id);
+            }
+            Record::Dump(DumpRecord::ClassDump(class_desc)) => {
+                self.classes.insert(class_desc.class_id, class_desc.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// HPROF string by its id.  Invalid UTF-8 sequences are replaced
+    /// with U+FFFD.
+    #[inline]
+    pub fn string(&self, id: Id) -> Option<&str> {
+        self.strings.get(&id).map(String::as_str)
+    }
+
+    /// Class name as it is stored in the dump, i.e. in the internal
+    /// form like `java/lang/String` or `[Ljava/lang/Object;`.
+    pub fn class_name(&self, class_id: Id) -> Option<&str> {
+        self.class_name_ids
+            .get(&class_id)
+            .and_then(|name_id| self.string(*name_id))
+    }
+
+    /// Class name in the Java binary form, like `java.lang.String`.
+    pub fn java_class_name(&self, class_id: Id) -> Option<String> {
+        self.class_name(class_id).map(java_name)
+    }
+
+    /// Class object id by LOAD_CLASS serial number.
+    #[inline]
+    pub fn class_id_by_serial(&self, serial: SerialNumber) -> Option<Id> {
+        self.class_serials.get(&serial).copied()
+    }
+
+    /// Class layout from the CLASS_DUMP record.
+    #[inline]
+    pub fn class(&self, class_id: Id) -> Option<&ClassDescription> {
+        self.classes.get(&class_id)
+    }
+
+    /// Iterate over all dumped classes.
+    pub fn classes(&self) -> impl Iterator<Item = &ClassDescription> {
+        self.classes.values()
+    }
+
+    /// Drop strings that are neither class names nor field names,
+    /// e.g. method names and signatures, which are most of a dump's
+    /// string table.
+    pub fn retain_class_strings(&mut self) {
+        let mut used: HashSet<Id> = self.class_name_ids.values().copied().collect();
+        for class_desc in self.classes.values() {
+            used.extend(
+                class_desc
+                    .static_fields
+                    .iter()
+                    .map(|(info, _)| info.name_id),
+            );
+            used.extend(class_desc.instance_fields.iter().map(|info| info.name_id));
+        }
+        self.strings.retain(|id, _| used.contains(id));
+        self.strings.shrink_to_fit();
+    }
+
+    #[inline]
+    pub fn field_name(&self, field: &FieldInfo) -> Option<&str> {
+        self.string(field.name_id)
+    }
+
+    /// Iterate over class and its superclasses, starting from the
+    /// class itself.  Iteration stops at the first class without
+    /// CLASS_DUMP record, and after as many steps as there are
+    /// classes, so a corrupted dump with a superclass cycle cannot
+    /// loop forever.
+    pub fn superclasses(&self, class_id: Id) -> Superclasses<'_> {
+        Superclasses {
+            registry: self,
+            current: class_id,
+            remaining: self.classes.len(),
+        }
+    }
+
+    /// Check if the class or any of its superclasses has the given Java
+    /// binary name (`java.util.HashMap`) or internal name
+    /// (`java/util/HashMap`).
+    pub fn is_subclass_of(&self, class_id: Id, name: &str) -> bool {
+        self.superclasses(class_id)
+            .any(|class_desc| self.has_name(class_desc.class_id, name))
+    }
+
+    pub(crate) fn has_name(&self, class_id: Id, name: &str) -> bool {
+        match self.class_name(class_id) {
+            Some(internal) => {
+                internal.len() == name.len()
+                    && internal
+                        .bytes()
+                        .zip(name.bytes())
+                        .all(|(a, b)| a == b || (a == b'/' && b == b'.'))
+            }
+            None => false,
+        }
+    }
+}
+
+/// Iterator over class hierarchy, see `ClassRegistry::superclasses`.
+pub struct Superclasses<'a> {
+    registry: &'a ClassRegistry,
+    current: Id,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Superclasses<'a> {
+    type Item = &'a ClassDescription;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if u64::from(self.current) == 0 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let class_desc = self.registry.class(self.current);
+        self.current = match class_desc {
+            Some(class_desc) => class_desc.super_class_object_id,
+            None => Id::from(0u64),
+        };
+        class_desc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_superclass_cycle() {
+        let mut dump = TestDump::new();
+        let base = dump.class("com/acme/Base", None, &[]);
+        let derived = dump.class("com/acme/Derived", Some(base), &[]);
+        let mut registry = ClassRegistry::new();
+        for item in dump.records() {
+            registry.add_record(&item.unwrap().1);
+        }
+        registry
+            .classes
+            .get_mut(&base)
+            .unwrap()
+            .super_class_object_id = derived;
+
+        let chain: Vec<Id> = registry
+            .superclasses(derived)
+            .map(|class_desc| class_desc.class_id)
+            .collect();
+        assert_eq!(chain, vec![derived, base]);
+        assert!(!registry.is_subclass_of(derived, "java.lang.Object"));
+    }
+}
@@ -0,0 +1,181 @@
+#![forbid(unsafe_code)]
+
+/*!
+Heap footprint of objects.
+
+The dump records field data and array elements only, and records
+references with the dump's id size, which is the pointer size even if
+the JVM used compressed oops.  `SizeModel` describes the object layout
+of the JVM: object and array headers, reference size and object
+alignment.
+
+Field padding inside objects is not modelled: an object takes header
+plus field data, rounded up to the alignment.
+ */
+
+use crate::decl::{FieldType, Id};
+
+/// Object layout parameters, see module documentation.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SizeModel {
+    /// Object header: mark word and class pointer.
+    pub header_size: u32,
+    /// Array header: object header and length, up to the first
+    /// element.
+    pub array_header_size: u32,
+    /// Size of a reference field or an object array element.
+    pub reference_size: u32,
+    /// Object alignment (`-XX:ObjectAlignmentInBytes`).
+    pub alignment: u32,
+}
+
+/// Heap size addressable by compressed oops with 8-byte alignment.
+const COMPRESSED_OOPS_SPAN: u64 = 32 << 30;
+
+impl SizeModel {
+    /// 32-bit HotSpot.
+    pub const HOTSPOT_32: Self = Self {
+        header_size: 8,
+        array_header_size: 12,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    /// 64-bit HotSpot with compressed oops and compressed class
+    /// pointers, the default for heaps under 32 GB.
+    pub const HOTSPOT_64_COMPRESSED: Self = Self {
+        header_size: 12,
+        array_header_size: 16,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    /// 64-bit HotSpot with compressed oops and 16-byte alignment, used
+    /// for heaps of 32 to 64 GB.
+    pub const HOTSPOT_64_COMPRESSED_ALIGN_16: Self = Self {
+        alignment: 16,
+        ..Self::HOTSPOT_64_COMPRESSED
+    };
+
+    /// 64-bit HotSpot without compressed oops
+    /// (`-XX:-UseCompressedOops -XX:-UseCompressedClassPointers`).
+    pub const HOTSPOT_64: Self = Self {
+        header_size: 16,
+        array_header_size: 24,
+        reference_size: 8,
+        alignment: 8,
+    };
+
+    /**
+    Guess the model from the dump's id size and object addresses.
+
+    With 4-byte ids, it is 32-bit HotSpot.  Otherwise the alignment is
+    16 if no object address has bit 3 set, and compressed oops are
+    assumed if all objects fit in the range compressed oops can
+    address with this alignment (32 GB per 8 bytes of alignment).
+    Object ids of HotSpot dumps are addresses.
+     */
+    pub fn infer<I: IntoIterator<Item = Id>>(id_size: u32, ids: I) -> Self {
+        if id_size <= 4 {
+            return Self::HOTSPOT_32;
+        }
+        let mut min = u64::MAX;
+        let mut max = 0;
+        let mut bits = 0;
+        for id in ids {
+            let id = u64::from(id);
+            min = min.min(id);
+            max = max.max(id);
+            bits |= id;
+        }
+        if min > max {
+            return Self::HOTSPOT_64_COMPRESSED;
+        }
+        let alignment = if bits & 0x8 == 0 { 16 } else { 8 };
+        if max - min < COMPRESSED_OOPS_SPAN / 8 * u64::from(alignment) {
+            Self {
+                alignment,
+                ..Self::HOTSPOT_64_COMPRESSED
+            }
+        } else {
+            Self {
+                alignment,
+                ..Self::HOTSPOT_64
+            }
+        }
+    }
+
+    /// Size of a field or an array element.
+    pub fn field_size(&self, field_type: FieldType) -> u64 {
+        match field_type {
+            FieldType::Object => self.reference_size.into(),
+            other => other.byte_size().unwrap_or(0),
+        }
+    }
+
+    /// Round the size up to the alignment.
+    #[inline]
+    pub fn align(&self, size: u64) -> u64 {
+        let alignment = u64::from(self.alignment.max(1));
+        size.div_ceil(alignment) * alignment
+    }
+
+    /// Size of an instance with `primitive_bytes` of primitive fields
+    /// and `references` reference fields.  Also used for classes with
+    /// their static fields.
+    pub fn instance_size(&self, primitive_bytes: u64, references: u64) -> u64 {
+        self.align(
+            u64::from(self.header_size)
+                + primitive_bytes
+                + references * u64::from(self.reference_size),
+        )
+    }
+
+    pub fn object_array_size(&self, length: u64) -> u64 {
+        self.align(u64::from(self.array_header_size) + length * u64::from(self.reference_size))
+    }
+
+    pub fn primitive_array_size(&self, elem_type: FieldType, length: u64) -> u64 {
+        self.align(u64::from(self.array_header_size) + length * self.field_size(elem_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        let model = SizeModel::HOTSPOT_64_COMPRESSED;
+        // java.lang.Object, Integer, Long.
+        assert_eq!(model.instance_size(0, 0), 16);
+        assert_eq!(model.instance_size(4, 0), 16);
+        assert_eq!(model.instance_size(8, 0), 24);
+        assert_eq!(model.object_array_size(3), 32);
+        assert_eq!(model.primitive_array_size(FieldType::Byte, 0), 16);
+        assert_eq!(model.primitive_array_size(FieldType::Long, 1), 24);
+        assert_eq!(SizeModel::HOTSPOT_64.object_array_size(3), 48);
+        assert_eq!(
+            SizeModel::HOTSPOT_64_COMPRESSED_ALIGN_16.instance_size(8, 0),
+            32
+        );
+    }
+
+    #[test]
+    fn test_infer() {
+        let ids = |ids: &[u64]| ids.iter().map(|id| Id::from(*id)).collect::<Vec<_>>();
+        assert_eq!(SizeModel::infer(4, ids(&[0x1000])), SizeModel::HOTSPOT_32);
+        assert_eq!(
+            SizeModel::infer(8, ids(&[0x7_0000_0008, 0x7_0000_0020])),
+            SizeModel::HOTSPOT_64_COMPRESSED
+        );
+        assert_eq!(
+            SizeModel::infer(8, ids(&[0x7_0000_0010, 0x7_0000_0020])),
+            SizeModel::HOTSPOT_64_COMPRESSED_ALIGN_16
+        );
+        assert_eq!(
+            SizeModel::infer(8, ids(&[0x8, 0x10_0000_0000])),
+            SizeModel::HOTSPOT_64
+        );
+    }
+}
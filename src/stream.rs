@@ -18,18 +18,14 @@ pub struct StreamHprofReader {
     pub load_object_arrays: bool,
 }
 
+#[derive(Default)]
 enum IteratorState<R, T> {
+    #[default]
     Eof,
     InData(Ts, T),
     InNormal(R),
 }
 
-impl<R, T> Default for IteratorState<R, T> {
-    fn default() -> Self {
-        Self::Eof
-    }
-}
-
 impl<R, T> IteratorState<R, T> {
     fn take(&mut self) -> Self {
         std::mem::take(self)
@@ -51,6 +47,7 @@ pub struct ReadHprofIterator<'hprof, R: io::BufRead> {
     iter: StreamHprofIterator<'hprof, 'hprof, MainStream<Stream<R>>, TakeStream<Stream<R>>>,
     pub timestamp: Ts,
     pub banner: String,
+    pub id_size: u32,
 }
 
 impl<'hprof, R: io::BufRead> ReadHprofIterator<'hprof, R> {
@@ -60,6 +57,7 @@ impl<'hprof, R: io::BufRead> ReadHprofIterator<'hprof, R> {
         Self {
             timestamp: iter.timestamp,
             banner: iter.banner.clone(),
+            id_size: iter.id_reader.id_size,
             iter,
         }
     }
@@ -69,6 +67,7 @@ pub struct MemoryHprofIterator<'data, 'hprof> {
     iter: StreamHprofIterator<'data, 'hprof, MainStream<Memory<'data>>, TakeStream<Memory<'data>>>,
     pub timestamp: Ts,
     pub banner: String,
+    pub id_size: u32,
 }
 
 impl<'data, 'hprof> MemoryHprofIterator<'data, 'hprof> {
@@ -83,6 +82,7 @@ impl<'data, 'hprof> MemoryHprofIterator<'data, 'hprof> {
         Self {
             timestamp: iter.timestamp,
             banner: iter.banner.clone(),
+            id_size: iter.id_reader.id_size,
             iter,
         }
     }
@@ -263,6 +263,14 @@ where
                         read_0b_end_thread(stream)
                             .map(|end_thread| (timestamp, Record::EndThread(end_thread))),
                     ),
+                    TAG_CPU_SAMPLES => Some(
+                        read_0d_cpu_samples(stream)
+                            .map(|samples| (timestamp, Record::CpuSamples(samples))),
+                    ),
+                    TAG_CONTROL_SETTINGS => Some(
+                        read_0e_control_settings(stream)
+                            .map(|settings| (timestamp, Record::ControlSettings(settings))),
+                    ),
                     TAG_HEAP_DUMP | TAG_HEAP_DUMP_SEGMENT => {
                         self.state = IteratorState::InData(
                             timestamp,
@@ -440,6 +448,62 @@ mod tests {
     use std::io::BufReader;
     use std::iter::Iterator;
 
+    fn record(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![tag];
+        data.extend_from_slice(&1u32.to_be_bytes()); // Time delta.
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_cpu_samples_and_control_settings() {
+        let mut data = b"JAVA PROFILE 1.0.2\0".to_vec();
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 10]);
+        let mut body = Vec::new();
+        for value in &[10u32, 2, 3, 300_001, 7, 300_002] {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend(record(TAG_CPU_SAMPLES, &body));
+        data.extend(record(TAG_CPU_SAMPLES, &[0, 0, 0, 0, 0, 0, 0, 0]));
+        data.extend(record(TAG_CONTROL_SETTINGS, &[0, 0, 0, 2, 0, 4]));
+
+        let hprof = StreamHprofReader::new();
+        let records = hprof
+            .read_hprof_from_memory(&data)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        match &records[0] {
+            (11, Record::CpuSamples(samples)) => {
+                assert_eq!(samples.total_samples, 10);
+                let traces: Vec<(u32, SerialNumber)> = samples
+                    .traces
+                    .iter()
+                    .map(|trace| (trace.samples, trace.stack_trace_serial))
+                    .collect();
+                assert_eq!(traces, vec![(3, 300_001), (7, 300_002)]);
+            }
+            other => panic!("unexpected record {:?}", other),
+        }
+        match &records[1] {
+            (_, Record::CpuSamples(samples)) => {
+                assert_eq!(samples.total_samples, 0);
+                assert!(samples.traces.is_empty());
+            }
+            other => panic!("unexpected record {:?}", other),
+        }
+        match &records[2] {
+            (_, Record::ControlSettings(settings)) => {
+                assert_eq!(settings.flags, CONTROL_CPU_SAMPLING);
+                assert_eq!(settings.stack_trace_depth, 4);
+            }
+            other => panic!("unexpected record {:?}", other),
+        }
+    }
+
     // Prepare dump before running this test with a tool in ${PROJECT}/java dir
     #[ignore]
     #[test]
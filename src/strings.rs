@@ -0,0 +1,523 @@
+#![forbid(unsafe_code)]
+
+use crate::decl::*;
+use crate::graph::HeapGraph;
+use crate::inbound::InboundIndex;
+use crate::objects::ObjectStore;
+use crate::size::SizeModel;
+use crate::value::{object_id_label, quote_java_string};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::{self, Write};
+
+/// `java.lang.String.coder` value for compact (Latin-1) strings.
+pub const CODER_LATIN1: i8 = 0;
+/// `java.lang.String.coder` value for UTF-16 strings.
+pub const CODER_UTF16: i8 = 1;
+
+/**
+Decode contents of `java.lang.String.value` array.
+
+Before JDK 9 the value is a `char[]`; since JDK 9 it is a `byte[]`
+whose encoding is defined by the `coder` field (`None` is treated as
+Latin-1).  UTF-16 `byte[]` data is stored in the JVM's native byte
+order, which is assumed to be little-endian (x86, AArch64).  Unpaired
+surrogates are replaced with U+FFFD.
+ */
+pub fn decode_string_value(value: &ArrayValue, coder: Option<i8>) -> Option<String> {
+    match value {
+        ArrayValue::Char(chars) => Some(String::from_utf16_lossy(chars)),
+        ArrayValue::Byte(bytes) => Some(match coder.unwrap_or(CODER_LATIN1) {
+            CODER_UTF16 => {
+                let units: Vec<u16> = utf16_units(bytes).collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => bytes.iter().map(|b| *b as u8 as char).collect(),
+        }),
+        _ => None,
+    }
+}
+
+/// Iterate over UTF-16 code units of `String.value` array contents,
+/// regardless of the representation.  Equal strings produce equal
+/// sequences for both `char[]` and compact `byte[]` values.
+pub fn string_code_units<'a>(
+    value: &'a ArrayValue,
+    coder: Option<i8>,
+) -> Option<Box<dyn Iterator<Item = u16> + 'a>> {
+    match value {
+        ArrayValue::Char(chars) => Some(Box::new(chars.iter().copied())),
+        ArrayValue::Byte(bytes) => Some(match coder.unwrap_or(CODER_LATIN1) {
+            CODER_UTF16 => Box::new(utf16_units(bytes)),
+            _ => Box::new(bytes.iter().map(|b| *b as u8 as u16)),
+        }),
+        _ => None,
+    }
+}
+
+fn utf16_units(bytes: &[i8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0] as u8, pair[1] as u8]))
+}
+
+/**
+Decode a `java.lang.String` instance.
+
+Returns `None` if the object is not found, is not a string, or its
+value array is missing or was not loaded (see
+`StreamHprofReader::with_load_primitive_arrays`).  Pre-JDK 7
+`offset`/`count` fields are honored when present.
+ */
+pub fn java_string(store: &ObjectStore, id: Id) -> Option<String> {
+    let instance = store.instance(id)?;
+    if !store
+        .registry()
+        .has_name(instance.class_object_id, "java.lang.String")
+    {
+        return None;
+    }
+    let array = store.primitive_array(store.object_field(instance, "value")?)?;
+    let coder = match store.field(instance, "coder") {
+        Some(FieldValue::Byte(coder)) => Some(coder),
+        _ => None,
+    };
+    let value = array.values.as_ref()?;
+
+    match (
+        value,
+        store.field(instance, "offset"),
+        store.field(instance, "count"),
+    ) {
+        (ArrayValue::Char(chars), Some(FieldValue::Int(offset)), Some(FieldValue::Int(count))) => {
+            let start = (offset.max(0) as usize).min(chars.len());
+            let end = start.saturating_add(count.max(0) as usize).min(chars.len());
+            Some(String::from_utf16_lossy(&chars[start..end]))
+        }
+        _ => decode_string_value(value, coder),
+    }
+}
+
+/// Hash of a string value, equal for equal strings in any
+/// representation.
+fn hash_code_units<I: Iterator<Item = u16>>(units: I) -> (u64, u32) {
+    let mut hasher = DefaultHasher::new();
+    let mut len = 0u32;
+    for unit in units {
+        hasher.write_u16(unit);
+        len += 1;
+    }
+    (hasher.finish(), len)
+}
+
+/// Hashes of a `char[]` or `byte[]` array that may be a string value.
+#[derive(Clone, Copy, Debug)]
+struct ArrayDigest {
+    /// Hash of `char[]`, or of `byte[]` as Latin-1.
+    hash: (u64, u32),
+    /// Hash of `byte[]` as UTF-16.
+    utf16_hash: Option<(u64, u32)>,
+    size: u64,
+}
+
+/// Summary of duplicate strings.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StringStats {
+    /// Strings with loaded values.
+    pub strings: u64,
+    /// Number of different values.
+    pub distinct_values: u64,
+    /// Strings whose value is shared with another string.
+    pub duplicated_strings: u64,
+    /// Memory that deduplication would free.
+    pub wasted_bytes: u64,
+}
+
+/// Strings with equal values.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateGroup {
+    /// Length in UTF-16 code units.
+    pub length: u32,
+    /// `java.lang.String` instances.
+    pub string_ids: Vec<Id>,
+    /// Number of distinct value arrays; strings deduplicated by G1
+    /// share them.
+    pub arrays: u64,
+    /// String instances and arrays beyond the first one.
+    pub wasted_bytes: u64,
+}
+
+/// Referrers of duplicate strings of the same class and field.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateHolder {
+    /// See `HeapGraph::type_name`.
+    pub class_name: String,
+    /// Field name, or `[]` for array elements.
+    pub field: String,
+    /// Duplicate strings referenced.
+    pub strings: u64,
+    /// Share of the groups' wasted bytes: every string of a group
+    /// takes an equal part, split evenly among the holders of the
+    /// string, so the shares add up to the wasted bytes of referenced
+    /// strings.
+    pub wasted_bytes: u64,
+}
+
+/// Duplicate strings, see `StringDuplicatesBuilder`.
+#[derive(Clone, Debug, Default)]
+pub struct StringDuplicates {
+    pub stats: StringStats,
+    /// Groups of at least two strings, most wasted bytes first.
+    pub groups: Vec<DuplicateGroup>,
+}
+
+/**
+Streaming collector of duplicate strings.
+
+`java.lang.String` instances are matched with their value arrays by
+hashes of array contents, so primitive arrays have to be loaded (see
+`StreamHprofReader::with_load_primitive_arrays`).  Only ids and
+hashes are kept, not the contents.  Pre-JDK 7 `offset`/`count` fields
+are ignored: the whole array is the value.
+ */
+#[derive(Debug)]
+pub struct StringDuplicatesBuilder {
+    size_model: SizeModel,
+    /// Ids of HPROF strings `java/lang/String`, `value` and `coder`.
+    class_name_id: Option<Id>,
+    value_name_id: Option<Id>,
+    coder_name_id: Option<Id>,
+    string_class_id: Option<Id>,
+    /// String id, value array id and coder.
+    strings: Vec<(Id, Id, Option<i8>)>,
+    string_size: u64,
+    arrays: HashMap<Id, ArrayDigest>,
+}
+
+impl StringDuplicatesBuilder {
+    pub fn new(size_model: SizeModel) -> Self {
+        Self {
+            size_model,
+            class_name_id: None,
+            value_name_id: None,
+            coder_name_id: None,
+            string_class_id: None,
+            strings: Vec::new(),
+            string_size: 0,
+            arrays: HashMap::new(),
+        }
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        match record {
+            Record::String(id, data) => match data.as_ref() {
+                b"java/lang/String" => self.class_name_id = Some(*id),
+                b"value" => self.value_name_id = Some(*id),
+                b"coder" => self.coder_name_id = Some(*id),
+                _ => {}
+            },
+            Record::LoadClass(class_record)
+                if Some(class_record.class_name_string_id) == self.class_name_id =>
+            {
+                self.string_class_id = Some(class_record.class_obj_id);
+            }
+            Record::Dump(DumpRecord::InstanceDump(instance))
+                if Some(instance.class_object_id) == self.string_class_id =>
+            {
+                self.add_string(instance);
+            }
+            Record::Dump(DumpRecord::PrimitiveArrayDump(array)) => self.add_array(array),
+            _ => {}
+        }
+    }
+
+    fn add_string(&mut self, instance: &InstanceDump) {
+        let mut value = None;
+        let mut coder = None;
+        let mut payload = 0;
+        let mut references = 0;
+        for (info, field_value) in &instance.values {
+            match field_value {
+                FieldValue::Object(_) => references += 1,
+                _ => payload += info.field_type.byte_size().unwrap_or(0),
+            }
+            match field_value {
+                FieldValue::Object(id) if Some(info.name_id) == self.value_name_id => {
+                    value = Some(*id)
+                }
+                FieldValue::Byte(v) if Some(info.name_id) == self.coder_name_id => coder = Some(*v),
+                _ => {}
+            }
+        }
+        self.string_size = self.size_model.instance_size(payload, references);
+        if let Some(value) = value.filter(|id| u64::from(*id) != 0) {
+            self.strings.push((instance.object_id, value, coder));
+        }
+    }
+
+    fn add_array(&mut self, array: &PrimitiveArrayDump) {
+        let (hash, utf16_hash) = match &array.values {
+            Some(ArrayValue::Char(chars)) => (hash_code_units(chars.iter().copied()), None),
+            Some(ArrayValue::Byte(bytes)) => (
+                hash_code_units(bytes.iter().map(|b| *b as u8 as u16)),
+                Some(hash_code_units(utf16_units(bytes))),
+            ),
+            _ => return,
+        };
+        let size = self
+            .size_model
+            .primitive_array_size(array.elem_type, array.num_elements.into());
+        self.arrays.insert(
+            array.object_id,
+            ArrayDigest {
+                hash,
+                utf16_hash,
+                size,
+            },
+        );
+    }
+
+    pub fn finish(self) -> StringDuplicates {
+        // Value hash, array id, array size, string id.
+        let mut values: Vec<((u64, u32), Id, u64, Id)> = self
+            .strings
+            .iter()
+            .filter_map(|(string_id, array_id, coder)| {
+                let digest = self.arrays.get(array_id)?;
+                let hash = match coder {
+                    Some(CODER_UTF16) => digest.utf16_hash?,
+                    _ => digest.hash,
+                };
+                Some((hash, *array_id, digest.size, *string_id))
+            })
+            .collect();
+        values.sort_unstable_by_key(|(hash, array_id, _, string_id)| {
+            (*hash, u64::from(*array_id), u64::from(*string_id))
+        });
+
+        let mut stats = StringStats {
+            strings: values.len() as u64,
+            ..StringStats::default()
+        };
+        let mut groups = Vec::new();
+        for chunk in values.chunk_by(|a, b| a.0 == b.0) {
+            stats.distinct_values += 1;
+            if chunk.len() < 2 {
+                continue;
+            }
+            let mut arrays = 0;
+            let mut wasted_bytes = 0;
+            for (idx, (_, array_id, size, _)) in chunk.iter().enumerate() {
+                if idx == 0 || chunk[idx - 1].1 != *array_id {
+                    arrays += 1;
+                    if idx > 0 {
+                        wasted_bytes += size;
+                    }
+                }
+                if idx > 0 {
+                    wasted_bytes += self.string_size;
+                }
+            }
+            stats.duplicated_strings += chunk.len() as u64;
+            stats.wasted_bytes += wasted_bytes;
+            groups.push(DuplicateGroup {
+                length: (chunk[0].0).1,
+                string_ids: chunk.iter().map(|value| value.3).collect(),
+                arrays,
+                wasted_bytes,
+            });
+        }
+        groups.sort_by_key(|group| std::cmp::Reverse(group.wasted_bytes));
+        StringDuplicates { stats, groups }
+    }
+}
+
+/// Split `total` into `parts` parts differing by at most one.
+fn split(total: u64, parts: usize) -> impl Iterator<Item = u64> {
+    let parts = parts.max(1) as u64;
+    (0..parts).map(move |idx| total / parts + u64::from(idx < total % parts))
+}
+
+impl StringDuplicates {
+    pub fn from_records<I, S>(records: I, size_model: SizeModel) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = StringDuplicatesBuilder::new(size_model);
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish())
+    }
+
+    /**
+    Classes and fields referencing duplicate strings, most wasted bytes
+    first.  The graph must be built from the same dump.
+     */
+    pub fn top_holders(
+        &self,
+        graph: &HeapGraph,
+        inbound: &InboundIndex,
+        limit: usize,
+    ) -> io::Result<Vec<DuplicateHolder>> {
+        let mut holders: HashMap<(String, String), (u64, u64)> = HashMap::new();
+        for group in &self.groups {
+            let string_shares = split(group.wasted_bytes, group.string_ids.len());
+            for (string_id, share) in group.string_ids.iter().zip(string_shares) {
+                let node = match graph.index_of(*string_id) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                let referrers = inbound.referrers_by_class(graph, node)?;
+                let holder_shares = split(share, referrers.len());
+                for (referrers, share) in referrers.into_iter().zip(holder_shares) {
+                    let holder = holders
+                        .entry((referrers.class_name, referrers.field))
+                        .or_default();
+                    holder.0 += 1;
+                    holder.1 += share;
+                }
+            }
+        }
+        let mut holders: Vec<DuplicateHolder> = holders
+            .into_iter()
+            .map(
+                |((class_name, field), (strings, wasted_bytes))| DuplicateHolder {
+                    class_name,
+                    field,
+                    strings,
+                    wasted_bytes,
+                },
+            )
+            .collect();
+        holders.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        holders.truncate(limit);
+        Ok(holders)
+    }
+
+    /// Write the summary and the `top` groups, with string values if
+    /// the store is given.
+    pub fn write_text<W: Write>(
+        &self,
+        out: &mut W,
+        top: usize,
+        store: Option<&ObjectStore>,
+    ) -> io::Result<()> {
+        let stats = &self.stats;
+        writeln!(
+            out,
+            "{} strings, {} distinct values, {} duplicated strings, {} bytes wasted",
+            stats.strings, stats.distinct_values, stats.duplicated_strings, stats.wasted_bytes
+        )?;
+        for group in self.groups.iter().take(top) {
+            write!(
+                out,
+                "{:>12} bytes  {:>8} copies  length {:>6}  ",
+                group.wasted_bytes,
+                group.string_ids.len(),
+                group.length
+            )?;
+            match store.and_then(|store| group.preview(store)) {
+                Some(preview) => writeln!(out, "{}", preview)?,
+                None => writeln!(out, "{}", object_id_label(group.string_ids[0]))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DuplicateGroup {
+    /// Quoted value, truncated like `quote_java_string` does.
+    pub fn preview(&self, store: &ObjectStore) -> Option<String> {
+        java_string(store, *self.string_ids.first()?).map(|value| quote_java_string(&value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_duplicates() {
+        let mut dump = TestDump::new();
+        let first = dump.java_string("hello");
+        let second = dump.java_string("hello");
+        dump.java_string("world");
+
+        let duplicates =
+            StringDuplicates::from_records(dump.records(), SizeModel::HOTSPOT_64_COMPRESSED)
+                .unwrap();
+        assert_eq!(duplicates.stats.strings, 3);
+        assert_eq!(duplicates.stats.distinct_values, 2);
+        assert_eq!(duplicates.stats.duplicated_strings, 2);
+        assert_eq!(duplicates.groups.len(), 1);
+        let group = &duplicates.groups[0];
+        assert_eq!(group.length, 5);
+        assert_eq!(group.arrays, 2);
+        let mut ids = group.string_ids.clone();
+        ids.sort_by_key(|id| u64::from(*id));
+        assert_eq!(ids, vec![first, second]);
+        // String (24 bytes) and byte[5] (24 bytes).
+        assert_eq!(group.wasted_bytes, 48);
+    }
+
+    #[test]
+    fn test_duplicate_holders() {
+        let mut dump = TestDump::new();
+        let user = dump.class("com/acme/User", None, &[("name", FieldType::Object)]);
+        let names = dump.class("[Ljava/lang/String;", None, &[]);
+        let mut strings = Vec::new();
+        for _ in 0..3 {
+            let name = dump.java_string("admin");
+            dump.instance(user, &[FieldValue::Object(name)]);
+            strings.push(name);
+        }
+        dump.object_array(names, &[strings[0], strings[1]]);
+
+        let duplicates =
+            StringDuplicates::from_records(dump.records(), SizeModel::HOTSPOT_64_COMPRESSED)
+                .unwrap();
+        // Two extra strings and arrays, 48 bytes each.
+        assert_eq!(duplicates.stats.wasted_bytes, 96);
+        assert_eq!(
+            duplicates.groups[0].preview(&dump.store()).as_deref(),
+            Some("\"admin\"")
+        );
+
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let holders = duplicates.top_holders(&graph, &inbound, 10).unwrap();
+        // Two strings are referenced from both the users and the array.
+        assert_eq!(
+            holders.iter().map(|h| h.wasted_bytes).sum::<u64>(),
+            duplicates.stats.wasted_bytes
+        );
+        let rows: Vec<(&str, &str, u64, u64)> = holders
+            .iter()
+            .map(|h| {
+                (
+                    h.class_name.as_str(),
+                    h.field.as_str(),
+                    h.strings,
+                    h.wasted_bytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("com.acme.User", "name", 3, 64),
+                ("java.lang.String[]", "[]", 2, 32),
+            ]
+        );
+    }
+}
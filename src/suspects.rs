@@ -0,0 +1,554 @@
+#![forbid(unsafe_code)]
+
+/*!
+Leak suspects: objects that retain a big part of the heap.
+
+A suspect is either a single object dominated by GC roots only, or a
+group of such objects of the same class, whose retained size exceeds a
+threshold fraction of the heap.  For every suspect the report shows:
+
+* the accumulation point: descending from the suspect through the
+  biggest child in the dominator tree while it keeps most of the
+  retained size, the object where memory actually accumulates;
+* the shortest strong path from GC roots;
+* the biggest collections dominated by the suspect.
+ */
+
+use crate::collections::{Collection, CollectionKind};
+use crate::decl::Id;
+use crate::dominator::DominatorTree;
+use crate::graph::{HeapGraph, NodeIndex, NodeKind};
+use crate::inbound::InboundIndex;
+use crate::objects::ObjectStore;
+use crate::paths::{PathFinder, RootPath};
+use crate::value::{object_id_label, render_object};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Descend to a child while it retains at least this part of its
+/// parent's retained size.
+const ACCUMULATION_RATIO: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SuspectKind {
+    /// A single object.
+    Object,
+    /// Top-level dominated objects of the same class.
+    ClassGroup { objects: u64 },
+}
+
+/// An object on the report.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ObjectInfo {
+    pub node: NodeIndex,
+    pub object_id: Id,
+    pub class_name: String,
+    pub retained_size: u64,
+    /// Value of a string, boxed primitive or other well-known class,
+    /// if an `ObjectStore` was provided; see `value::render_object`.
+    pub value: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CollectionInfo {
+    pub object: ObjectInfo,
+    pub kind: CollectionKind,
+    /// Number of elements, if an `ObjectStore` was provided.
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suspect {
+    pub kind: SuspectKind,
+    /// The object, or the biggest object of the group.
+    pub object: ObjectInfo,
+    /// Retained size of the object or the whole group.
+    pub retained_size: u64,
+    /// Part of the reachable heap.
+    pub heap_fraction: f64,
+    /// `None` if it is the object itself.
+    pub accumulation_point: Option<ObjectInfo>,
+    /// Shortest path from GC roots to the object, ignoring weak
+    /// references.
+    pub path: Option<RootPath>,
+    /// Biggest collections dominated by the accumulation point (or the
+    /// object), biggest first.
+    pub collections: Vec<CollectionInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuspectsReport {
+    /// Size of objects reachable from GC roots.
+    pub heap_size: u64,
+    pub threshold: f64,
+    /// Biggest first.
+    pub suspects: Vec<Suspect>,
+}
+
+/**
+Leak suspects query.
+
+```no_run
+# use hprof_dump_parser::dominator::DominatorTree;
+# use hprof_dump_parser::graph::HeapGraph;
+# use hprof_dump_parser::inbound::InboundIndex;
+# use hprof_dump_parser::suspects::LeakSuspects;
+# fn report(graph: &HeapGraph) -> std::io::Result<()> {
+let inbound = InboundIndex::build(graph);
+let tree = DominatorTree::compute_with_inbound(graph, &inbound)?;
+let report = LeakSuspects::new(graph, &tree, &inbound)
+    .with_threshold(0.2)
+    .find()?;
+report.write_text(&mut std::io::stdout())?;
+# Ok(())
+# }
+```
+ */
+#[derive(Debug)]
+pub struct LeakSuspects<'a> {
+    graph: &'a HeapGraph,
+    tree: &'a DominatorTree,
+    inbound: &'a InboundIndex,
+    store: Option<&'a ObjectStore>,
+    threshold: f64,
+    max_collections: usize,
+}
+
+impl<'a> LeakSuspects<'a> {
+    /// Query with 10% threshold and 5 collections per suspect.
+    pub fn new(graph: &'a HeapGraph, tree: &'a DominatorTree, inbound: &'a InboundIndex) -> Self {
+        Self {
+            graph,
+            tree,
+            inbound,
+            store: None,
+            threshold: 0.1,
+            max_collections: 5,
+        }
+    }
+
+    /// Minimal part of the reachable heap retained by a suspect.
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_max_collections(mut self, max_collections: usize) -> Self {
+        self.max_collections = max_collections;
+        self
+    }
+
+    /// Use the store for collection sizes and object values.  The
+    /// store should be built from the same dump.
+    pub fn with_object_store(mut self, store: &'a ObjectStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn find(&self) -> io::Result<SuspectsReport> {
+        let heap_size = self.tree.total_retained_size();
+        let min_size = (heap_size as f64 * self.threshold).ceil() as u64;
+        let mut suspects = Vec::new();
+
+        let mut groups: HashMap<_, (u64, u64, NodeIndex)> = HashMap::new();
+        for node in self.tree.root_children().iter().copied() {
+            let retained = self.tree.retained_size(node);
+            if retained >= min_size.max(1) {
+                suspects.push(self.suspect(SuspectKind::Object, node, retained, heap_size)?);
+                continue;
+            }
+            // Classes are not grouped: they are all `java.lang.Class`.
+            if self.graph.kind(node) == NodeKind::Class {
+                continue;
+            }
+            let group = groups
+                .entry(self.graph.type_key(node))
+                .or_insert((0, 0, node));
+            group.0 += 1;
+            group.1 += retained;
+            if retained > self.tree.retained_size(group.2) {
+                group.2 = node;
+            }
+        }
+        for (objects, retained, biggest) in groups.into_values() {
+            if objects > 1 && retained >= min_size.max(1) {
+                suspects.push(self.suspect(
+                    SuspectKind::ClassGroup { objects },
+                    biggest,
+                    retained,
+                    heap_size,
+                )?);
+            }
+        }
+        suspects.sort_by(|a, b| {
+            b.retained_size
+                .cmp(&a.retained_size)
+                .then_with(|| u64::from(a.object.object_id).cmp(&u64::from(b.object.object_id)))
+        });
+
+        Ok(SuspectsReport {
+            heap_size,
+            threshold: self.threshold,
+            suspects,
+        })
+    }
+
+    fn info(&self, node: NodeIndex) -> ObjectInfo {
+        ObjectInfo {
+            node,
+            object_id: self.graph.id(node),
+            class_name: self.graph.type_name(node),
+            retained_size: self.tree.retained_size(node),
+            value: self
+                .store
+                .and_then(|store| render_object(store, self.graph.id(node))),
+        }
+    }
+
+    fn suspect(
+        &self,
+        kind: SuspectKind,
+        node: NodeIndex,
+        retained_size: u64,
+        heap_size: u64,
+    ) -> io::Result<Suspect> {
+        let mut point = node;
+        while let Some(child) = self.tree.children(point).first().copied() {
+            let child_size = self.tree.retained_size(child) as f64;
+            if child_size < self.tree.retained_size(point) as f64 * ACCUMULATION_RATIO {
+                break;
+            }
+            point = child;
+        }
+        let path = PathFinder::new(self.graph, self.inbound)
+            .with_exclude_weak_references(true)
+            .find(node)?
+            .into_iter()
+            .next();
+        Ok(Suspect {
+            kind,
+            object: self.info(node),
+            retained_size,
+            heap_fraction: if heap_size == 0 {
+                0.0
+            } else {
+                retained_size as f64 / heap_size as f64
+            },
+            accumulation_point: if point == node {
+                None
+            } else {
+                Some(self.info(point))
+            },
+            path,
+            collections: self.collections(point),
+        })
+    }
+
+    /// Biggest collections dominated by the node, the node included.
+    fn collections(&self, top: NodeIndex) -> Vec<CollectionInfo> {
+        let graph = self.graph;
+        let registry = graph.registry();
+        let mut kinds: HashMap<NodeIndex, Option<CollectionKind>> = HashMap::new();
+        let mut found: Vec<(NodeIndex, CollectionKind)> = Vec::new();
+        let mut stack = vec![top];
+        while let Some(node) = stack.pop() {
+            if graph.kind(node) == NodeKind::Instance {
+                if let Some(class) = graph.class_of(node) {
+                    let kind = *kinds
+                        .entry(class)
+                        .or_insert_with(|| CollectionKind::of_class(registry, graph.id(class)));
+                    if let Some(kind) = kind {
+                        found.push((node, kind));
+                    }
+                }
+            }
+            stack.extend_from_slice(self.tree.children(node));
+        }
+        found.sort_by_key(|(node, _)| std::cmp::Reverse(self.tree.retained_size(*node)));
+        found.truncate(self.max_collections);
+        found
+            .into_iter()
+            .map(|(node, kind)| CollectionInfo {
+                object: self.info(node),
+                kind,
+                size: self
+                    .store
+                    .and_then(|store| Collection::new(store, graph.id(node)))
+                    .map(|collection| collection.size()),
+            })
+            .collect()
+    }
+}
+
+impl SuspectsReport {
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "Reachable heap: {} bytes, threshold {:.0}%",
+            self.heap_size,
+            self.threshold * 100.0
+        )?;
+        if self.suspects.is_empty() {
+            writeln!(out, "No leak suspects.")?;
+        }
+        for (idx, suspect) in self.suspects.iter().enumerate() {
+            writeln!(out, "\nProblem suspect {}", idx + 1)?;
+            writeln!(out, "  {}", suspect.description())?;
+            if let Some(point) = &suspect.accumulation_point {
+                writeln!(
+                    out,
+                    "  Memory is accumulated in {} ({} bytes).",
+                    point.label(),
+                    point.retained_size
+                )?;
+            }
+            if let Some(path) = &suspect.path {
+                writeln!(out, "  Path from GC roots:")?;
+                for (step, line) in path_lines(path).iter().enumerate() {
+                    writeln!(out, "  {:width$}{}", "", line, width = 2 * step + 2)?;
+                }
+            }
+            if !suspect.collections.is_empty() {
+                writeln!(out, "  Biggest collections:")?;
+                for collection in &suspect.collections {
+                    writeln!(out, "    {}", collection_line(collection))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a standalone HTML page.
+    pub fn write_html<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Leak suspects</title></head><body>"
+        )?;
+        writeln!(
+            out,
+            "<h1>Leak suspects</h1>\n<p>Reachable heap: {} bytes, threshold {:.0}%</p>",
+            self.heap_size,
+            self.threshold * 100.0
+        )?;
+        if self.suspects.is_empty() {
+            writeln!(out, "<p>No leak suspects.</p>")?;
+        }
+        for (idx, suspect) in self.suspects.iter().enumerate() {
+            writeln!(out, "<h2>Problem suspect {}</h2>", idx + 1)?;
+            writeln!(out, "<p>{}</p>", html_escape(&suspect.description()))?;
+            if let Some(point) = &suspect.accumulation_point {
+                writeln!(
+                    out,
+                    "<p>Memory is accumulated in <code>{}</code> ({} bytes).</p>",
+                    html_escape(&point.label()),
+                    point.retained_size
+                )?;
+            }
+            if let Some(path) = &suspect.path {
+                writeln!(out, "<h3>Path from GC roots</h3>\n<ol>")?;
+                for line in path_lines(path) {
+                    writeln!(out, "<li><code>{}</code></li>", html_escape(&line))?;
+                }
+                writeln!(out, "</ol>")?;
+            }
+            if !suspect.collections.is_empty() {
+                writeln!(out, "<h3>Biggest collections</h3>\n<ul>")?;
+                for collection in &suspect.collections {
+                    writeln!(
+                        out,
+                        "<li>{}</li>",
+                        html_escape(&collection_line(collection))
+                    )?;
+                }
+                writeln!(out, "</ul>")?;
+            }
+        }
+        writeln!(out, "</body></html>")
+    }
+}
+
+impl ObjectInfo {
+    /// `class@id`, followed by the value if known.
+    fn label(&self) -> String {
+        let label = format!("{}@{}", self.class_name, object_id_label(self.object_id));
+        match &self.value {
+            Some(value) => format!("{} = {}", label, value),
+            None => label,
+        }
+    }
+}
+
+impl Suspect {
+    fn description(&self) -> String {
+        let percent = self.heap_fraction * 100.0;
+        match self.kind {
+            SuspectKind::Object => format!(
+                "One instance of {} retains {} bytes ({:.1}%).",
+                self.object.label(),
+                self.retained_size,
+                percent
+            ),
+            SuspectKind::ClassGroup { objects } => format!(
+                "{} instances of {} retain {} bytes ({:.1}%); the biggest one is {}.",
+                objects,
+                self.object.class_name,
+                self.retained_size,
+                percent,
+                object_id_label(self.object.object_id)
+            ),
+        }
+    }
+}
+
+fn path_lines(path: &RootPath) -> Vec<String> {
+    let roots: Vec<&str> = path.roots.iter().map(|root| root.name()).collect();
+    path.steps
+        .iter()
+        .enumerate()
+        .map(|(idx, step)| {
+            let mut line = format!("{}@{}", step.class_name, object_id_label(step.object_id));
+            if idx == 0 {
+                line.push_str(&format!(" [{}]", roots.join(", ")));
+            }
+            if let Some(via) = &step.via {
+                line.push_str(&format!(" .{}", via));
+            }
+            line
+        })
+        .collect()
+}
+
+fn collection_line(collection: &CollectionInfo) -> String {
+    let size = match collection.size {
+        Some(size) => format!(", {} elements", size),
+        None => String::new(),
+    };
+    format!(
+        "{}: {} bytes retained{}",
+        collection.object.label(),
+        collection.object.retained_size,
+        size
+    )
+}
+
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decl::*;
+    use crate::graph::tests::sample_dump;
+    use crate::graph::GcRootKind;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_leak_suspects() {
+        let (mut dump, ids) = sample_dump();
+        let list = dump.class(
+            "java/util/ArrayList",
+            None,
+            &[("elementData", FieldType::Object), ("size", FieldType::Int)],
+        );
+        let objects = dump.class("[Ljava/lang/Object;", None, &[]);
+        let session = dump.class("com/acme/Session", None, &[("cache", FieldType::Object)]);
+        for _ in 0..3 {
+            let data = dump.object_array(objects, &[id(0), id(0)]);
+            let cache = dump.instance(list, &[FieldValue::Object(data), FieldValue::Int(0)]);
+            let session = dump.instance(session, &[FieldValue::Object(cache)]);
+            dump.dump(DumpRecord::RootJniGlobal {
+                obj_id: session,
+                jni_global_ref: id(1),
+            });
+        }
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let tree = DominatorTree::compute(&graph);
+        let report = LeakSuspects::new(&graph, &tree, &inbound)
+            .with_threshold(0.3)
+            .find()
+            .unwrap();
+        assert_eq!(report.heap_size, 376);
+        assert_eq!(report.suspects.len(), 2);
+
+        let group = &report.suspects[0];
+        assert_eq!(group.kind, SuspectKind::ClassGroup { objects: 3 });
+        assert_eq!(group.object.class_name, "com.acme.Session");
+        assert_eq!(group.retained_size, 192);
+        assert_eq!(group.accumulation_point, None);
+        assert_eq!(group.collections.len(), 1);
+        assert_eq!(group.collections[0].kind, CollectionKind::ArrayList);
+        assert_eq!(group.collections[0].object.retained_size, 48);
+
+        let holder = &report.suspects[1];
+        assert_eq!(holder.kind, SuspectKind::Object);
+        assert_eq!(holder.object.object_id, ids[0]);
+        assert_eq!(
+            holder
+                .accumulation_point
+                .as_ref()
+                .map(|point| point.object_id),
+            Some(ids[1])
+        );
+        assert_eq!(
+            holder.path.as_ref().unwrap().roots,
+            vec![GcRootKind::JniGlobal]
+        );
+
+        let mut text = Vec::new();
+        report.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("3 instances of com.acme.Session retain 192 bytes (51.1%)"));
+        assert!(text.contains("Memory is accumulated in com.acme.Item[]@0x1090 (128 bytes)."));
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<code>com.acme.Item[]@0x1090</code>"));
+    }
+
+    #[test]
+    fn test_string_suspect_value() {
+        let mut dump = TestDump::new();
+        let object = dump.class("java/lang/Object", None, &[]);
+        let text = dump.java_string(&"x".repeat(300));
+        let small = dump.instance(object, &[]);
+        for obj_id in [text, small].iter().copied() {
+            dump.dump(DumpRecord::RootJniGlobal {
+                obj_id,
+                jni_global_ref: id(1),
+            });
+        }
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        let inbound = InboundIndex::build(&graph);
+        let tree = DominatorTree::compute(&graph);
+        let store = dump.store();
+        let report = LeakSuspects::new(&graph, &tree, &inbound)
+            .with_threshold(0.5)
+            .with_object_store(&store)
+            .find()
+            .unwrap();
+        assert_eq!(report.suspects.len(), 1);
+        let value = report.suspects[0].object.value.as_deref().unwrap();
+        assert!(value.starts_with("\"xxx"));
+
+        let mut text = Vec::new();
+        report.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains(&format!(
+            "One instance of java.lang.String@{} = \"xxx",
+            object_id_label(report.suspects[0].object.object_id)
+        )));
+    }
+}
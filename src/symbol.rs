@@ -0,0 +1,263 @@
+#![forbid(unsafe_code)]
+
+/*!
+Symbolization of `StackFrame` and `StackTrace` records.
+
+A frame record references the method name, its JVM descriptor and the
+source file by string ids, and the declaring class by its LOAD_CLASS
+serial.  The symbolizer collects these records and renders frames the
+way Java prints exceptions:
+
+```text
+at com.acme.Foo.bar(Foo.java:42)
+at java.lang.Object.wait(Native Method)
+```
+ */
+
+use crate::decl::*;
+use crate::descriptor::MethodDescriptor;
+use crate::registry::{java_name, ClassRegistry};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// `StackFrameRecord::line_number` value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineNumber {
+    Line(u32),
+    /// The method has no line number table (0).
+    NoInfo,
+    /// Unknown location (-1).
+    Unknown,
+    /// Compiled method (-2).
+    Compiled,
+    /// Native method (-3).
+    Native,
+}
+
+impl LineNumber {
+    pub fn from_raw(line_number: i32) -> Self {
+        match line_number {
+            line if line > 0 => LineNumber::Line(line as u32),
+            0 => LineNumber::NoInfo,
+            -2 => LineNumber::Compiled,
+            -3 => LineNumber::Native,
+            _ => LineNumber::Unknown,
+        }
+    }
+
+    pub fn to_raw(self) -> i32 {
+        match self {
+            LineNumber::Line(line) => line as i32,
+            LineNumber::NoInfo => 0,
+            LineNumber::Unknown => -1,
+            LineNumber::Compiled => -2,
+            LineNumber::Native => -3,
+        }
+    }
+}
+
+/// A resolved stack frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StackFrame {
+    /// Declaring class in the Java form, if its LOAD_CLASS record is
+    /// found.
+    pub class_name: Option<String>,
+    pub method_name: String,
+    /// JVM method descriptor, e.g. `(I)V`.
+    pub signature: String,
+    /// Parsed signature; `None` if it is malformed.
+    pub descriptor: Option<MethodDescriptor>,
+    pub source_file: Option<String>,
+    pub line: LineNumber,
+}
+
+impl StackFrame {
+    /// Class and method name: `com.acme.Foo.bar`.
+    pub fn qualified_name(&self) -> String {
+        format!(
+            "{}.{}",
+            self.class_name.as_deref().unwrap_or("<unknown class>"),
+            self.method_name
+        )
+    }
+
+    /// Source location in parentheses of a Java stack trace line:
+    /// `Foo.java:42`, `Native Method`, `Unknown Source`.
+    pub fn source_location(&self) -> String {
+        match (self.line, &self.source_file) {
+            (LineNumber::Native, _) => "Native Method".to_string(),
+            (LineNumber::Compiled, _) => "Compiled Code".to_string(),
+            (LineNumber::Line(line), Some(file)) => format!("{}:{}", file, line),
+            (_, Some(file)) => file.clone(),
+            (_, None) => "Unknown Source".to_string(),
+        }
+    }
+
+    /// Method with parameter and return types, e.g.
+    /// `void bar(int, String)`; the raw signature is appended to the
+    /// name if it is malformed.
+    pub fn method_declaration(&self) -> String {
+        match &self.descriptor {
+            Some(descriptor) => descriptor.java_declaration(&self.method_name),
+            None => format!("{}{}", self.method_name, self.signature),
+        }
+    }
+}
+
+/// `com.acme.Foo.bar(Foo.java:42)`.
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.qualified_name(), self.source_location())
+    }
+}
+
+/**
+Symbolizer of stack frames and traces: feed it the records with
+`add_record`.  Only strings, LOAD_CLASS, frame and trace records are
+used.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Symbolizer {
+    registry: ClassRegistry,
+    frames: HashMap<Id, StackFrameRecord>,
+    traces: HashMap<SerialNumber, Vec<Id>>,
+}
+
+impl Symbolizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_records<I, S>(records: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut symbolizer = Self::new();
+        for rec in records {
+            let (_, record) = rec?;
+            symbolizer.add_record(&record);
+        }
+        Ok(symbolizer)
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        match record {
+            Record::StackFrame(frame) => {
+                self.frames.insert(frame.stack_frame_id, frame.clone());
+            }
+            Record::StackTrace(trace) => {
+                self.traces
+                    .insert(trace.stack_trace_serial, trace.stack_frame_ids.clone());
+            }
+            Record::Dump(_) => {}
+            record => self.registry.add_record(record),
+        }
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &ClassRegistry {
+        &self.registry
+    }
+
+    /// Frame ids of a stack trace, top frame first.
+    pub fn frame_ids(&self, stack_trace_serial: SerialNumber) -> Option<&[Id]> {
+        self.traces.get(&stack_trace_serial).map(Vec::as_slice)
+    }
+
+    /// Resolve a frame; `None` if its record is missing.
+    pub fn frame(&self, stack_frame_id: Id) -> Option<StackFrame> {
+        let frame = self.frames.get(&stack_frame_id)?;
+        let registry = &self.registry;
+        let signature = registry
+            .string(frame.method_signature_id)
+            .unwrap_or_default()
+            .to_string();
+        Some(StackFrame {
+            class_name: registry
+                .class_id_by_serial(frame.class_serial)
+                .and_then(|class_id| registry.class_name(class_id))
+                .map(java_name),
+            method_name: registry
+                .string(frame.method_name_id)
+                .unwrap_or("<unknown method>")
+                .to_string(),
+            descriptor: MethodDescriptor::parse(&signature).ok(),
+            signature,
+            source_file: registry
+                .string(frame.source_file_name_id)
+                .filter(|file| !file.is_empty())
+                .map(str::to_string),
+            line: LineNumber::from_raw(frame.line_number),
+        })
+    }
+
+    /// Resolved frames of a stack trace, top frame first; `None` for
+    /// missing frame records.
+    pub fn stack_trace(&self, stack_trace_serial: SerialNumber) -> Option<Vec<Option<StackFrame>>> {
+        self.frame_ids(stack_trace_serial)
+            .map(|ids| ids.iter().map(|id| self.frame(*id)).collect())
+    }
+
+    /// Write `\tat ...` lines of a stack trace.  Returns `false` if the
+    /// trace is not found.
+    pub fn write_stack_trace<W: Write>(
+        &self,
+        out: &mut W,
+        stack_trace_serial: SerialNumber,
+    ) -> io::Result<bool> {
+        let frames = match self.stack_trace(stack_trace_serial) {
+            Some(frames) => frames,
+            None => return Ok(false),
+        };
+        for frame in frames {
+            match frame {
+                Some(frame) => writeln!(out, "\tat {}", frame)?,
+                None => writeln!(out, "\tat <unknown frame>")?,
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_symbolize_stack_trace() {
+        let mut dump = TestDump::new();
+        let foo = dump.class("com/acme/Foo", None, &[]);
+        let bar = dump.stack_frame(foo, "bar", "(ILjava/lang/String;[[J)V", 42);
+        let wait = dump.stack_frame(foo, "wait", "()Ljava/lang/Object;", -3);
+        let run = dump.stack_frame(foo, "run", "(broken", 0);
+        // The last frame record is missing.
+        dump.stack_trace(3, &[bar, wait, run, id(0x200)]);
+
+        let symbolizer = Symbolizer::from_records(dump.records()).unwrap();
+        let bar = symbolizer.frame(bar).unwrap();
+        assert_eq!(bar.line, LineNumber::Line(42));
+        assert_eq!(bar.method_declaration(), "void bar(int, String, long[][])");
+        assert_eq!(
+            symbolizer.frame(wait).unwrap().method_declaration(),
+            "Object wait()"
+        );
+        assert_eq!(
+            symbolizer.frame(run).unwrap().method_declaration(),
+            "run(broken"
+        );
+
+        let mut out = Vec::new();
+        assert!(symbolizer.write_stack_trace(&mut out, 3).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\tat com.acme.Foo.bar(Foo.java:42)\n\
+             \tat com.acme.Foo.wait(Native Method)\n\
+             \tat com.acme.Foo.run(Foo.java)\n\
+             \tat <unknown frame>\n"
+        );
+        assert!(!symbolizer.write_stack_trace(&mut Vec::new(), 4).unwrap());
+    }
+}
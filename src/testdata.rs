@@ -0,0 +1,330 @@
+#![forbid(unsafe_code)]
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+//! Synthetic dumps for unit tests.
+
+use crate::decl::*;
+use crate::objects::ObjectStore;
+use std::collections::HashMap;
+
+pub(crate) type TestRecords = Vec<Result<(Ts, Record<Vec<u8>>), Error>>;
+
+/// Id size used for synthetic dumps.
+pub(crate) const ID_SIZE: u32 = 8;
+
+pub(crate) fn id(n: u64) -> Id {
+    Id::from(n)
+}
+
+fn field_size(field_type: FieldType) -> u32 {
+    match field_type {
+        FieldType::Object => ID_SIZE,
+        other => other.byte_size().unwrap() as u32,
+    }
+}
+
+/// Builder of a record sequence similar to one produced by HotSpot.
+pub(crate) struct TestDump {
+    pub(crate) records: Vec<Record<Vec<u8>>>,
+    next_id: u64,
+    next_serial: SerialNumber,
+    strings: HashMap<String, Id>,
+    class_fields: HashMap<Id, Vec<FieldInfo>>,
+    string_class: Option<Id>,
+}
+
+impl TestDump {
+    pub(crate) fn new() -> Self {
+        Self {
+            records: vec![],
+            next_id: 0x1000,
+            next_serial: 1,
+            strings: HashMap::new(),
+            class_fields: HashMap::new(),
+            string_class: None,
+        }
+    }
+
+    /// Ids are 8-byte aligned addresses, like with HotSpot defaults.
+    fn alloc(&mut self) -> Id {
+        self.next_id += 8;
+        id(self.next_id)
+    }
+
+    pub(crate) fn string(&mut self, s: &str) -> Id {
+        if let Some(id) = self.strings.get(s) {
+            return *id;
+        }
+        let id = self.alloc();
+        self.strings.insert(s.to_string(), id);
+        self.records.push(Record::String(id, s.as_bytes().to_vec()));
+        id
+    }
+
+    /// Define a class; `name` is in internal form (`java/lang/String`).
+    pub(crate) fn class(
+        &mut self,
+        name: &str,
+        super_class: Option<Id>,
+        fields: &[(&str, FieldType)],
+    ) -> Id {
+        self.class_with_statics(name, super_class, fields, &[])
+    }
+
+    pub(crate) fn class_with_statics(
+        &mut self,
+        name: &str,
+        super_class: Option<Id>,
+        fields: &[(&str, FieldType)],
+        statics: &[(&str, FieldValue)],
+    ) -> Id {
+        let name_id = self.string(name);
+        let class_id = self.alloc();
+        let serial = self.next_serial;
+        self.next_serial += 1;
+
+        let instance_fields: Vec<FieldInfo> = fields
+            .iter()
+            .map(|(name, field_type)| FieldInfo {
+                name_id: self.string(name),
+                field_type: *field_type,
+            })
+            .collect();
+        let static_fields = statics
+            .iter()
+            .map(|(name, value)| {
+                let field_type = match value {
+                    FieldValue::Bool(_) => FieldType::Bool,
+                    FieldValue::Byte(_) => FieldType::Byte,
+                    FieldValue::Char(_) => FieldType::Char,
+                    FieldValue::Short(_) => FieldType::Short,
+                    FieldValue::Int(_) => FieldType::Int,
+                    FieldValue::Long(_) => FieldType::Long,
+                    FieldValue::Float(_) => FieldType::Float,
+                    FieldValue::Double(_) => FieldType::Double,
+                    FieldValue::Object(_) => FieldType::Object,
+                };
+                (
+                    FieldInfo {
+                        name_id: self.string(name),
+                        field_type,
+                    },
+                    *value,
+                )
+            })
+            .collect();
+
+        self.records.push(Record::LoadClass(ClassRecord {
+            serial,
+            class_obj_id: class_id,
+            stack_trace_serial: 0,
+            class_name_string_id: name_id,
+        }));
+        self.records
+            .push(Record::Dump(DumpRecord::ClassDump(ClassDescription {
+                class_id,
+                stack_trace_serial: 0,
+                super_class_object_id: super_class.unwrap_or_else(|| id(0)),
+                class_loader_object_id: id(0),
+                signers_object_id: id(0),
+                protection_domain_object_id: id(0),
+                reserved1: id(0),
+                reserved2: id(0),
+                instance_size: instance_fields
+                    .iter()
+                    .map(|f| field_size(f.field_type))
+                    .sum(),
+                const_fields: vec![],
+                static_fields,
+                instance_fields: instance_fields.clone(),
+            })));
+
+        let mut all_fields = instance_fields;
+        if let Some(super_class) = super_class {
+            all_fields.extend(self.class_fields[&super_class].iter().copied());
+        }
+        self.class_fields.insert(class_id, all_fields);
+        class_id
+    }
+
+    /// Add an instance; `values` go in the parser's order: fields of
+    /// the class itself first, then superclass fields.
+    pub(crate) fn instance(&mut self, class: Id, values: &[FieldValue]) -> Id {
+        let object_id = self.alloc();
+        self.instance_with_id(object_id, class, values);
+        object_id
+    }
+
+    pub(crate) fn instance_with_id(&mut self, object_id: Id, class: Id, values: &[FieldValue]) {
+        let fields = &self.class_fields[&class];
+        assert_eq!(fields.len(), values.len(), "wrong number of field values");
+        let data_size = fields.iter().map(|f| field_size(f.field_type)).sum();
+        let values = fields.iter().copied().zip(values.iter().copied()).collect();
+        self.records
+            .push(Record::Dump(DumpRecord::InstanceDump(InstanceDump {
+                object_id,
+                stack_trace_serial: 0,
+                class_object_id: class,
+                data_size,
+                values,
+            })));
+    }
+
+    pub(crate) fn object_array(&mut self, element_class: Id, elements: &[Id]) -> Id {
+        let object_id = self.alloc();
+        self.records
+            .push(Record::Dump(DumpRecord::ObjectArrayDump(ObjectArrayDump {
+                object_id,
+                stack_trace_serial: 0,
+                num_elements: elements.len() as u32,
+                element_class_id: element_class,
+                values: Some(elements.to_vec()),
+            })));
+        object_id
+    }
+
+    pub(crate) fn primitive_array(&mut self, values: ArrayValue) -> Id {
+        let object_id = self.alloc();
+        let (elem_type, num_elements) = match &values {
+            ArrayValue::Bool(v) => (FieldType::Bool, v.len()),
+            ArrayValue::Byte(v) => (FieldType::Byte, v.len()),
+            ArrayValue::Char(v) => (FieldType::Char, v.len()),
+            ArrayValue::Short(v) => (FieldType::Short, v.len()),
+            ArrayValue::Int(v) => (FieldType::Int, v.len()),
+            ArrayValue::Long(v) => (FieldType::Long, v.len()),
+            ArrayValue::Float(v) => (FieldType::Float, v.len()),
+            ArrayValue::Double(v) => (FieldType::Double, v.len()),
+            ArrayValue::Object(_) => panic!("object array is not primitive"),
+        };
+        self.records
+            .push(Record::Dump(DumpRecord::PrimitiveArrayDump(
+                PrimitiveArrayDump {
+                    object_id,
+                    stack_trace_serial: 0,
+                    num_elements: num_elements as u32,
+                    elem_type,
+                    values: Some(values),
+                },
+            )));
+        object_id
+    }
+
+    /// Add a compact (JDK 9+) `java.lang.String` with Latin-1 contents.
+    pub(crate) fn java_string(&mut self, s: &str) -> Id {
+        let string_class = match self.string_class {
+            Some(class) => class,
+            None => {
+                let class = self.class(
+                    "java/lang/String",
+                    None,
+                    &[
+                        ("value", FieldType::Object),
+                        ("hash", FieldType::Int),
+                        ("coder", FieldType::Byte),
+                    ],
+                );
+                self.string_class = Some(class);
+                class
+            }
+        };
+        let value = self.primitive_array(ArrayValue::Byte(s.bytes().map(|b| b as i8).collect()));
+        self.instance(
+            string_class,
+            &[
+                FieldValue::Object(value),
+                FieldValue::Int(0),
+                FieldValue::Byte(0),
+            ],
+        )
+    }
+
+    /// Serial number of the LOAD_CLASS record of a class.
+    pub(crate) fn class_serial(&self, class: Id) -> SerialNumber {
+        self.records
+            .iter()
+            .find_map(|record| match record {
+                Record::LoadClass(load) if load.class_obj_id == class => Some(load.serial),
+                _ => None,
+            })
+            .expect("class is not loaded")
+    }
+
+    /// Add a frame of a method of the class; the source file is named
+    /// after the top-level class, like `Foo.java` for `com/acme/Foo$1`.
+    pub(crate) fn stack_frame(
+        &mut self,
+        class: Id,
+        method: &str,
+        signature: &str,
+        line_number: i32,
+    ) -> Id {
+        let class_name_id = self
+            .records
+            .iter()
+            .find_map(|record| match record {
+                Record::LoadClass(load) if load.class_obj_id == class => {
+                    Some(load.class_name_string_id)
+                }
+                _ => None,
+            })
+            .expect("class is not loaded");
+        let class_name = self
+            .strings
+            .iter()
+            .find_map(|(name, id)| {
+                if *id == class_name_id {
+                    Some(name)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        let simple_name = class_name.rsplit('/').next().unwrap();
+        let source_file = format!("{}.java", simple_name.split('$').next().unwrap());
+
+        let stack_frame_id = self.alloc();
+        let record = StackFrameRecord {
+            stack_frame_id,
+            method_name_id: self.string(method),
+            method_signature_id: self.string(signature),
+            source_file_name_id: self.string(&source_file),
+            class_serial: self.class_serial(class),
+            line_number,
+        };
+        self.records.push(Record::StackFrame(record));
+        stack_frame_id
+    }
+
+    /// Add a stack trace of thread 1; `frames` are top first.
+    pub(crate) fn stack_trace(&mut self, stack_trace_serial: SerialNumber, frames: &[Id]) {
+        self.records.push(Record::StackTrace(StackTraceRecord {
+            stack_trace_serial,
+            thread_serial: 1,
+            stack_frame_ids: frames.to_vec(),
+        }));
+    }
+
+    pub(crate) fn dump(&mut self, record: DumpRecord) {
+        self.records.push(Record::Dump(record));
+    }
+
+    pub(crate) fn record(&mut self, record: Record<Vec<u8>>) {
+        self.records.push(record);
+    }
+
+    /// Records in HotSpot order: strings and LOAD_CLASS records first,
+    /// then the heap dump.
+    pub(crate) fn records(&self) -> TestRecords {
+        let (dump, other): (Vec<_>, Vec<_>) = self
+            .records
+            .iter()
+            .cloned()
+            .partition(|r| matches!(r, Record::Dump(_)));
+        other.into_iter().chain(dump).map(|r| Ok((0, r))).collect()
+    }
+
+    pub(crate) fn store(&self) -> ObjectStore {
+        ObjectStore::from_records(self.records()).unwrap()
+    }
+}
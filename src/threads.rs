@@ -0,0 +1,421 @@
+#![forbid(unsafe_code)]
+
+/*!
+Thread stacks reconstructed from a heap dump, like `jstack` output.
+
+HotSpot writes a `RootThreadObject` for every thread with the serial
+of its stack trace, `StackTrace` and `StackFrame` records for the
+stacks, and a `RootJavaFrame` (or `RootJniLocal`) for every object
+referenced by a local variable of a frame.  Other tools may write
+`StartThread` records with thread names instead.
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::threads::Threads;
+# let data = vec![];
+let hprof = StreamHprofReader::new().with_load_primitive_arrays(false);
+let records = hprof.read_hprof_from_memory(&data).unwrap();
+let threads = Threads::from_records(records).unwrap();
+threads.write_jstack(&mut std::io::stdout(), None, None).unwrap();
+```
+ */
+
+use crate::decl::*;
+use crate::dominator::DominatorTree;
+use crate::graph::HeapGraph;
+use crate::objects::ObjectStore;
+use crate::strings::java_string;
+use crate::symbol::{StackFrame, Symbolizer};
+use crate::value::{escape_java, object_id_label, render_object};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+
+/// A frame with objects referenced by its local variables.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ThreadFrame {
+    /// `None` if the frame record is missing.
+    pub frame: Option<StackFrame>,
+    pub locals: Vec<Id>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ThreadInfo {
+    pub thread_serial: SerialNumber,
+    /// `java.lang.Thread` instance.
+    pub object_id: Option<Id>,
+    pub name: Option<String>,
+    pub group_name: Option<String>,
+    /// Top frame first.
+    pub frames: Vec<ThreadFrame>,
+    /// Objects pinned by the thread but not by a known frame: native
+    /// stack and thread block roots, JNI locals of unknown frames.
+    pub pinned: Vec<Id>,
+    /// See `Threads::compute_retained_sizes`.
+    pub retained_size: Option<u64>,
+}
+
+impl ThreadInfo {
+    /// Objects referenced by all frames and pinned by the thread.
+    pub fn locals(&self) -> impl Iterator<Item = Id> + '_ {
+        self.frames
+            .iter()
+            .flat_map(|frame| frame.locals.iter().copied())
+            .chain(self.pinned.iter().copied())
+    }
+}
+
+/// Threads ordered by serial number.
+#[derive(Clone, Debug, Default)]
+pub struct Threads {
+    pub threads: Vec<ThreadInfo>,
+}
+
+#[derive(Debug, Default)]
+struct ThreadState {
+    object_id: Option<Id>,
+    stack_trace_serial: Option<SerialNumber>,
+    name_id: Option<Id>,
+    group_name_id: Option<Id>,
+    pinned: Vec<Id>,
+}
+
+/// Streaming builder, see `Threads::from_records`.
+#[derive(Debug, Default)]
+pub struct ThreadsBuilder {
+    symbolizer: Symbolizer,
+    threads: BTreeMap<SerialNumber, ThreadState>,
+    /// Objects by thread serial and frame number.
+    locals: HashMap<(SerialNumber, u32), Vec<Id>>,
+}
+
+impl ThreadsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_record<S: AsRef<[u8]>>(&mut self, record: &Record<S>) {
+        self.symbolizer.add_record(record);
+        match record {
+            Record::StartThread(start) => {
+                let thread = self.threads.entry(start.thread_serial).or_default();
+                thread.object_id = Some(start.thead_object_id);
+                thread.stack_trace_serial = Some(start.stack_trace_serial);
+                thread.name_id = Some(start.thread_name_id);
+                thread.group_name_id = Some(start.thread_group_name_id);
+            }
+            Record::Dump(dump) => self.add_dump_record(dump),
+            _ => {}
+        }
+    }
+
+    fn add_dump_record(&mut self, dump: &DumpRecord) {
+        match *dump {
+            DumpRecord::RootThreadObject {
+                obj_id,
+                thread_serial,
+                stack_trace_serial,
+            } => {
+                let thread = self.threads.entry(thread_serial).or_default();
+                thread.object_id = Some(obj_id);
+                thread.stack_trace_serial = Some(stack_trace_serial);
+            }
+            DumpRecord::RootJavaFrame {
+                obj_id,
+                thread_serial,
+                frame_number,
+            }
+            | DumpRecord::RootJniLocal {
+                obj_id,
+                thread_serial,
+                frame_number,
+            } => {
+                self.threads.entry(thread_serial).or_default();
+                self.locals
+                    .entry((thread_serial, frame_number))
+                    .or_default()
+                    .push(obj_id);
+            }
+            DumpRecord::RootNativeStack {
+                obj_id,
+                thread_serial,
+            }
+            | DumpRecord::RootThreadBlock {
+                obj_id,
+                thread_serial,
+            } => {
+                self.threads
+                    .entry(thread_serial)
+                    .or_default()
+                    .pinned
+                    .push(obj_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> Threads {
+        let symbolizer = self.symbolizer;
+        let mut locals = self.locals;
+        let mut threads = Vec::with_capacity(self.threads.len());
+        for (thread_serial, state) in self.threads {
+            let frame_ids = state
+                .stack_trace_serial
+                .and_then(|serial| symbolizer.frame_ids(serial))
+                .unwrap_or_default();
+            let mut frames: Vec<ThreadFrame> = frame_ids
+                .iter()
+                .enumerate()
+                .map(|(frame_number, frame_id)| ThreadFrame {
+                    frame: symbolizer.frame(*frame_id),
+                    locals: locals
+                        .remove(&(thread_serial, frame_number as u32))
+                        .unwrap_or_default(),
+                })
+                .collect();
+            frames.shrink_to_fit();
+            let string = |id: Option<Id>| {
+                id.and_then(|id| symbolizer.registry().string(id))
+                    .map(str::to_string)
+            };
+            threads.push(ThreadInfo {
+                thread_serial,
+                object_id: state.object_id,
+                name: string(state.name_id),
+                group_name: string(state.group_name_id),
+                frames,
+                pinned: state.pinned,
+                retained_size: None,
+            });
+        }
+        // Locals of frames beyond the stack trace, or of no frame.
+        let mut rest: Vec<_> = locals.into_iter().collect();
+        rest.sort_by_key(|((_, frame_number), _)| *frame_number);
+        for ((thread_serial, _), objects) in rest {
+            if let Ok(idx) = threads.binary_search_by_key(&thread_serial, |t| t.thread_serial) {
+                threads[idx].pinned.extend(objects);
+            }
+        }
+        Threads { threads }
+    }
+}
+
+impl Threads {
+    pub fn from_records<I, S>(records: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = Result<(Ts, Record<S>), Error>>,
+        S: AsRef<[u8]>,
+    {
+        let mut builder = ThreadsBuilder::new();
+        for rec in records {
+            let (_, record) = rec?;
+            builder.add_record(&record);
+        }
+        Ok(builder.finish())
+    }
+
+    pub fn get(&self, thread_serial: SerialNumber) -> Option<&ThreadInfo> {
+        self.threads
+            .binary_search_by_key(&thread_serial, |thread| thread.thread_serial)
+            .ok()
+            .map(|idx| &self.threads[idx])
+    }
+
+    /// Take missing thread names from `java.lang.Thread.name` fields.
+    pub fn resolve_names(&mut self, store: &ObjectStore) {
+        for thread in &mut self.threads {
+            if thread.name.is_some() {
+                continue;
+            }
+            thread.name = thread
+                .object_id
+                .and_then(|id| store.instance(id))
+                .and_then(|instance| store.object_field(instance, "name"))
+                .and_then(|name| java_string(store, name));
+        }
+    }
+
+    /**
+    Set retained sizes: the thread object and the local objects that
+    are not dominated by anything but GC roots.  Objects referenced
+    from several threads are counted for each of them.
+     */
+    pub fn compute_retained_sizes(&mut self, graph: &HeapGraph, tree: &DominatorTree) {
+        for thread in &mut self.threads {
+            let mut seen = HashSet::new();
+            let mut size = 0;
+            for (idx, id) in thread
+                .object_id
+                .iter()
+                .copied()
+                .chain(thread.locals())
+                .enumerate()
+            {
+                let node = match graph.index_of(id) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                // The thread object is counted anyway.
+                if ((idx == 0 && thread.object_id.is_some())
+                    || tree.immediate_dominator(node).is_none())
+                    && seen.insert(node)
+                {
+                    size += tree.retained_size(node);
+                }
+            }
+            thread.retained_size = Some(size);
+        }
+    }
+
+    /// Write `jstack`-like stacks with the local objects of frames.
+    /// Class names of the objects are taken from the graph, if given;
+    /// values of strings, boxed primitives and other well-known classes
+    /// from the store, see `value::render_object`.
+    pub fn write_jstack<W: Write>(
+        &self,
+        out: &mut W,
+        graph: Option<&HeapGraph>,
+        store: Option<&ObjectStore>,
+    ) -> io::Result<()> {
+        let label = |id: Id| {
+            let mut label = match graph {
+                Some(graph) => match graph.index_of(id) {
+                    Some(node) => format!("{}@{}", graph.type_name(node), object_id_label(id)),
+                    None => object_id_label(id),
+                },
+                None => object_id_label(id),
+            };
+            if let Some(value) = store.and_then(|store| render_object(store, id)) {
+                label.push_str(" = ");
+                label.push_str(&value);
+            }
+            label
+        };
+        for thread in &self.threads {
+            write!(
+                out,
+                "\"{}\" #{}",
+                escape_java(thread.name.as_deref().unwrap_or("<unknown>")),
+                thread.thread_serial
+            )?;
+            if let Some(object_id) = thread.object_id {
+                write!(out, " {}", object_id_label(object_id))?;
+            }
+            if let Some(group_name) = &thread.group_name {
+                write!(out, " group=\"{}\"", escape_java(group_name))?;
+            }
+            if let Some(retained_size) = thread.retained_size {
+                write!(out, " retained={}", retained_size)?;
+            }
+            writeln!(out)?;
+            for frame in &thread.frames {
+                match &frame.frame {
+                    Some(frame) => writeln!(out, "\tat {}", frame)?,
+                    None => writeln!(out, "\tat <unknown frame>")?,
+                }
+                for local in &frame.locals {
+                    writeln!(out, "\t- local {}", label(*local))?;
+                }
+            }
+            for object in &thread.pinned {
+                writeln!(out, "\t- pinned {}", label(*object))?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_thread_stacks() {
+        let mut dump = TestDump::new();
+        let thread_class = dump.class("java/lang/Thread", None, &[("name", FieldType::Object)]);
+        let worker = dump.class("com/acme/Worker", None, &[]);
+        let name = dump.java_string("main");
+        let thread = dump.instance(thread_class, &[FieldValue::Object(name)]);
+        let local = dump.instance(worker, &[]);
+
+        let run = dump.stack_frame(worker, "run", "()V", 42);
+        let park = dump.stack_frame(worker, "park", "()V", -3);
+        dump.stack_trace(7, &[park, run]);
+        dump.dump(DumpRecord::RootThreadObject {
+            obj_id: thread,
+            thread_serial: 1,
+            stack_trace_serial: 7,
+        });
+        dump.dump(DumpRecord::RootJavaFrame {
+            obj_id: local,
+            thread_serial: 1,
+            frame_number: 1,
+        });
+        let job = dump.java_string("job-1");
+        dump.dump(DumpRecord::RootJavaFrame {
+            obj_id: job,
+            thread_serial: 1,
+            frame_number: 0,
+        });
+
+        let store = dump.store();
+        let mut threads = Threads::from_records(dump.records()).unwrap();
+        threads.resolve_names(&store);
+        let graph = HeapGraph::from_records(dump.records(), ID_SIZE).unwrap();
+        threads.compute_retained_sizes(&graph, &DominatorTree::compute(&graph));
+
+        let main = threads.get(1).unwrap();
+        assert_eq!(main.name.as_deref(), Some("main"));
+        assert_eq!(main.frames.len(), 2);
+        assert_eq!(main.frames[1].locals, vec![local]);
+        // Thread (16), its name (24 + byte[4] 24) and the locals (16
+        // and 24 + byte[5] 24).
+        assert_eq!(main.retained_size, Some(128));
+
+        let mut out = Vec::new();
+        threads
+            .write_jstack(&mut out, Some(&graph), Some(&store))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "\"main\" #1 {} retained=128\n\
+                 \tat com.acme.Worker.park(Native Method)\n\
+                 \t- local java.lang.String@{} = \"job-1\"\n\
+                 \tat com.acme.Worker.run(Worker.java:42)\n\
+                 \t- local com.acme.Worker@{}\n\n",
+                object_id_label(thread),
+                object_id_label(job),
+                object_id_label(local)
+            )
+        );
+    }
+
+    #[test]
+    fn test_jstack_escapes_names() {
+        let mut dump = TestDump::new();
+        let name = dump.string("say \"hi\"");
+        let group = dump.string("main\tgroup");
+        dump.stack_trace(7, &[]);
+        dump.record(Record::StartThread(StartThreadRecord {
+            thread_serial: 1,
+            thead_object_id: id(100),
+            stack_trace_serial: 7,
+            thread_name_id: name,
+            thread_group_name_id: group,
+            thread_group_parent_name_id: id(0),
+        }));
+
+        let threads = Threads::from_records(dump.records()).unwrap();
+        let mut out = Vec::new();
+        threads.write_jstack(&mut out, None, None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "\"say \\\"hi\\\"\" #1 {} group=\"main\\tgroup\"\n\n",
+                object_id_label(id(100))
+            )
+        );
+    }
+}
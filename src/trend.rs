@@ -0,0 +1,453 @@
+#![forbid(unsafe_code)]
+
+/*!
+Leak trend analysis over a series of dumps.
+
+Every dump is summarized with `DumpSummary`; the series is ordered by
+dump timestamps (`ReadHprofIterator::timestamp`, the HPROF header
+timestamp in milliseconds).  For every class and collection kind a
+least-squares line is fitted to its size and count, and classes that
+grow in every dump are flagged as suspects.
+
+```no_run
+# use hprof_dump_parser::StreamHprofReader;
+# use hprof_dump_parser::diff::DumpSummary;
+# use hprof_dump_parser::size::SizeModel;
+# use hprof_dump_parser::trend::TrendAnalysis;
+# let dumps: Vec<Vec<u8>> = vec![];
+let hprof = StreamHprofReader::new();
+let mut analysis = TrendAnalysis::new();
+for data in &dumps {
+    let records = hprof.read_hprof_from_memory(data).unwrap();
+    let (timestamp, id_size) = (records.timestamp, records.id_size);
+    let summary =
+        DumpSummary::from_records(records, id_size, SizeModel::HOTSPOT_64_COMPRESSED, true)
+            .unwrap();
+    analysis.add_dump(timestamp, &summary);
+}
+analysis.finish().write_text(&mut std::io::stdout(), 20).unwrap();
+```
+ */
+
+use crate::decl::Ts;
+use crate::diff::DumpSummary;
+use crate::json;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const MS_PER_HOUR: f64 = 3_600_000.0;
+
+/// Values of a class or collection kind, one per dump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    pub values: Vec<u64>,
+    /// Slope of the fitted line, per hour.
+    pub slope: f64,
+    /// The values never decrease and the last one is bigger than the
+    /// first one.
+    pub monotonic: bool,
+}
+
+impl Series {
+    fn new(timestamps: &[Ts], values: Vec<u64>) -> Self {
+        let monotonic =
+            values.windows(2).all(|pair| pair[0] <= pair[1]) && values.first() < values.last();
+        Self {
+            slope: slope(timestamps, &values),
+            monotonic,
+            values,
+        }
+    }
+
+    /// Growth from the first dump to the last one.
+    pub fn growth(&self) -> i64 {
+        match (self.values.first(), self.values.last()) {
+            (Some(first), Some(last)) => *last as i64 - *first as i64,
+            _ => 0,
+        }
+    }
+}
+
+/// Least-squares slope per hour.
+fn slope(timestamps: &[Ts], values: &[u64]) -> f64 {
+    let count = values.len() as f64;
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let xs: Vec<f64> = timestamps
+        .iter()
+        .map(|ts| (ts - timestamps[0]) as f64 / MS_PER_HOUR)
+        .collect();
+    let mean_x = xs.iter().sum::<f64>() / count;
+    let mean_y = values.iter().map(|v| *v as f64).sum::<f64>() / count;
+    let mut cov = 0.0;
+    let mut var = 0.0;
+    for (x, y) in xs.iter().zip(values.iter()) {
+        cov += (x - mean_x) * (*y as f64 - mean_y);
+        var += (x - mean_x) * (x - mean_x);
+    }
+    if var == 0.0 {
+        0.0
+    } else {
+        cov / var
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassTrend {
+    pub class_name: String,
+    pub instances: Series,
+    /// Retained sizes if all summaries have them, shallow sizes
+    /// otherwise.
+    pub size: Series,
+}
+
+impl ClassTrend {
+    /// Both instance count and size grow in every dump.
+    pub fn is_suspect(&self) -> bool {
+        self.instances.monotonic && self.size.monotonic
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollectionTrend {
+    /// Collection class name.
+    pub kind: String,
+    /// Total elements of all collections of the kind.
+    pub elements: Series,
+    /// Size of the biggest collection.
+    pub max_size: Series,
+}
+
+/// Result of `TrendAnalysis`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrendReport {
+    /// Dump timestamps in milliseconds, ascending.
+    pub timestamps: Vec<Ts>,
+    /// Whether sizes are retained sizes.
+    pub retained_sizes: bool,
+    /// Fastest size growth first.
+    pub classes: Vec<ClassTrend>,
+    /// Fastest growth of total elements first.
+    pub collections: Vec<CollectionTrend>,
+}
+
+/**
+Collects dump summaries for a `TrendReport`.  Only per-class numbers
+are kept, not whole summaries.
+ */
+#[derive(Debug, Default)]
+pub struct TrendAnalysis {
+    dumps: Vec<(Ts, DumpNumbers)>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ClassNumbers {
+    instances: u64,
+    shallow_size: u64,
+    retained_size: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct DumpNumbers {
+    classes: BTreeMap<String, ClassNumbers>,
+    /// Elements and max size.
+    collections: BTreeMap<String, (u64, u64)>,
+}
+
+impl DumpNumbers {
+    fn has_retained_sizes(&self) -> bool {
+        self.classes
+            .values()
+            .all(|class| class.retained_size.is_some())
+    }
+}
+
+impl TrendAnalysis {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dump; dumps may be added in any order.
+    pub fn add_dump(&mut self, timestamp: Ts, summary: &DumpSummary) {
+        let classes = summary
+            .classes
+            .iter()
+            .map(|class| {
+                (
+                    class.class_name.clone(),
+                    ClassNumbers {
+                        instances: class.instances,
+                        shallow_size: class.shallow_size,
+                        retained_size: class.retained_size,
+                    },
+                )
+            })
+            .collect();
+        let collections = summary
+            .collections
+            .iter()
+            .map(|c| (c.kind.class_name().to_string(), (c.elements, c.max_size)))
+            .collect();
+        self.dumps.push((
+            timestamp,
+            DumpNumbers {
+                classes,
+                collections,
+            },
+        ));
+    }
+
+    pub fn finish(mut self) -> TrendReport {
+        self.dumps.sort_by_key(|(timestamp, _)| *timestamp);
+        let timestamps: Vec<Ts> = self.dumps.iter().map(|(timestamp, _)| *timestamp).collect();
+        // The same kind of size for all dumps, so the series is
+        // comparable.
+        let retained_sizes =
+            !self.dumps.is_empty() && self.dumps.iter().all(|(_, dump)| dump.has_retained_sizes());
+
+        let mut class_names: Vec<&String> = self
+            .dumps
+            .iter()
+            .flat_map(|(_, dump)| dump.classes.keys())
+            .collect();
+        class_names.sort();
+        class_names.dedup();
+        let mut classes: Vec<ClassTrend> = class_names
+            .into_iter()
+            .map(|class_name| {
+                let (instances, size) = self.series(&timestamps, |dump| {
+                    let class = dump.classes.get(class_name).copied().unwrap_or_default();
+                    let size = match class.retained_size {
+                        Some(retained) if retained_sizes => retained,
+                        _ => class.shallow_size,
+                    };
+                    (class.instances, size)
+                });
+                ClassTrend {
+                    class_name: class_name.clone(),
+                    instances,
+                    size,
+                }
+            })
+            .collect();
+        classes.sort_by(|a, b| b.size.slope.total_cmp(&a.size.slope));
+
+        let mut kinds: Vec<&String> = self
+            .dumps
+            .iter()
+            .flat_map(|(_, dump)| dump.collections.keys())
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        let mut collections: Vec<CollectionTrend> = kinds
+            .into_iter()
+            .map(|kind| {
+                let (elements, max_size) = self.series(&timestamps, |dump| {
+                    dump.collections.get(kind).copied().unwrap_or_default()
+                });
+                CollectionTrend {
+                    kind: kind.clone(),
+                    elements,
+                    max_size,
+                }
+            })
+            .collect();
+        collections.sort_by(|a, b| b.elements.slope.total_cmp(&a.elements.slope));
+
+        TrendReport {
+            timestamps,
+            retained_sizes,
+            classes,
+            collections,
+        }
+    }
+
+    fn series<F>(&self, timestamps: &[Ts], value: F) -> (Series, Series)
+    where
+        F: Fn(&DumpNumbers) -> (u64, u64),
+    {
+        let (first, second) = self.dumps.iter().map(|(_, dump)| value(dump)).unzip();
+        (
+            Series::new(timestamps, first),
+            Series::new(timestamps, second),
+        )
+    }
+}
+
+impl TrendReport {
+    /// Classes growing in every dump, fastest first.
+    pub fn suspects(&self) -> impl Iterator<Item = &ClassTrend> {
+        self.classes.iter().filter(|class| class.is_suspect())
+    }
+
+    /// Collection kinds whose element count grows in every dump.
+    pub fn growing_collections(&self) -> impl Iterator<Item = &CollectionTrend> {
+        self.collections
+            .iter()
+            .filter(|collection| collection.elements.monotonic)
+    }
+
+    /// Write a plain text report with up to `top` suspects.
+    pub fn write_text<W: Write>(&self, out: &mut W, top: usize) -> io::Result<()> {
+        let hours = match (self.timestamps.first(), self.timestamps.last()) {
+            (Some(first), Some(last)) => (last - first) as f64 / MS_PER_HOUR,
+            _ => 0.0,
+        };
+        writeln!(
+            out,
+            "{} dumps over {:.1} hours, sizes are {}",
+            self.timestamps.len(),
+            hours,
+            if self.retained_sizes {
+                "retained"
+            } else {
+                "shallow"
+            }
+        )?;
+        writeln!(out, "\nClasses growing in every dump:")?;
+        for class in self.suspects().take(top) {
+            writeln!(
+                out,
+                "{:>14.0} bytes/h {:>12.1} objects/h {:>+14} bytes  {}",
+                class.size.slope,
+                class.instances.slope,
+                class.size.growth(),
+                class.class_name
+            )?;
+        }
+        writeln!(out, "\nCollections growing in every dump:")?;
+        for collection in self.growing_collections() {
+            writeln!(
+                out,
+                "{:>12.1} elements/h {:>+12} elements, biggest {}  {}",
+                collection.elements.slope,
+                collection.elements.growth(),
+                collection.max_size.values.last().copied().unwrap_or(0),
+                collection.kind
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the suspects and growing collections as a JSON object.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"timestamps\": [")?;
+        for (idx, timestamp) in self.timestamps.iter().enumerate() {
+            if idx > 0 {
+                out.write_all(b", ")?;
+            }
+            write!(out, "{}", timestamp)?;
+        }
+        writeln!(out, "], \"retained_sizes\": {},", self.retained_sizes)?;
+        out.write_all(b"\"suspects\": [")?;
+        for (idx, class) in self.suspects().enumerate() {
+            out.write_all(if idx > 0 { b",\n  " } else { b"\n  " })?;
+            out.write_all(b"{\"class_name\": ")?;
+            json::write_string(out, &class.class_name)?;
+            write!(out, ", \"instances\": ")?;
+            write_series(out, &class.instances)?;
+            write!(out, ", \"size\": ")?;
+            write_series(out, &class.size)?;
+            out.write_all(b"}")?;
+        }
+        out.write_all(b"],\n\"collections\": [")?;
+        for (idx, collection) in self.growing_collections().enumerate() {
+            out.write_all(if idx > 0 { b",\n  " } else { b"\n  " })?;
+            out.write_all(b"{\"kind\": ")?;
+            json::write_string(out, &collection.kind)?;
+            write!(out, ", \"elements\": ")?;
+            write_series(out, &collection.elements)?;
+            write!(out, ", \"max_size\": ")?;
+            write_series(out, &collection.max_size)?;
+            out.write_all(b"}")?;
+        }
+        out.write_all(b"]}\n")
+    }
+}
+
+fn write_series<W: Write>(out: &mut W, series: &Series) -> io::Result<()> {
+    out.write_all(b"{\"values\": [")?;
+    for (idx, value) in series.values.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b", ")?;
+        }
+        write!(out, "{}", value)?;
+    }
+    // JSON has no NaN or infinities; slopes of finite values are
+    // finite.
+    write!(out, "], \"slope_per_hour\": {}}}", series.slope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::ClassSummary;
+
+    fn summary(classes: &[(&str, u64, u64)]) -> DumpSummary {
+        DumpSummary {
+            classes: classes
+                .iter()
+                .map(|(class_name, instances, size)| ClassSummary {
+                    class_name: class_name.to_string(),
+                    instances: *instances,
+                    shallow_size: *size,
+                    retained_size: None,
+                })
+                .collect(),
+            ..DumpSummary::default()
+        }
+    }
+
+    #[test]
+    fn test_trend() {
+        let hour = 3_600_000;
+        let mut analysis = TrendAnalysis::new();
+        analysis.add_dump(2 * hour, &summary(&[("Leak", 30, 300), ("Stable", 5, 50)]));
+        analysis.add_dump(0, &summary(&[("Leak", 10, 100), ("Stable", 5, 50)]));
+        analysis.add_dump(hour, &summary(&[("Leak", 20, 200), ("Stable", 6, 40)]));
+        let report = analysis.finish();
+
+        assert_eq!(report.timestamps, vec![0, hour, 2 * hour]);
+        assert!(!report.retained_sizes);
+        let leak = &report.classes[0];
+        assert_eq!(leak.class_name, "Leak");
+        assert_eq!(leak.size.values, vec![100, 200, 300]);
+        assert!((leak.size.slope - 100.0).abs() < 1e-9);
+        assert_eq!(
+            report
+                .suspects()
+                .map(|c| c.class_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Leak"]
+        );
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(
+            "{\"class_name\": \"Leak\", \"instances\": {\"values\": [10, 20, 30], \"slope_per_hour\": 10}"
+        ));
+    }
+
+    #[test]
+    fn test_mixed_retained_sizes() {
+        let mut retained = summary(&[("Leak", 10, 100)]);
+        retained.classes[0].retained_size = Some(1000);
+        let mut analysis = TrendAnalysis::new();
+        analysis.add_dump(0, &retained);
+        analysis.add_dump(3_600_000, &summary(&[("Leak", 20, 200)]));
+        let report = analysis.finish();
+
+        assert!(!report.retained_sizes);
+        assert_eq!(report.classes[0].size.values, vec![100, 200]);
+        assert!(report.classes[0].size.monotonic);
+
+        let mut analysis = TrendAnalysis::new();
+        analysis.add_dump(0, &retained);
+        let report = analysis.finish();
+        assert!(report.retained_sizes);
+        assert_eq!(report.classes[0].size.values, vec![1000]);
+    }
+}
@@ -0,0 +1,379 @@
+#![forbid(unsafe_code)]
+
+/*!
+Rendering of values for text and JSON output.
+
+Well-known JDK value classes are rendered as their values: strings,
+boxed primitives, atomics, `BigInteger`, `BigDecimal`, `UUID` and enum
+constants.  Other objects are rendered as `class@id`.
+
+The reports that print objects use it when they are given an
+`ObjectStore`: thread stacks (`threads`), leak suspects (`suspects`),
+duplicate strings (`strings`) and subgraph exports (`graph_export`).
+Record-level exports (`jsonl`) write raw field values instead.
+ */
+
+use crate::decl::*;
+use crate::objects::{ObjectRef, ObjectStore};
+use crate::strings::java_string;
+use std::fmt::{Display, Write};
+
+/// Strings longer than this are truncated by `render_object`.
+pub const MAX_STRING_PREVIEW: usize = 256;
+
+/// Render a field value: primitives in Java syntax, `null`, values of
+/// well-known classes, or `class@id` for other objects.
+pub fn render_value(store: &ObjectStore, value: FieldValue) -> String {
+    match value {
+        FieldValue::Object(id) if u64::from(id) == 0 => "null".to_string(),
+        FieldValue::Object(id) => {
+            render_object(store, id).unwrap_or_else(|| object_label(store, id))
+        }
+        primitive => render_primitive(primitive),
+    }
+}
+
+/// Render a primitive value in Java syntax.
+pub fn render_primitive(value: FieldValue) -> String {
+    match value {
+        FieldValue::Bool(v) => v.to_string(),
+        FieldValue::Byte(v) => v.to_string(),
+        FieldValue::Char(v) => {
+            let mut out = String::from("'");
+            escape_java_into(&String::from_utf16_lossy(&[v]), &mut out);
+            out.push('\'');
+            out
+        }
+        FieldValue::Short(v) => v.to_string(),
+        FieldValue::Int(v) => v.to_string(),
+        FieldValue::Long(v) => v.to_string(),
+        FieldValue::Float(v) => java_float(v.into(), &v),
+        FieldValue::Double(v) => java_float(v, &v),
+        FieldValue::Object(id) => object_id_label(id),
+    }
+}
+
+/// `display` formats the value in its own type, so `f32` values get
+/// their shortest representation instead of the widened `f64` one.
+fn java_float(v: f64, display: &dyn Display) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if v.fract() == 0.0 {
+        format!("{}.0", display)
+    } else {
+        display.to_string()
+    }
+}
+
+/// `0x...` label of an object id.
+pub fn object_id_label(id: Id) -> String {
+    format!("{:#x}", u64::from(id))
+}
+
+/// `class@0x...` label of an object.
+pub fn object_label(store: &ObjectStore, id: Id) -> String {
+    let class_name = match store.get(id) {
+        Some(ObjectRef::Instance(instance)) => {
+            store.registry().java_class_name(instance.class_object_id)
+        }
+        Some(ObjectRef::Class(_)) => Some("java.lang.Class".to_string()),
+        Some(ObjectRef::ObjectArray(array)) => {
+            store.registry().java_class_name(array.element_class_id)
+        }
+        Some(ObjectRef::PrimitiveArray(array)) => {
+            Some(format!("{}[]", array.elem_type.java_name()))
+        }
+        None => None,
+    };
+    format!(
+        "{}@{}",
+        class_name.as_deref().unwrap_or("unknown"),
+        object_id_label(id)
+    )
+}
+
+/**
+Render an object of a well-known class as its value; `None` for other
+objects.
+
+Strings are quoted and escaped, enum constants are rendered by their
+`name` field, class objects as `class name`.
+ */
+pub fn render_object(store: &ObjectStore, id: Id) -> Option<String> {
+    let instance = match store.get(id)? {
+        ObjectRef::Instance(instance) => instance,
+        ObjectRef::Class(class_desc) => {
+            return store
+                .registry()
+                .java_class_name(class_desc.class_id)
+                .map(|name| format!("class {}", name))
+        }
+        _ => return None,
+    };
+    let registry = store.registry();
+    let class_name = registry.java_class_name(instance.class_object_id)?;
+
+    match class_name.as_str() {
+        "java.lang.String" => java_string(store, id).map(|s| quote_java_string(&s)),
+        "java.lang.Integer"
+        | "java.lang.Long"
+        | "java.lang.Short"
+        | "java.lang.Byte"
+        | "java.lang.Character"
+        | "java.lang.Boolean"
+        | "java.lang.Float"
+        | "java.lang.Double" => store.field(instance, "value").map(render_primitive),
+        "java.util.concurrent.atomic.AtomicInteger" | "java.util.concurrent.atomic.AtomicLong" => {
+            store.field(instance, "value").map(render_primitive)
+        }
+        "java.util.concurrent.atomic.AtomicBoolean" => match store.field(instance, "value")? {
+            FieldValue::Int(v) => Some((v != 0).to_string()),
+            _ => None,
+        },
+        "java.util.UUID" => {
+            match (
+                store.field(instance, "mostSigBits")?,
+                store.field(instance, "leastSigBits")?,
+            ) {
+                (FieldValue::Long(msb), FieldValue::Long(lsb)) => Some(format_uuid(msb, lsb)),
+                _ => None,
+            }
+        }
+        "java.math.BigInteger" => big_integer(store, instance),
+        "java.math.BigDecimal" => big_decimal(store, instance),
+        _ if registry.is_subclass_of(instance.class_object_id, "java.lang.Enum") => store
+            .object_field(instance, "name")
+            .and_then(|name| java_string(store, name)),
+        _ => None,
+    }
+}
+
+/// Quote and escape a string with Java escapes, truncating it to
+/// `MAX_STRING_PREVIEW` characters.
+pub fn quote_java_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len().min(MAX_STRING_PREVIEW) + 2);
+    out.push('"');
+    let mut chars = s.chars();
+    let preview: String = chars.by_ref().take(MAX_STRING_PREVIEW).collect();
+    escape_java_into(&preview, &mut out);
+    out.push('"');
+    if chars.next().is_some() {
+        out.push_str("...");
+    }
+    out
+}
+
+/// Escape a string with Java escapes, without quoting or truncating.
+pub(crate) fn escape_java(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    escape_java_into(s, &mut out);
+    out
+}
+
+fn escape_java_into(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+fn format_uuid(msb: i64, lsb: i64) -> String {
+    let (msb, lsb) = (msb as u64, lsb as u64);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        msb >> 32,
+        (msb >> 16) & 0xffff,
+        msb & 0xffff,
+        lsb >> 48,
+        lsb & 0xffff_ffff_ffff
+    )
+}
+
+fn big_integer(store: &ObjectStore, instance: &InstanceDump) -> Option<String> {
+    let signum = match store.field(instance, "signum")? {
+        FieldValue::Int(signum) => signum,
+        _ => return None,
+    };
+    let mag = match store
+        .primitive_array(store.object_field(instance, "mag")?)?
+        .values
+        .as_ref()?
+    {
+        ArrayValue::Int(mag) => mag,
+        _ => return None,
+    };
+    let digits = magnitude_to_decimal(mag.iter().map(|v| *v as u32).collect());
+    Some(if signum < 0 {
+        format!("-{}", digits)
+    } else {
+        digits
+    })
+}
+
+fn big_decimal(store: &ObjectStore, instance: &InstanceDump) -> Option<String> {
+    let scale = match store.field(instance, "scale")? {
+        FieldValue::Int(scale) => scale,
+        _ => return None,
+    };
+    let unscaled = match store.field(instance, "intCompact") {
+        // Long.MIN_VALUE means that the value is kept in intVal.
+        Some(FieldValue::Long(compact)) if compact != i64::MIN => compact.to_string(),
+        _ => big_integer(
+            store,
+            store.instance(store.object_field(instance, "intVal")?)?,
+        )?,
+    };
+    let (negative, digits) = match unscaled.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, unscaled.as_str()),
+    };
+    let plain = if scale <= 0 {
+        let mut plain = digits.to_string();
+        if digits != "0" {
+            plain.push_str(&"0".repeat(scale.unsigned_abs() as usize));
+        }
+        plain
+    } else {
+        let scale = scale as usize;
+        let padded = if digits.len() <= scale {
+            format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+        } else {
+            digits.to_string()
+        };
+        let (int_part, frac_part) = padded.split_at(padded.len() - scale);
+        format!("{}.{}", int_part, frac_part)
+    };
+    Some(if negative {
+        format!("-{}", plain)
+    } else {
+        plain
+    })
+}
+
+/// Convert big-endian magnitude of 32-bit words to a decimal string.
+fn magnitude_to_decimal(mut mag: Vec<u32>) -> String {
+    const CHUNK: u64 = 1_000_000_000;
+    let mut chunks = Vec::new();
+    while mag.iter().any(|w| *w != 0) {
+        let mut rem: u64 = 0;
+        for word in mag.iter_mut() {
+            let cur = (rem << 32) | u64::from(*word);
+            *word = (cur / CHUNK) as u32;
+            rem = cur % CHUNK;
+        }
+        chunks.push(rem);
+    }
+    match chunks.split_last() {
+        None => "0".to_string(),
+        Some((last, rest)) => {
+            let mut out = last.to_string();
+            for chunk in rest.iter().rev() {
+                let _ = write!(out, "{:09}", chunk);
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata::*;
+
+    #[test]
+    fn test_render_primitives() {
+        assert_eq!(render_primitive(FieldValue::Double(1.0)), "1.0");
+        assert_eq!(render_primitive(FieldValue::Float(0.5)), "0.5");
+        assert_eq!(render_primitive(FieldValue::Float(0.1)), "0.1");
+        assert_eq!(render_primitive(FieldValue::Float(-2.0)), "-2.0");
+        assert_eq!(
+            render_primitive(FieldValue::Double(f64::NEG_INFINITY)),
+            "-Infinity"
+        );
+        assert_eq!(render_primitive(FieldValue::Char(b'\n' as u16)), "'\\n'");
+    }
+
+    #[test]
+    fn test_magnitude_to_decimal() {
+        assert_eq!(magnitude_to_decimal(vec![]), "0");
+        assert_eq!(magnitude_to_decimal(vec![1, 0]), "4294967296");
+        assert_eq!(
+            magnitude_to_decimal(vec![0x8ac7_2304, 0x89e8_0000]),
+            "10000000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_render_well_known() {
+        let mut dump = TestDump::new();
+        let integer = dump.class("java/lang/Integer", None, &[("value", FieldType::Int)]);
+        let uuid = dump.class(
+            "java/util/UUID",
+            None,
+            &[
+                ("mostSigBits", FieldType::Long),
+                ("leastSigBits", FieldType::Long),
+            ],
+        );
+        let big_decimal = dump.class(
+            "java/math/BigDecimal",
+            None,
+            &[
+                ("intVal", FieldType::Object),
+                ("scale", FieldType::Int),
+                ("intCompact", FieldType::Long),
+            ],
+        );
+        let enum_class = dump.class(
+            "java/lang/Enum",
+            None,
+            &[("name", FieldType::Object), ("ordinal", FieldType::Int)],
+        );
+        let color = dump.class("com/acme/Color", Some(enum_class), &[]);
+
+        let int_id = dump.instance(integer, &[FieldValue::Int(-5)]);
+        let uuid_id = dump.instance(
+            uuid,
+            &[
+                FieldValue::Long(0x123e_4567_e89b_12d3),
+                FieldValue::Long(0xa456_4266_1417_4000u64 as i64),
+            ],
+        );
+        let decimal_id = dump.instance(
+            big_decimal,
+            &[
+                FieldValue::Object(id(0)),
+                FieldValue::Int(3),
+                FieldValue::Long(-1234),
+            ],
+        );
+        let name = dump.java_string("RED");
+        let red = dump.instance(color, &[FieldValue::Object(name), FieldValue::Int(0)]);
+        let store = dump.store();
+
+        assert_eq!(render_object(&store, int_id).unwrap(), "-5");
+        assert_eq!(
+            render_object(&store, uuid_id).unwrap(),
+            "123e4567-e89b-12d3-a456-426614174000"
+        );
+        assert_eq!(render_object(&store, decimal_id).unwrap(), "-1.234");
+        assert_eq!(render_object(&store, red).unwrap(), "RED");
+        assert_eq!(
+            render_value(&store, FieldValue::Object(name)),
+            "\"RED\"".to_string()
+        );
+        assert_eq!(render_value(&store, FieldValue::Object(id(0))), "null");
+    }
+}